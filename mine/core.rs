@@ -1,35 +1,72 @@
 use std::fs::File;
 use std::io::{Read, BufReader};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use crate::types::MalType;
+use rustyline::{Editor, error::ReadlineError};
+
+use crate::types::{MalType, MalError, MalHashable, MalResult};
 use crate::printer::pr_str;
-use crate::reader::read_str;
+use crate::reader::{read_str, read_str_from};
 
 pub(crate) fn ns() -> HashMap<String, MalType> {
     let mut symbols = HashMap::new();
 
-    symbols.insert("+".to_string(), binary_operator!(Number + Number -> Number));
-    symbols.insert("-".to_string(), binary_operator!(Number - Number -> Number));
-    symbols.insert("*".to_string(), binary_operator!(Number * Number -> Number));
-    symbols.insert("/".to_string(), binary_operator!(Number / Number -> Number));
+    symbols.insert("+".to_string(), arithmetic_operator!(+));
+    symbols.insert("-".to_string(), arithmetic_operator!(-));
+    symbols.insert("*".to_string(), arithmetic_operator!(*));
+    symbols.insert("/".to_string(), arithmetic_operator!(/));
 
     symbols.insert("<".to_string(), binary_operator!(Number < Number -> Bool));
     symbols.insert(">".to_string(), binary_operator!(Number > Number -> Bool));
-    symbols.insert("<".to_string(), binary_operator!(Number <= Number -> Bool));
-    symbols.insert(">".to_string(), binary_operator!(Number >= Number -> Bool));
+    symbols.insert("<=".to_string(), binary_operator!(Number <= Number -> Bool));
+    symbols.insert(">=".to_string(), binary_operator!(Number >= Number -> Bool));
+
+    symbols.insert("inc".to_string(), function!(x: Number -> Number { *x + Int(1) }));
+    symbols.insert("dec".to_string(), function!(x: Number -> Number { *x - Int(1) }));
+
+    symbols.insert("numerator".to_string(), function!(x: Number -> Number {
+        match x {
+            Int(_)   => Ok(*x),
+            Ratio(r) => Ok(Int(*r.numer())),
+            Float(_) => Err(TypeCheckFailed{}),
+        }
+    }));
 
-    symbols.insert("inc".to_string(), function!(x: Number -> Number { Ok(*x + Int(1)) }));
-    symbols.insert("dec".to_string(), function!(x: Number -> Number { Ok(*x - Int(1)) }));
+    symbols.insert("denominator".to_string(), function!(x: Number -> Number {
+        match x {
+            Int(_)   => Ok(Int(1)),
+            Ratio(r) => Ok(Int(*r.denom())),
+            Float(_) => Err(TypeCheckFailed{}),
+        }
+    }));
+
+    symbols.insert("double".to_string(), function!(x: Number -> Number { Ok(Float(x.to_f64())) }));
+
+    symbols.insert("int".to_string(), function!(x: Number -> Number {
+        match x {
+            Int(_)   => Ok(*x),
+            Ratio(r) => Ok(Int(r.to_integer())),
+            Float(f) => {
+                // `i64::MAX as f64` rounds up to 2^63, which is already out of range
+                if f.is_finite() && *f >= i64::MIN as f64 && *f < i64::MAX as f64 {
+                    Ok(Int(*f as i64))
+                } else {
+                    Err(NotRepresentableAsInt(*f))
+                }
+            }
+        }
+    }));
 
     symbols.insert("not".to_string(), function!(x -> Bool {
         Ok({
             match x {
-                Bool(p)               => !p,
-                Number(n)             => !(*n == Int(0)),
-                List(ls) | Vector(ls) => !ls.is_empty(),
-                Nil                   => true,
-                _                     => false,
+                Bool(p)                     => !p,
+                Number(n)                   => !(*n == Int(0)),
+                List(ls, _) | Vector(ls, _) => !ls.is_empty(),
+                Nil                         => true,
+                _                           => false,
             }
         })
     }));
@@ -37,34 +74,302 @@ pub(crate) fn ns() -> HashMap<String, MalType> {
     symbols.insert("=".to_string(), function!(a, b -> Bool { Ok(a == b) }));
     symbols.insert("!=".to_string(), function!(a, b -> Bool { Ok(a != b) }));
 
-    symbols.insert("nil".to_string(), function!(x -> Bool {
+    symbols.insert("nil?".to_string(), function!(x -> Bool {
         Ok(if let Nil = x { true } else { false })
     }));
 
+    symbols.insert("true?".to_string(), function!(x -> Bool {
+        Ok(if let Bool(true) = x { true } else { false })
+    }));
+
+    symbols.insert("false?".to_string(), function!(x -> Bool {
+        Ok(if let Bool(false) = x { true } else { false })
+    }));
+
+    symbols.insert("symbol".to_string(), function!(s: Str -> Symbol {
+        Ok(s.clone())
+    }));
+
+    symbols.insert("symbol?".to_string(), function!(x -> Bool {
+        Ok(if let Symbol(_) = x { true } else { false })
+    }));
+
+    symbols.insert("keyword".to_string(), function!(x {
+        match x {
+            Str(s) | Keyword(s) => Ok(Keyword(s.clone())),
+            _                   => Err(TypeCheckFailed{}),
+        }
+    }));
+
+    symbols.insert("keyword?".to_string(), function!(x -> Bool {
+        Ok(if let Keyword(_) = x { true } else { false })
+    }));
+
+    symbols.insert("string?".to_string(), function!(x -> Bool {
+        Ok(if let Str(_) = x { true } else { false })
+    }));
+
+    symbols.insert("number?".to_string(), function!(x -> Bool {
+        Ok(if let Number(_) = x { true } else { false })
+    }));
+
+    symbols.insert("fn?".to_string(), function!(x -> Bool {
+        Ok(if let Function(..) = x { true } else { false })
+    }));
+
+    symbols.insert("macro?".to_string(), function!(x -> Bool {
+        Ok(if let Macro(_) = x { true } else { false })
+    }));
+
     symbols.insert("list?".to_string(), function!(ls -> Bool {
-        Ok(if let List(_) = ls { true } else { false })
+        Ok(if let List(_, _) = ls { true } else { false })
+    }));
+
+    symbols.insert("vector?".to_string(), function!(ls -> Bool {
+        Ok(if let Vector(_, _) = ls { true } else { false })
+    }));
+
+    symbols.insert("sequential?".to_string(), function!(ls -> Bool {
+        Ok(if let List(_, _) | Vector(_, _) = ls { true } else { false })
+    }));
+
+    symbols.insert("map?".to_string(), function!(m -> Bool {
+        Ok(if let Dict(_, _) = m { true } else { false })
     }));
 
     symbols.insert("empty?".to_string(), function!(ls -> Bool {
         match ls {
-            Nil                   => Ok(true),
-            List(ls) | Vector(ls) => Ok(ls.is_empty()),
-            _                     => Err(TypeCheckFailed{}),
+            Nil                         => Ok(true),
+            List(ls, _) | Vector(ls, _) => Ok(ls.is_empty()),
+            Set(set)                    => Ok(set.is_empty()),
+            _                           => Err(TypeCheckFailed{}),
         }
     }));
 
     symbols.insert("list".to_string(), variadic_function!(args {
-        Ok(List(args.to_owned()))
+        Ok(MalType::list(args.to_owned()))
+    }));
+
+    symbols.insert("vector".to_string(), variadic_function!(args {
+        Ok(MalType::vector(args.to_owned()))
+    }));
+
+    symbols.insert("hash-map".to_string(), variadic_function!(args {
+        assoc(HashMap::new(), args).map(MalType::dict)
+    }));
+
+    symbols.insert("assoc".to_string(), variadic_function!(args {
+        match args.split_first() {
+            Some((Dict(map, _), kvs)) => assoc(map.clone(), kvs).map(MalType::dict),
+            _                         => Err(TypeCheckFailed{}),
+        }
+    }));
+
+    symbols.insert("dissoc".to_string(), variadic_function!(args {
+        match args.split_first() {
+            Some((Dict(map, _), keys)) => {
+                let mut map = map.clone();
+                for key in keys {
+                    map.remove(&hashable(key)?);
+                }
+                Ok(MalType::dict(map))
+            }
+            _                          => Err(TypeCheckFailed{}),
+        }
+    }));
+
+    symbols.insert("get".to_string(), function!(m, key {
+        match m {
+            Nil          => Ok(Nil),
+            Dict(map, _) => Ok(map.get(&hashable(key)?).cloned().unwrap_or(Nil)),
+            Set(set)     => MalType::set_lookup(set, &[key.clone()]),
+            _            => Err(TypeCheckFailed{}),
+        }
+    }));
+
+    symbols.insert("contains?".to_string(), function!(m, key -> Bool {
+        match m {
+            Dict(map, _) => Ok(map.contains_key(&hashable(key)?)),
+            Set(set)     => Ok(set.contains(&hashable(key)?)),
+            _            => Err(TypeCheckFailed{}),
+        }
+    }));
+
+    symbols.insert("keys".to_string(), function!(m: Dict {
+        Ok(MalType::list(m.keys().cloned().map(MalHashable::into).collect()))
+    }));
+
+    symbols.insert("vals".to_string(), function!(m: Dict {
+        Ok(MalType::list(m.values().cloned().collect()))
+    }));
+
+    symbols.insert("conj".to_string(), variadic_function!(args {
+        match args.split_first() {
+            Some((List(ls, _), xs))   => Ok(MalType::list(xs.iter().rev().chain(ls.iter()).cloned().collect())),
+            Some((Vector(ls, _), xs)) => Ok(MalType::vector(ls.iter().chain(xs.iter()).cloned().collect())),
+            Some((Set(set), xs))      => {
+                let mut set = set.clone();
+                for x in xs {
+                    set.insert(hashable(x)?);
+                }
+                Ok(Set(set))
+            }
+            _                         => Err(TypeCheckFailed{}),
+        }
+    }));
+
+    symbols.insert("set".to_string(), function!(ls -> Set {
+        match ls {
+            Nil                         => Ok(HashSet::new()),
+            List(ls, _) | Vector(ls, _) => ls.iter().map(hashable).collect(),
+            Set(set)                    => Ok(set.clone()),
+            _                           => Err(TypeCheckFailed{}),
+        }
+    }));
+
+    symbols.insert("set?".to_string(), function!(x -> Bool {
+        Ok(if let Set(_) = x { true } else { false })
+    }));
+
+    symbols.insert("disj".to_string(), variadic_function!(args {
+        match args.split_first() {
+            Some((Set(set), xs)) => {
+                let mut set = set.clone();
+                for x in xs {
+                    set.remove(&hashable(x)?);
+                }
+                Ok(Set(set))
+            }
+            _                    => Err(TypeCheckFailed{}),
+        }
+    }));
+
+    symbols.insert("union".to_string(), variadic_function!(args {
+        let mut union = HashSet::new();
+        for set in sets(args)? {
+            union.extend(set.iter().cloned());
+        }
+        Ok(Set(union))
+    }));
+
+    symbols.insert("intersection".to_string(), variadic_function!(args {
+        match sets(args)?.split_first() {
+            Some((first, rest)) => Ok(Set(first.iter().filter(|x| rest.iter().all(|set| set.contains(x))).cloned().collect())),
+            None                => Err(VariadicArityError { minimum: 1, reached: 0 }),
+        }
+    }));
+
+    symbols.insert("difference".to_string(), variadic_function!(args {
+        match sets(args)?.split_first() {
+            Some((first, rest)) => Ok(Set(first.iter().filter(|x| !rest.iter().any(|set| set.contains(x))).cloned().collect())),
+            None                => Err(VariadicArityError { minimum: 1, reached: 0 }),
+        }
+    }));
+
+    symbols.insert("subset?".to_string(), function!(a: Set, b: Set -> Bool {
+        Ok(a.is_subset(b))
+    }));
+
+    symbols.insert("seq".to_string(), function!(x {
+        match x {
+            List(ls, _) | Vector(ls, _) if ls.is_empty() => Ok(Nil),
+            List(ls, _) | Vector(ls, _)                  => Ok(MalType::list(ls.clone())),
+            Set(set) if set.is_empty()                   => Ok(Nil),
+            Set(set)                                     => Ok(MalType::list(set.iter().cloned().map(MalHashable::into).collect())),
+            Str(s) if s.is_empty()                       => Ok(Nil),
+            Str(s)                                       => Ok(MalType::list(s.chars().map(|c| Str(c.to_string())).collect())),
+            Nil                                          => Ok(Nil),
+            _                                            => Err(TypeCheckFailed{}),
+        }
     }));
 
     symbols.insert("count".to_string(), function!(ls -> Number {
         match ls {
-            Nil                   => Ok(Int(0)),
-            List(ls) | Vector(ls) => Ok(Int(ls.len() as i64)),
-            _                     => Err(TypeCheckFailed{}),
+            Nil                         => Ok(Int(0)),
+            List(ls, _) | Vector(ls, _) => Ok(Int(ls.len() as i64)),
+            Set(set)                    => Ok(Int(set.len() as i64)),
+            _                           => Err(TypeCheckFailed{}),
+        }
+    }));
+
+    symbols.insert("cons".to_string(), function!(x, ls {
+        match ls {
+            List(ls, _) | Vector(ls, _) => {
+                let mut elements = Vec::with_capacity(ls.len() + 1);
+                elements.push(x.clone());
+                elements.extend_from_slice(ls);
+                Ok(MalType::list(elements))
+            }
+            _                           => Err(TypeCheckFailed{}),
+        }
+    }));
+
+    symbols.insert("concat".to_string(), variadic_function!(args {
+        let mut elements = vec![];
+        for arg in args.iter() {
+            match arg {
+                List(ls, _) | Vector(ls, _) => elements.extend_from_slice(ls),
+                _                           => return Err(TypeCheckFailed{}),
+            }
+        }
+        Ok(MalType::list(elements))
+    }));
+
+    symbols.insert("nth".to_string(), function!(ls, index: Number {
+        match (ls, index) {
+            (List(ls, _), Int(i)) | (Vector(ls, _), Int(i)) => {
+                if *i < 0 || *i as usize >= ls.len() {
+                    return Err(IndexOutOfRange(*i));
+                }
+                Ok(ls[*i as usize].clone())
+            }
+            _                                               => Err(TypeCheckFailed{}),
+        }
+    }));
+
+    symbols.insert("first".to_string(), function!(ls {
+        match ls {
+            Nil                         => Ok(Nil),
+            List(ls, _) | Vector(ls, _) => Ok(ls.first().cloned().unwrap_or(Nil)),
+            _                           => Err(TypeCheckFailed{}),
+        }
+    }));
+
+    symbols.insert("rest".to_string(), function!(ls {
+        match ls {
+            Nil                         => Ok(MalType::list(vec![])),
+            List(ls, _) | Vector(ls, _) => Ok(MalType::list(ls.iter().skip(1).cloned().collect())),
+            _                           => Err(TypeCheckFailed{}),
+        }
+    }));
+
+    symbols.insert("apply".to_string(), variadic_function!(args {
+        if args.len() < 2 {
+            return Err(ArityError {
+                expected: 2,
+                reached: args.len(),
+            });
+        }
+
+        let mut call_args = args[1..args.len() - 1].to_vec();
+        match &args[args.len() - 1] {
+            List(ls, _) | Vector(ls, _) => call_args.extend_from_slice(ls),
+            _                           => return Err(TypeCheckFailed{}),
+        }
+        call(&args[0], &call_args)
+    }));
+
+    symbols.insert("map".to_string(), function!(f, ls {
+        match ls {
+            List(ls, _) | Vector(ls, _) => ls.iter().map(|x| call(f, &[x.clone()])).collect::<Result<_, _>>().map(MalType::list),
+            _                           => Err(TypeCheckFailed{}),
         }
     }));
 
+    symbols.insert("throw".to_string(), function!(x {
+        Err(Exception(x.clone()))
+    }));
+
     symbols.insert("pr-str".to_string(), variadic_function!(args {
         Ok(Str({
             let mut s = String::new();
@@ -110,8 +415,34 @@ pub(crate) fn ns() -> HashMap<String, MalType> {
         Ok(Nil)
     }));
 
-    symbols.insert("read-string".to_string(), function!(s: Str {
-        read_str(&s).transpose().unwrap_or(Ok(Nil))
+    // an optional second argument names the origin of the text for error locations
+    symbols.insert("read-string".to_string(), variadic_function!(args {
+        expect_arity!(args, 1, 2);
+        let read = match args {
+            [Str(s)]              => read_str(s),
+            [Str(s), Str(origin)] => read_str_from(s, origin),
+            _                     => return Err(TypeCheckFailed{}),
+        };
+        read.transpose().unwrap_or(Ok(Nil))
+    }));
+
+    // Mal data as JSON and back, through MalType's serde impls
+    symbols.insert("to-json".to_string(), function!(x -> Str {
+        serde_json::to_string(x).map_err(|e| IOError(e.into()))
+    }));
+
+    symbols.insert("from-json".to_string(), function!(json: Str {
+        serde_json::from_str(json).map_err(|e| IOError(e.into()))
+    }));
+
+    symbols.insert("readline".to_string(), function!(prompt: Str {
+        lazy_static! { static ref EDITOR: Mutex<Editor<()>> = Mutex::new(Editor::<()>::new()); }
+
+        match EDITOR.lock().unwrap().readline(prompt) {
+            Ok(line)                => Ok(Str(line.trim_end_matches(&['\r', '\n'][..]).to_string())),
+            Err(ReadlineError::Eof) => Ok(Nil),
+            Err(e)                  => Err(IOError(std::io::Error::new(std::io::ErrorKind::Other, e))),
+        }
     }));
 
     symbols.insert("slurp".to_string(), function!(filename: Str {
@@ -121,7 +452,7 @@ pub(crate) fn ns() -> HashMap<String, MalType> {
         let mut buffered_reader = BufReader::new(file);
         buffered_reader.read_to_string(&mut contents).map_err(IOError)?;
 
-        read_str(&contents).transpose().unwrap_or(Ok(Nil))
+        Ok(Str(contents))
     }));
 
     symbols.insert("atom".to_string(), function!(x {
@@ -137,12 +468,74 @@ pub(crate) fn ns() -> HashMap<String, MalType> {
     }));
 
     symbols.insert("reset!".to_string(), function!(x: Atom, value {
-        Ok(x.replace(value.clone()))
+        x.replace(value.clone());
+        Ok(value.clone())
+    }));
+
+    symbols.insert("swap!".to_string(), variadic_function!(args {
+        if args.len() < 2 {
+            return Err(ArityError {
+                expected: 2,
+                reached: args.len(),
+            });
+        }
+
+        match &args[0] {
+            Atom(x) => {
+                let mut call_args = vec![x.borrow().clone()];
+                call_args.extend_from_slice(&args[2..]);
+                let value = call(&args[1], &call_args)?;
+                x.replace(value.clone());
+                Ok(value)
+            }
+            _       => Err(TypeCheckFailed{}),
+        }
     }));
 
-    //symbols.insert("swap!".to_string(), function!(x: Atom, f: Function {
-    //    Ok(x.replace_with(f)) // TODO have `f` take extra arguments
-    //}));
+    symbols.insert("meta".to_string(), function!(x {
+        Ok(x.meta())
+    }));
+
+    symbols.insert("with-meta".to_string(), function!(x, meta {
+        x.with_meta(meta).ok_or(TypeCheckFailed{})
+    }));
+
+    symbols.insert("time-ms".to_string(), variadic_function!(args {
+        expect_arity!(args, 0);
+        let elapsed = SystemTime::now().duration_since(UNIX_EPOCH)
+            .map_err(|e| IOError(std::io::Error::new(std::io::ErrorKind::Other, e)))?;
+        Ok(Number(Int(elapsed.as_millis() as i64)))
+    }));
 
     symbols
 }
+
+fn call(f: &MalType, args: &[MalType]) -> MalResult {
+    match f {
+        MalType::Function(f, _) => f(args),
+        MalType::Set(set)       => MalType::set_lookup(set, args),
+        _                       => Err(MalError::NotEvaluable(f.clone())),
+    }
+}
+
+fn hashable(key: &MalType) -> Result<MalHashable, MalError> {
+    MalHashable::try_from(key.clone()).map_err(MalError::NotHashable)
+}
+
+/// The sets passed to `union`, `intersection` and `difference`.
+fn sets(args: &[MalType]) -> Result<Vec<&HashSet<MalHashable>>, MalError> {
+    args.iter()
+        .map(|x| if let MalType::Set(set) = x { Ok(set) } else { Err(MalError::TypeCheckFailed{}) })
+        .collect()
+}
+
+fn assoc(mut map: HashMap<MalHashable, MalType>, kvs: &[MalType]) -> Result<HashMap<MalHashable, MalType>, MalError> {
+    if kvs.len() % 2 != 0 {
+        return Err(MalError::OddMapEntries);
+    }
+
+    for kv in kvs.chunks(2) {
+        map.insert(hashable(&kv[0])?, kv[1].clone());
+    }
+    Ok(map)
+}
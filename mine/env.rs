@@ -4,7 +4,7 @@ use std::collections::HashMap;
 use std::hash::Hash;
 use std::rc::Rc;
 
-use crate::types::MalType;
+use crate::types::{MalError, MalType};
 
 #[derive(Clone, Debug)]
 pub(crate) struct Env {
@@ -44,6 +44,42 @@ impl Env {
     }
 }
 
+/// The parameter list of a `fn*`: positional symbols, optionally followed by
+/// `& rest` to collect any remaining arguments into a list.
+#[derive(Clone, Debug)]
+pub(crate) struct Params {
+    symbols: Vec<String>,
+    rest: Option<String>,
+}
+
+impl Params {
+    pub fn parse(bindings: &MalType) -> Result<Self, MalError> {
+        let values = match bindings {
+            MalType::List(values, _) | MalType::Vector(values, _) => values,
+            _ => return Err(MalError::CannotBindArguments(bindings.clone())),
+        };
+
+        let mut symbols = vec![];
+        let mut rest = None;
+        let mut values = values.iter();
+        while let Some(value) = values.next() {
+            match value {
+                MalType::Symbol(symbol) if symbol == "&" => {
+                    // `&` must be followed by exactly one symbol
+                    match (values.next(), values.next()) {
+                        (Some(MalType::Symbol(symbol)), None) => rest = Some(symbol.clone()),
+                        _                                     => return Err(MalError::CannotBindArguments(bindings.clone())),
+                    }
+                }
+                MalType::Symbol(symbol) => symbols.push(symbol.clone()),
+                _                       => return Err(MalError::CanOnlyDefineSymbols(bindings.clone())),
+            }
+        }
+
+        Ok(Params { symbols, rest })
+    }
+}
+
 #[derive(Clone, Debug)]
 pub(crate) struct EnvRef(Rc<RefCell<Env>>);
 
@@ -56,6 +92,36 @@ impl EnvRef {
         Self::new(Env::wrap(outer))
     }
 
+    /// Creates a child of `outer` with `params` bound to `args`.
+    pub fn bind(outer: EnvRef, params: &Params, args: &[MalType]) -> Result<Self, MalError> {
+        let reached = args.len();
+        match params.rest {
+            None if reached != params.symbols.len() => {
+                return Err(MalError::ArityError {
+                    expected: params.symbols.len(),
+                    reached,
+                });
+            }
+            Some(_) if reached < params.symbols.len() => {
+                return Err(MalError::VariadicArityError {
+                    minimum: params.symbols.len(),
+                    reached,
+                });
+            }
+            _ => {}
+        }
+
+        let mut env = Self::refer_to(outer);
+        for (symbol, value) in params.symbols.iter().zip(args.iter()) {
+            env.set(symbol.clone(), value.clone());
+        }
+        if let Some(rest) = &params.rest {
+            env.set(rest.clone(), MalType::list(args[params.symbols.len()..].to_vec()));
+        }
+
+        Ok(env)
+    }
+
     pub fn get<Q: ?Sized>(&self, key: &Q) -> Option<MalType>
         where String: Borrow<Q>,
               Q: Hash + Eq,
@@ -1,15 +1,15 @@
 #[allow(unused_macros)]
 macro_rules! expect_arity {
     ($args:expr, $($expected:expr),*) => {
-        #[allow(unused_assignments, unused_variables)] {
+        #[allow(unused_assignments)] {
+            let mut matched = false;
+            let mut expected = 0;
             $(
-                let mut expected = Some($expected);
-                if $args.len() == $expected {
-                    expected = None;
-                }
+                matched |= $args.len() == $expected;
+                expected = $expected;
             )*
 
-            if let Some(expected) = expected {
+            if !matched {
                 return Err($crate::types::MalError::ArityError {
                     expected: expected,
                     reached: $args.len(),
@@ -31,7 +31,7 @@ macro_rules! make_function {
                 MalResult,
             };
 
-            Function(std::rc::Rc::new($f))
+            MalType::function($f)
         }
     }
 }
@@ -53,7 +53,7 @@ macro_rules! bind_args {
 
             $(
                 let $binding = {
-                    if let $binding_type($binding) = $binding {
+                    if let $binding_type($binding, ..) = $binding {
                         $binding
                     } else {
                         return Err(TypeCheckFailed{});
@@ -108,7 +108,7 @@ macro_rules! function_chain {
 
             move |args: &[MalType]| -> MalResult {
                 for f in functions.iter() {
-                    if let Function(f) = f {
+                    if let Function(f, _) = f {
                         match f(args) {
                             Err(TypeCheckFailed{}) => {},
                             ret @ Ok(_) | ret @ Err(_)   => return ret,
@@ -130,3 +130,12 @@ macro_rules! binary_operator {
         function!(a: $left, b: $right -> $out { Ok((*a) $op (*b)) })
     }
 }
+
+/// Arithmetic can fail (division by zero, overflow), so unlike `binary_operator!` the result is
+/// passed through as-is.
+#[allow(unused_macros)]
+macro_rules! arithmetic_operator {
+    ($op:tt) => {
+        function!(a: Number, b: Number -> Number { (*a) $op (*b) })
+    }
+}
@@ -3,19 +3,36 @@ use crate::types::{MalType, MalHashable};
 pub(crate) fn pr_str(t: &MalType, readably: bool) -> String {
     use MalType::*;
     match t {
-        Atom(x)     => pr_str(&x.borrow(), readably),
-        List(x)     => pr_list(x, "(", ")", readably),
-        Vector(x)   => pr_list(x, "[", "]", readably),
-        Dict(x)     => pr_dict(x, "{", "}", readably),
-        Symbol(x)   => x.clone(),
-        Number(x)   => x.to_string(),
-        Bool(x)     => x.to_string(),
-        Str(x)      => if readably { "\"".to_string() + x + "\"" } else { x.clone() },
-        Nil         => "nil".to_string(),
-        Function(_) => "#<function>".to_string(),
+        Atom(x)      => format!("(atom {})", pr_str(&x.borrow(), readably)),
+        List(x, _)   => pr_list(x, "(", ")", readably),
+        Vector(x, _) => pr_list(x, "[", "]", readably),
+        Dict(x, _)   => pr_dict(x, "{", "}", readably),
+        Set(x)       => pr_list(&x.iter().cloned().map(MalHashable::into).collect::<Vec<_>>(), "#{", "}", readably),
+        Symbol(x)    => x.clone(),
+        Keyword(x)   => format!(":{}", x),
+        Number(x)    => x.to_string(),
+        Bool(x)      => x.to_string(),
+        Str(x)       => if readably { "\"".to_string() + &escape_str(x) + "\"" } else { x.clone() },
+        Nil          => "nil".to_string(),
+        Function(..) => "#<function>".to_string(),
+        Macro(_)     => "#<macro>".to_string(),
     }
 }
 
+/// Escapes a string so that it reads back as itself. Inverse of `reader::unescape_str`.
+pub(crate) fn escape_str(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"'  => escaped += "\\\"",
+            '\\' => escaped += "\\\\",
+            '\n' => escaped += "\\n",
+            _    => escaped.push(c),
+        }
+    }
+    escaped
+}
+
 fn pr_list<'a, It>(elements: It, opening: &str, closing: &str, readably: bool) -> String
     where It: IntoIterator<Item=&'a MalType>,
 {
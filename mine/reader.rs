@@ -1,73 +1,117 @@
-use std::collections::HashMap;
-use std::str::{self, FromStr};
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+use std::str::FromStr;
 
 use regex::Regex;
+use num_rational::Rational64;
 
-use crate::types::{MalType, MalError, MalHashable, MalNumber};
+use crate::types::{MalType, MalError, MalHashable, MalNumber, Span};
 
-struct Reader(Vec<Token>);
+/// 1-based line and column of a token.
+type Position = (usize, usize);
+
+struct Reader {
+    tokens: Vec<(Token, Position)>,
+    origin: Option<Rc<str>>,
+}
 
 impl Reader {
-    fn new(mut tokens: Vec<Token>) -> Self {
-        tokens.reverse();
-        Reader(tokens)
+    fn next(&mut self) -> Option<(Token, Position)> {
+        self.tokens.pop()
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.last().map(|(token, _)| token)
     }
 
-    fn next(&mut self) -> Option<Token> {
-        self.0.pop()
+    /// Where `position` is, when reading from a named origin.
+    fn span(&self, (line, column): Position) -> Option<Span> {
+        self.origin.as_ref().map(|origin| Span { origin: origin.clone(), line, column })
     }
 
-    fn peek(&self) -> Option<&Token> {
-        self.0.last()
+    /// Attach the location of `position` to `error` when reading from a named origin.
+    fn locate(&self, error: MalError, position: Position) -> MalError {
+        match self.span(position) {
+            Some(span) => error.at(span),
+            None       => error,
+        }
     }
 }
 
-const TOKENS_REGEX: &str = r#"[\s,]*(~@|[\[\]{}()'`~^@]|"(?:\\.|[^\\"])*"?|;.*|[^\s\[\]{}('"`,;)]*)"#;
+const TOKENS_REGEX: &str = r#"[\s,]*(~@|#\{|[\[\]{}()'`~^@]|"(?:\\.|[^\\"])*"?|;.*|[^\s\[\]{}('"`,;)]*)"#;
 
 #[derive(Debug, PartialEq)]
 pub(crate) struct ReaderParseError;
 
-impl FromStr for Reader {
-    type Err = MalError;
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
+impl Reader {
+    fn tokenize(s: &str, origin: Option<Rc<str>>) -> Result<Self, MalError> {
         lazy_static! { static ref RE: Regex = Regex::new(TOKENS_REGEX).unwrap(); }
 
-        // tokenize
-        let tokens: Result<Vec<_>, _> = RE.captures_iter(s)
-            .map(|cap| Token::from_str(str::from_utf8(cap[1].as_bytes()).unwrap()))
-            .collect();
-        tokens.map(Reader::new)
+        let mut reader = Reader { tokens: vec![], origin };
+        let (mut line, mut line_start, mut scanned) = (1, 0, 0);
+        for cap in RE.captures_iter(s) {
+            let m = cap.get(1).unwrap();
+            for (i, c) in s[scanned..m.start()].char_indices() {
+                if c == '\n' {
+                    line += 1;
+                    line_start = scanned + i + 1;
+                }
+            }
+            scanned = m.start();
+
+            // skip the empty match left by trailing whitespace
+            if m.as_str().is_empty() {
+                continue;
+            }
+
+            // tokenize, dropping comments
+            let position = (line, s[line_start..m.start()].chars().count() + 1);
+            match Token::from_str(m.as_str()) {
+                Ok(Token::Comment) => {}
+                Ok(token)          => reader.tokens.push((token, position)),
+                Err(e)             => return Err(reader.locate(e, position)),
+            }
+        }
+
+        reader.tokens.reverse();
+        Ok(reader)
     }
 }
 
 #[derive(Debug, PartialEq)]
 enum Token {
     Special(char),        // []{}()'`~^@
-    SpecialTwoCharacters, // @~
+    SpecialTwoCharacters, // ~@
+    SetOpening,           // #{
     Comment,              // The ";" token
     Literal(Literal),     // integers, floats, booleans, strings, nil, ...
     Symbol(String),       // identifiers
 }
 
 const SPECIAL_CHARS: &str = "[]{}()'`~^@";
-const SPECIAL_TWO_CHARS: &str = "@~";
+const SPECIAL_TWO_CHARS: &str = "~@";
+const SET_OPENING: &str = "#{";
 const COMMENT_CHAR: char = ';';
 
 impl FromStr for Token {
     type Err = MalError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        // special characters / comments
+        // comments run until the end of the line
+        if s.starts_with(COMMENT_CHAR) {
+            return Ok(Token::Comment);
+        }
+
+        // special characters
         if s.len() == 1 {
             let c = s.chars().next().unwrap();
             if SPECIAL_CHARS.contains(c) {
                 return Ok(Token::Special(c));
-            } else if c == COMMENT_CHAR {
-                return Ok(Token::Comment);
             }
         } else if s == SPECIAL_TWO_CHARS {
             return Ok(Token::SpecialTwoCharacters);
+        } else if s == SET_OPENING {
+            return Ok(Token::SetOpening);
         }
 
         // literals
@@ -88,13 +132,16 @@ impl FromStr for Token {
 #[derive(Debug, PartialEq)]
 enum Literal {
     Int(i64),
+    Ratio(Rational64),
     Float(f64),
     Bool(bool),
     Str(String),
+    Keyword(String),
     Nil,
 }
 
 const NIL_STR: &str = "nil";
+const KEYWORD_CHAR: char = ':';
 const STRING_QUOTE_CHAR: char = '"';
 
 #[derive(Debug, PartialEq)]
@@ -122,44 +169,78 @@ impl FromStr for Literal {
             return Ok(Literal::Int(i));
         }
 
+        // ratios, e.g. 1/3
+        if let Ok(r) = s.parse::<Rational64>() {
+            return Ok(Literal::Ratio(r));
+        }
+
         // floats
         if let Ok(f) = s.parse::<f64>() {
             return Ok(Literal::Float(f));
         }
 
+        // keywords
+        if s.len() >= 2 && s.starts_with(KEYWORD_CHAR) {
+            return Ok(Literal::Keyword(s[1..].to_string()));
+        }
+
         // strings
-        if s.len() >= 2 {
-            let first = s.chars().next().unwrap();
+        if s.starts_with(STRING_QUOTE_CHAR) {
+            return unescape_str(&s[1..])
+                .map(Literal::Str)
+                .ok_or(LiteralParseError::UnbalancedString);
+        }
 
-            if first == STRING_QUOTE_CHAR {
-                let last = s.chars().last().unwrap();
-                if last != STRING_QUOTE_CHAR {
-                    return Err(LiteralParseError::UnbalancedString);
-                }
+        Err(LiteralParseError::Unspecified)
+    }
+}
 
-                let s = s.chars()
-                    .skip(1)            // after the opening quote
-                    .take(s.len() - 2)  // before the closing quote
-                    .collect();
-                return Ok(Literal::Str(s));
+/// Reads the contents of a string literal (after the opening quote) up to the
+/// closing quote, which must end the token. Inverse of `printer::escape_str`.
+fn unescape_str(s: &str) -> Option<String> {
+    let mut unescaped = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                match chars.next()? {
+                    'n' => unescaped.push('\n'),
+                    c   => unescaped.push(c),
+                }
             }
-        }
 
-        Err(LiteralParseError::Unspecified)
+            STRING_QUOTE_CHAR => return if chars.next().is_none() { Some(unescaped) } else { None },
+
+            _ => unescaped.push(c),
+        }
     }
+
+    None // no closing quote
 }
 
 pub(crate) fn read_str(s: &str) -> Result<Option<MalType>, MalError> {
-    let mut reader = Reader::from_str(s)?;
+    let mut reader = Reader::tokenize(s, None)?;
+    read_form(&mut reader)
+}
+
+/// Like `read_str`, but errors report where in `origin` (e.g. a file name) they occurred.
+pub(crate) fn read_str_from(s: &str, origin: &str) -> Result<Option<MalType>, MalError> {
+    let mut reader = Reader::tokenize(s, Some(origin.into()))?;
     read_form(&mut reader)
 }
 
 fn read_form(reader: &mut Reader) -> Result<Option<MalType>, MalError> {
     reader.next()
-        .map(|token| {
-            match token {
-                Token::Special('(') => read_list(reader, ')', |elements| Ok(MalType::List(elements))),
-                Token::Special('[') => read_list(reader, ']', |elements| Ok(MalType::Vector(elements))),
+        .map(|(token, position)| {
+            let form = match token {
+                Token::Special('(') => read_list(reader, ')', |elements| Ok(MalType::list(elements))).map(|list| {
+                    // lists from a named origin carry their location, for evaluation errors
+                    match reader.span(position) {
+                        Some(span) => list.with_meta(&span.to_meta()).unwrap_or(list),
+                        None       => list,
+                    }
+                }),
+                Token::Special('[') => read_list(reader, ']', |elements| Ok(MalType::vector(elements))),
                 Token::Special('{') => read_list(reader, '}', |elements| {
                     if elements.len() % 2 != 0 {
                         return Err(MalError::OddMapEntries);
@@ -181,22 +262,58 @@ fn read_form(reader: &mut Reader) -> Result<Option<MalType>, MalError> {
                         }
                     }
 
-                    Ok(MalType::Dict(map))
+                    Ok(MalType::dict(map))
                 }),
-                Token::Special('@') => {
-                    if let Some(value) = read_form(reader)? {
-                        let deref_symbol = MalType::Symbol("deref".to_string());
-                        Ok(MalType::List(vec![deref_symbol, value]))
-                    } else {
-                        Err(MalError::LoneDeref)
+                Token::SetOpening => read_list(reader, '}', |elements| {
+                    let mut set = HashSet::with_capacity(elements.len());
+                    for element in elements {
+                        let element = MalHashable::try_from(element)
+                            .map_err(MalError::NotHashable)?;
+                        if !set.insert(element.clone()) {
+                            return Err(MalError::DuplicateKey(element.into()));
+                        }
                     }
+                    Ok(MalType::Set(set))
+                }),
+                Token::Special('@') => {
+                    read_macro(reader, "deref", MalError::LoneDeref)
                 }
-                _                   => Ok(read_atom(token))
-            }
+                Token::Special('\'') => {
+                    read_macro(reader, "quote", MalError::LoneQuote("'".to_string()))
+                }
+                Token::Special('`') => {
+                    read_macro(reader, "quasiquote", MalError::LoneQuote("`".to_string()))
+                }
+                Token::Special('~') => {
+                    read_macro(reader, "unquote", MalError::LoneQuote("~".to_string()))
+                }
+                Token::Special('^') => {
+                    // `^meta value` reads as `(with-meta value meta)`
+                    let meta = read_form(reader)?.ok_or(MalError::LoneQuote("^".to_string()))?;
+                    let value = read_form(reader)?.ok_or(MalError::LoneQuote("^".to_string()))?;
+                    Ok(MalType::list(vec![MalType::Symbol("with-meta".to_string()), value, meta]))
+                }
+                Token::SpecialTwoCharacters => {
+                    read_macro(reader, "splice-unquote", MalError::LoneQuote(SPECIAL_TWO_CHARS.to_string()))
+                }
+                _                   => read_atom(token)
+            };
+
+            // nested forms are already located, so this only marks errors raised by this one
+            form.map_err(|e| reader.locate(e, position))
         })
         .transpose()
 }
 
+/// Expand a reader macro such as `'x` into `(quote x)`, failing with `error` if no form follows.
+fn read_macro(reader: &mut Reader, symbol: &str, error: MalError) -> Result<MalType, MalError> {
+    if let Some(value) = read_form(reader)? {
+        Ok(MalType::list(vec![MalType::Symbol(symbol.to_string()), value]))
+    } else {
+        Err(error)
+    }
+}
+
 fn read_list<T>(reader: &mut Reader, closing: char, consumer: fn(Vec<MalType>) -> Result<T, MalError>) -> Result<T, MalError> {
     let mut paren_matched = false;
     let mut elements = vec![];
@@ -219,15 +336,20 @@ fn read_list<T>(reader: &mut Reader, closing: char, consumer: fn(Vec<MalType>) -
     }
 }
 
-fn read_atom(token: Token) -> MalType {
+fn read_atom(token: Token) -> Result<MalType, MalError> {
     match token {
-        Token::Symbol(s)                  => MalType::Symbol(s),
-        Token::Literal(Literal::Int(i))   => MalType::Number(MalNumber::Int(i)),
-        Token::Literal(Literal::Float(f)) => MalType::Number(MalNumber::Float(f)),
-        Token::Literal(Literal::Bool(b))  => MalType::Bool(b),
-        Token::Literal(Literal::Str(s))   => MalType::Str(s),
-        Token::Literal(Literal::Nil)      => MalType::Nil,
-        _                                 => unimplemented!(),
+        Token::Symbol(s)                    => Ok(MalType::Symbol(s)),
+        Token::Literal(Literal::Int(i))     => Ok(MalType::Number(MalNumber::Int(i))),
+        Token::Literal(Literal::Ratio(r))   => Ok(MalType::Number(MalNumber::Ratio(r).normalize())),
+        Token::Literal(Literal::Float(f))   => Ok(MalType::Number(MalNumber::Float(f))),
+        Token::Literal(Literal::Bool(b))    => Ok(MalType::Bool(b)),
+        Token::Literal(Literal::Str(s))     => Ok(MalType::Str(s)),
+        Token::Literal(Literal::Keyword(k)) => Ok(MalType::Keyword(k)),
+        Token::Literal(Literal::Nil)        => Ok(MalType::Nil),
+        Token::Special(c)                   => Err(MalError::UnexpectedToken(c.to_string())), // a stray closing bracket
+        Token::SpecialTwoCharacters         => Err(MalError::UnexpectedToken(SPECIAL_TWO_CHARS.to_string())),
+        Token::SetOpening                   => Err(MalError::UnexpectedToken(SET_OPENING.to_string())),
+        Token::Comment                      => Err(MalError::UnexpectedToken(COMMENT_CHAR.to_string())),
     }
 }
 
@@ -242,6 +364,7 @@ mod tests {
         }
 
         assert_eq!(Token::from_str(SPECIAL_TWO_CHARS).unwrap(), Token::SpecialTwoCharacters);
+        assert_eq!(Token::from_str(SET_OPENING).unwrap(), Token::SetOpening);
 
         assert_eq!(Token::from_str("true").unwrap(), Token::Literal(Literal::Bool(true)));
     }
@@ -255,6 +378,11 @@ mod tests {
         // integers
         assert_eq!(Literal::from_str("123"), Ok(Literal::Int(123)));
 
+        // ratios
+        assert_eq!(Literal::from_str("1/3"), Ok(Literal::Ratio(Rational64::new(1, 3))));
+        assert_eq!(Literal::from_str("-6/4"), Ok(Literal::Ratio(Rational64::new(-3, 2))));
+        assert_eq!(Literal::from_str("1/0"), Err(LiteralParseError::Unspecified));
+
         // floats
         assert_eq!(Literal::from_str("1.2"), Ok(Literal::Float(1.2)));
         assert_eq!(Literal::from_str("0.2"), Ok(Literal::Float(0.2)));
@@ -263,8 +391,118 @@ mod tests {
         // nil
         assert_eq!(Literal::from_str("nil"), Ok(Literal::Nil));
 
+        // keywords
+        assert_eq!(Literal::from_str(":foo"), Ok(Literal::Keyword("foo".to_string())));
+        assert_eq!(Literal::from_str(":"), Err(LiteralParseError::Unspecified));
+
         // strings
         assert_eq!(Literal::from_str("\"foobar\""), Ok(Literal::Str("foobar".to_string())));
         assert_eq!(Literal::from_str("\"foobar"), Err(LiteralParseError::UnbalancedString));
+        assert_eq!(Literal::from_str("\"a\\\"b\\nc\\\\\""), Ok(Literal::Str("a\"b\nc\\".to_string())));
+        assert_eq!(Literal::from_str("\"foobar\\\""), Err(LiteralParseError::UnbalancedString));
+    }
+
+    #[test]
+    fn test_string_round_trip() {
+        for s in &["", "foo", "a\"b", "back\\slash", "new\nline", "\\\"\n\\n"] {
+            let printed = crate::printer::pr_str(&MalType::Str(s.to_string()), true);
+            assert_eq!(read_str(&printed).unwrap(), Some(MalType::Str(s.to_string())));
+        }
+
+        for s in &["\"\"", "\"a\\\\b\"", "\"\\\"\\n\""] {
+            let read = read_str(s).unwrap().unwrap();
+            assert_eq!(crate::printer::pr_str(&read, true), *s);
+        }
+    }
+
+    #[test]
+    fn test_keywords() {
+        let dict = read_str("{:a 1}").unwrap().unwrap();
+        assert_eq!(crate::printer::pr_str(&dict, true), "{:a 1}");
+        assert_ne!(read_str(":a").unwrap(), read_str("\"a\"").unwrap());
+    }
+
+    #[test]
+    fn test_sets() {
+        let set = read_str("#{:a}").unwrap().unwrap();
+        assert_eq!(crate::printer::pr_str(&set, true), "#{:a}");
+        assert_eq!(read_str("#{1 2}").unwrap(), read_str("#{2 1}").unwrap());
+        assert_ne!(read_str("#{1}").unwrap(), read_str("[1]").unwrap());
+
+        match read_str("#{1 2 1}") {
+            Err(MalError::DuplicateKey(x)) => assert_eq!(x, MalType::Number(MalNumber::Int(1))),
+            other                          => panic!("unexpected result {:?}", other),
+        }
+        assert!(read_str("#{1.5}").is_err());
+        assert!(read_str("#{1").is_err());
+    }
+
+    #[test]
+    fn test_quote_reader_macros() {
+        let quoted = |symbol: &str, value| {
+            MalType::list(vec![MalType::Symbol(symbol.to_string()), value])
+        };
+        let a = || MalType::Symbol("a".to_string());
+
+        assert_eq!(read_str("'a").unwrap(), Some(quoted("quote", a())));
+        assert_eq!(read_str("`a").unwrap(), Some(quoted("quasiquote", a())));
+        assert_eq!(read_str("~a").unwrap(), Some(quoted("unquote", a())));
+        assert_eq!(read_str("~@a").unwrap(), Some(quoted("splice-unquote", a())));
+        assert_eq!(read_str("@a").unwrap(), Some(quoted("deref", a())));
+
+        assert_eq!(
+            read_str("^{\"a\" 1} b").unwrap(),
+            Some(MalType::list(vec![
+                MalType::Symbol("with-meta".to_string()),
+                MalType::Symbol("b".to_string()),
+                read_str("{\"a\" 1}").unwrap().unwrap(),
+            ]))
+        );
+
+        match read_str("`") {
+            Err(MalError::LoneQuote(token)) => assert_eq!(token, "`"),
+            other                           => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_malformed_input() {
+        for (input, token) in &[(")", ")"), ("]", "]"), ("[1 }", "}")] {
+            match read_str(input) {
+                Err(MalError::UnexpectedToken(t)) => assert_eq!(t, *token),
+                other                             => panic!("unexpected result {:?} for {:?}", other, input),
+            }
+        }
+
+        assert_eq!(read_str("   ").unwrap(), None);
+        assert!(read_str("(1 2").is_err());
+        assert!(read_str("{1}").is_err());
+        assert!(read_str("^{}").is_err());
+    }
+
+    #[test]
+    fn test_error_locations() {
+        match read_str_from("(1 ; one\n  (2 ]", "x.mal") {
+            Err(MalError::At(e, span)) => {
+                assert_eq!(format!("{:?}", e), "UnexpectedToken(\"]\")");
+                assert_eq!(span.to_string(), "x.mal:2:6");
+            }
+            other                      => panic!("unexpected result {:?}", other),
+        }
+
+        match read_str_from("\n\n  (1 2", "y.mal") {
+            Err(MalError::At(_, span)) => assert_eq!(span.to_string(), "y.mal:3:3"),
+            other                      => panic!("unexpected result {:?}", other),
+        }
+
+        // without an origin errors are left as they are
+        assert!(match read_str("(1 2") { Err(MalError::UnbalancedList) => true, _ => false });
+    }
+
+    #[test]
+    fn test_comments() {
+        assert_eq!(read_str("7 ;; comment").unwrap(), Some(MalType::Number(MalNumber::Int(7))));
+        assert_eq!(read_str(";; comment").unwrap(), None);
+        assert_eq!(read_str("(1 ; one\n 2)").unwrap(), read_str("(1 2)").unwrap());
     }
 }
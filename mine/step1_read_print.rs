@@ -25,11 +25,9 @@ fn print(t: types::MalType) -> String {
 
 fn rep(s: &str) -> String {
     match read(s) {
-        Ok(Some(t))                            => print(eval(t)),
-        Ok(None)                               => "EOF".to_string(),
-        Err(types::MalError::UnbalancedString) => "unbalanced string".to_string(),
-        Err(types::MalError::UnbalancedList)   => "unbalanced list".to_string(),
-        _                                      => unimplemented!(),
+        Ok(Some(t)) => print(eval(t)),
+        Ok(None)    => "EOF".to_string(),
+        Err(e)      => e.to_string(),
     }
 }
 
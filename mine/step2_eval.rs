@@ -16,11 +16,11 @@ fn read(s: &str) -> Result<Option<types::MalType>, types::MalError> {
     reader::read_str(s)
 }
 
-struct Callable<'a>(Box<'a + Fn(&[types::MalType]) -> types::MalType>);
+struct Callable<'a>(Box<'a + Fn(&[types::MalType]) -> Result<types::MalType, EvalError>>);
 
 impl<'a> Callable<'a> {
     fn new2<F>(f: F) -> Self
-        where F: 'a + Fn(&types::MalType, &types::MalType) -> types::MalType,
+        where F: 'a + Fn(&types::MalType, &types::MalType) -> Result<types::MalType, EvalError>,
     {
         Callable(Box::new(move |args| {
             if args.len() != 2 { panic!("function expected 2 arguments, got {}", args.len()); }
@@ -35,7 +35,7 @@ type ReplEnv = HashMap<String, Callable<'static>>;
 struct EvalError;
 
 fn eval(ast: types::MalType, repl_env: &ReplEnv) -> Result<types::MalType, EvalError> {
-    if let types::MalType::List(elements) = ast {
+    if let types::MalType::List(elements, _) = ast {
         let mut evaluated_elements = vec![];
         for element in elements {
             let evaluated = eval(element, repl_env)?;
@@ -46,15 +46,15 @@ fn eval(ast: types::MalType, repl_env: &ReplEnv) -> Result<types::MalType, EvalE
             match &evaluated_elements[0] {
                 types::MalType::Symbol(symbol) => {
                     repl_env.get(symbol)
-                        .map(|callable| callable.0(&evaluated_elements[1..]))
                         .ok_or(EvalError)
+                        .and_then(|callable| callable.0(&evaluated_elements[1..]))
                 }
 
                 _ => Err(EvalError),
 
             }
         } else {
-            Ok(types::MalType::List(vec![]))
+            Ok(types::MalType::list(vec![]))
         }
     } else {
         Ok(ast)
@@ -73,12 +73,12 @@ fn rep(s: &str) -> String {
             Callable::new2(|a, b| {
                 use types::MalType::*;
                 let ret = if let (Number(a), Number(b)) = (a, b) {
-                    Some(Number((*a) $op (*b)))
+                    Some(Number(((*a) $op (*b)).map_err(|_| EvalError)?))
                 } else {
                     None
                 };
 
-                ret.unwrap()
+                Ok(ret.unwrap())
             })
         }
     }
@@ -89,11 +89,9 @@ fn rep(s: &str) -> String {
     repl_env.insert("/".to_string(), arithmetic_operation!(/));
 
     match read(s) {
-        Ok(Some(t))                            => eval(t, &repl_env).map(print).unwrap_or("evaluation error".to_string()),
-        Ok(None)                               => "EOF".to_string(),
-        Err(types::MalError::UnbalancedString) => "unbalanced string".to_string(),
-        Err(types::MalError::UnbalancedList)   => "unbalanced list".to_string(),
-        _                                      => unimplemented!(),
+        Ok(Some(t)) => eval(t, &repl_env).map(print).unwrap_or("evaluation error".to_string()),
+        Ok(None)    => "EOF".to_string(),
+        Err(e)      => e.to_string(),
     }
 }
 
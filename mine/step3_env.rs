@@ -1,5 +1,6 @@
 // #![deny(warnings)]
 
+use std::collections::HashSet;
 use std::io;
 
 #[macro_use] extern crate lazy_static;
@@ -17,6 +18,7 @@ use env::{Env, EnvRef};
 use types::{
     MalType as AST,
     MalError as ASTError,
+    MalHashable,
 };
 
 fn read(s: &str) -> Result<Option<AST>, ASTError> {
@@ -45,7 +47,8 @@ fn eval_def(args: &[AST], env: &mut EnvRef) -> Result<AST, ASTError> {
 fn eval_let(args: &[AST], env: &EnvRef) -> Result<AST, ASTError> {
     if args.len() == 2 {
         match &args[0] {
-            AST::List(bindings) | AST::Vector(bindings) => {
+            AST::List(bindings, _) | AST::Vector(bindings, _) if bindings.len() % 2 != 0 => Err(OddBindings(args[0].clone())),
+            AST::List(bindings, _) | AST::Vector(bindings, _) => {
                 let mut new_env = EnvRef::refer_to(env.clone());
                 for let_args in bindings.chunks(2) {
                     if let AST::Symbol(symbol) = &let_args[0] {
@@ -57,7 +60,7 @@ fn eval_let(args: &[AST], env: &EnvRef) -> Result<AST, ASTError> {
                 }
                 eval(&args[1], &mut new_env)
             }
-            _ => unimplemented!()
+            _ => Err(CannotBindArguments(args[0].clone()))
         }
     } else {
         Err(ArityError {
@@ -74,22 +77,31 @@ fn eval_ast(ast: &AST, env: &mut EnvRef) -> Result<AST, ASTError> {
                 .unwrap_or(ast.clone()))
         }
 
-        AST::List(elements) => {
+        AST::List(elements, _) => {
             let mut evals = vec![];
             for element in elements {
                 let evaluated = eval(element, env)?;
                 evals.push(evaluated);
             }
-            Ok(AST::List(evals))
+            Ok(AST::list(evals))
         }
 
-        AST::Vector(elements) => {
+        AST::Vector(elements, _) => {
             let mut evals = vec![];
             for element in elements {
                 let evaluated = eval(element, env)?;
                 evals.push(evaluated);
             }
-            Ok(AST::Vector(evals))
+            Ok(AST::vector(evals))
+        }
+
+        AST::Set(elements) => {
+            let mut evals = HashSet::new();
+            for element in elements {
+                let evaluated = eval(&element.clone().into(), env)?;
+                evals.insert(MalHashable::try_from(evaluated).map_err(NotHashable)?);
+            }
+            Ok(AST::Set(evals))
         }
 
         ast @ _ => Ok(ast.clone()),
@@ -97,7 +109,7 @@ fn eval_ast(ast: &AST, env: &mut EnvRef) -> Result<AST, ASTError> {
 }
 
 fn eval_apply(ast: &AST) -> Result<AST, ASTError> {
-    if let AST::List(elems) = ast {
+    if let AST::List(elems, _) = ast {
         match &elems[0] {
             AST::Symbol(symbol)          => Err(SymbolNotFound(symbol.to_string())),
             ast @ _                      => Err(NotEvaluable(ast.clone())),
@@ -108,7 +120,7 @@ fn eval_apply(ast: &AST) -> Result<AST, ASTError> {
 }
 
 fn eval(ast: &AST, env: &mut EnvRef) -> Result<AST, ASTError> {
-    if let AST::List(elems) = ast {
+    if let AST::List(elems, _) = ast {
         if !elems.is_empty() {
             if let AST::Symbol(symbol) = &elems[0] {
                 if symbol == "def!" {
@@ -119,7 +131,7 @@ fn eval(ast: &AST, env: &mut EnvRef) -> Result<AST, ASTError> {
             }
             eval_apply(&eval_ast(ast, env)?)
         } else {
-            Ok(AST::List(vec![]))
+            Ok(AST::list(vec![]))
         }
     } else {
         eval_ast(ast, env)
@@ -133,36 +145,24 @@ fn print(ast: &AST) -> String {
 fn eval_print(ast: &AST, env: &mut EnvRef) -> String {
     match eval(ast, env) {
         Ok(ast) => print(&ast),
-        Err(e)  => {
-            match e {
-                CanOnlyDefineSymbols(ast)        => format!("can only def! symbols (not '{}')", print(&ast)),
-                NotEvaluable(ast)                => format!("cannot evaluate '{}'", print(&ast)),
-                SymbolNotFound(symbol)           => format!("symbol '{}' not found", symbol),
-                TypeCheckFailed {}               => format!("typecheck failed"),
-                ArityError { expected, reached } =>
-                    format!("arity error, tried to call symbol expecting {} arguments with {}", reached, expected),
-                _                                => unimplemented!(),
-            }
-        }
+        Err(e)  => e.to_string(),
     }
 }
 
 fn rep(s: &str, env: &mut EnvRef) -> String {
     match read(s) {
-        Ok(Some(ast))         => eval_print(&ast, env),
-        Ok(None)              => "EOF".to_string(),
-        Err(UnbalancedString) => "unbalanced string".to_string(),
-        Err(UnbalancedList)   => "unbalanced list".to_string(),
-        _                     => unimplemented!(),
+        Ok(Some(ast)) => eval_print(&ast, env),
+        Ok(None)      => "EOF".to_string(),
+        Err(e)        => e.to_string(),
     }
 }
 
 fn default_env() -> Env {
     let mut env = Env::new();
-    env.set("+".to_string(), binary_operator!(Number + Number -> Number));
-    env.set("-".to_string(), binary_operator!(Number - Number -> Number));
-    env.set("*".to_string(), binary_operator!(Number * Number -> Number));
-    env.set("/".to_string(), binary_operator!(Number / Number -> Number));
+    env.set("+".to_string(), arithmetic_operator!(+));
+    env.set("-".to_string(), arithmetic_operator!(-));
+    env.set("*".to_string(), arithmetic_operator!(*));
+    env.set("/".to_string(), arithmetic_operator!(/));
     env
 }
 
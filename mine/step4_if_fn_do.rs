@@ -1,5 +1,6 @@
 // #![deny(warnings)]
 
+use std::collections::HashSet;
 use std::io;
 
 #[macro_use] extern crate lazy_static;
@@ -14,12 +15,12 @@ mod printer;
 mod reader;
 mod types;
 
-use std::rc::Rc;
-use env::{Env, EnvRef};
+use env::{Env, EnvRef, Params};
 
 use types::{
     MalType as AST,
     MalError as ASTError,
+    MalHashable,
 };
 
 fn read(s: &str) -> Result<Option<AST>, ASTError> {
@@ -48,7 +49,8 @@ fn eval_def(args: &[AST], env: &mut EnvRef) -> Result<AST, ASTError> {
 fn eval_let(args: &[AST], env: &EnvRef) -> Result<AST, ASTError> {
     if args.len() == 2 {
         match &args[0] {
-            AST::List(bindings) | AST::Vector(bindings) => {
+            AST::List(bindings, _) | AST::Vector(bindings, _) if bindings.len() % 2 != 0 => Err(OddBindings(args[0].clone())),
+            AST::List(bindings, _) | AST::Vector(bindings, _) => {
                 let mut new_env = EnvRef::refer_to(env.clone());
                 for let_args in bindings.chunks(2) {
                     if let AST::Symbol(symbol) = &let_args[0] {
@@ -60,7 +62,7 @@ fn eval_let(args: &[AST], env: &EnvRef) -> Result<AST, ASTError> {
                 }
                 eval(&args[1], &mut new_env)
             }
-            _ => unimplemented!()
+            _ => Err(CannotBindArguments(args[0].clone()))
         }
     } else {
         Err(ArityError {
@@ -117,42 +119,15 @@ fn eval_fn(args: &[AST], env: &EnvRef) -> Result<AST, ASTError> {
         });
     }
 
-    match &args[0] {
-        AST::List(bindings) | AST::Vector(bindings) => {
-            let mut symbols = vec![];
-            for value in bindings {
-                match value {
-                    AST::Symbol(symbol) => {
-                        symbols.push(symbol.clone());
-                    }
-
-                    _ => unimplemented!() // can only name args using symbols
-                }
-            }
-
-            let body = args[1].clone();
-            let captured_env = EnvRef::refer_to(env.clone());
-            let f = move |args: &[AST]| -> Result<AST, ASTError> {
-                if args.len() != symbols.len() {
-                    return Err(ArityError {
-                        expected: symbols.len(),
-                        reached: args.len(),
-                    });
-                }
-
-                let mut call_env = EnvRef::refer_to(captured_env.clone());
-                for (symbol, value) in symbols.iter().zip(args.iter()) {
-                    call_env.set(symbol.clone(), value.clone());
-                }
-
-                eval(&body, &mut call_env)
-            };
+    let params = Params::parse(&args[0])?;
+    let body = args[1].clone();
+    let captured_env = EnvRef::refer_to(env.clone());
+    let f = move |args: &[AST]| -> Result<AST, ASTError> {
+        let mut call_env = EnvRef::bind(captured_env.clone(), &params, args)?;
+        eval(&body, &mut call_env)
+    };
 
-            Ok(AST::Function(Rc::new(f)))
-        }
-
-        _ => unimplemented!() // cannot read args from anything other than a list/vector
-    }
+    Ok(AST::function(f))
 }
 
 fn eval_ast(ast: &AST, env: &mut EnvRef) -> Result<AST, ASTError> {
@@ -162,22 +137,31 @@ fn eval_ast(ast: &AST, env: &mut EnvRef) -> Result<AST, ASTError> {
                 .unwrap_or(ast.clone()))
         }
 
-        AST::List(elements) => {
+        AST::List(elements, _) => {
             let mut evals = vec![];
             for element in elements {
                 let evaluated = eval(element, env)?;
                 evals.push(evaluated);
             }
-            Ok(AST::List(evals))
+            Ok(AST::list(evals))
         }
 
-        AST::Vector(elements) => {
+        AST::Vector(elements, _) => {
             let mut evals = vec![];
             for element in elements {
                 let evaluated = eval(element, env)?;
                 evals.push(evaluated);
             }
-            Ok(AST::Vector(evals))
+            Ok(AST::vector(evals))
+        }
+
+        AST::Set(elements) => {
+            let mut evals = HashSet::new();
+            for element in elements {
+                let evaluated = eval(&element.clone().into(), env)?;
+                evals.insert(MalHashable::try_from(evaluated).map_err(NotHashable)?);
+            }
+            Ok(AST::Set(evals))
         }
 
         ast @ _ => Ok(ast.clone()),
@@ -185,9 +169,10 @@ fn eval_ast(ast: &AST, env: &mut EnvRef) -> Result<AST, ASTError> {
 }
 
 fn eval_apply(ast: &AST) -> Result<AST, ASTError> {
-    if let AST::List(elems) = ast {
+    if let AST::List(elems, _) = ast {
         match &elems[0] {
-            AST::Function(func)          => func(&elems[1..]),
+            AST::Function(func, _)       => func(&elems[1..]),
+            AST::Set(set)                => AST::set_lookup(set, &elems[1..]),
             AST::Symbol(symbol)          => Err(SymbolNotFound(symbol.to_string())),
             ast @ _                      => Err(NotEvaluable(ast.clone())),
         }
@@ -197,7 +182,7 @@ fn eval_apply(ast: &AST) -> Result<AST, ASTError> {
 }
 
 fn eval(ast: &AST, env: &mut EnvRef) -> Result<AST, ASTError> {
-    if let AST::List(elems) = ast {
+    if let AST::List(elems, _) = ast {
         if !elems.is_empty() {
             if let AST::Symbol(symbol) = &elems[0] {
                 let args = &elems[1..];
@@ -215,7 +200,7 @@ fn eval(ast: &AST, env: &mut EnvRef) -> Result<AST, ASTError> {
             }
             eval_apply(&eval_ast(ast, env)?)
         } else {
-            Ok(AST::List(vec![]))
+            Ok(AST::list(vec![]))
         }
     } else {
         eval_ast(ast, env)
@@ -229,27 +214,15 @@ fn print(ast: &AST) -> String {
 fn eval_print(ast: &AST, env: &mut EnvRef) -> String {
     match eval(ast, env) {
         Ok(ast) => print(&ast),
-        Err(e)  => {
-            match e {
-                CanOnlyDefineSymbols(ast)        => format!("can only def! symbols (not '{}')", print(&ast)),
-                NotEvaluable(ast)                => format!("cannot evaluate '{}'", print(&ast)),
-                SymbolNotFound(symbol)           => format!("symbol '{}' not found", symbol),
-                TypeCheckFailed {}               => format!("typecheck failed"),
-                ArityError { expected, reached } =>
-                    format!("arity error, tried to call symbol expecting {} arguments with {}", expected, reached),
-                _                                => unimplemented!(),
-            }
-        }
+        Err(e)  => e.to_string(),
     }
 }
 
 fn rep(s: &str, env: &mut EnvRef) -> String {
     match read(s) {
-        Ok(Some(ast))         => eval_print(&ast, env),
-        Ok(None)              => "EOF".to_string(),
-        Err(UnbalancedString) => "unbalanced string".to_string(),
-        Err(UnbalancedList)   => "unbalanced list".to_string(),
-        _                     => unimplemented!(),
+        Ok(Some(ast)) => eval_print(&ast, env),
+        Ok(None)      => "EOF".to_string(),
+        Err(e)        => e.to_string(),
     }
 }
 
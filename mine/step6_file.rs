@@ -1,5 +1,6 @@
 // #![deny(warnings)]
 
+use std::collections::HashSet;
 use std::io;
 use std::iter::FromIterator;
 
@@ -15,11 +16,12 @@ mod printer;
 mod reader;
 mod types;
 
-use env::{Env, EnvRef};
+use env::{Env, EnvRef, Params};
 
 use types::{
     MalType as AST,
     MalError as ASTError,
+    MalHashable,
 };
 
 fn read(s: &str) -> Result<Option<AST>, ASTError> {
@@ -36,19 +38,28 @@ fn eval_ast_list<T>(elements: T, env: &mut EnvRef) -> Result<Vec<AST>, ASTError>
         .collect()
 }
 
+fn eval_ast_set(elements: HashSet<MalHashable>, env: &mut EnvRef) -> Result<HashSet<MalHashable>, ASTError> {
+    elements.into_iter()
+        .map(|x| eval(x.into(), env.clone()))
+        .map(|x| x.and_then(|x| MalHashable::try_from(x).map_err(NotHashable)))
+        .collect()
+}
+
 fn eval_ast(ast: AST, env: &mut EnvRef) -> Result<AST, ASTError> {
     match ast.clone() {
-        AST::Symbol(symbol)   => Ok(env.get(&symbol[..]).unwrap_or(ast)),
-        AST::List(elements)   => eval_ast_list(elements, env).map(AST::List),
-        AST::Vector(elements) => eval_ast_list(elements, env).map(AST::Vector),
-        ast @ _               => Ok(ast),
+        AST::Symbol(symbol)      => Ok(env.get(&symbol[..]).unwrap_or(ast)),
+        AST::List(elements, _)   => eval_ast_list(elements, env).map(AST::list),
+        AST::Vector(elements, _) => eval_ast_list(elements, env).map(AST::vector),
+        AST::Set(elements)       => eval_ast_set(elements, env).map(AST::Set),
+        ast @ _                  => Ok(ast),
     }
 }
 
 fn eval_apply(ast: &AST) -> Result<AST, ASTError> {
-    if let AST::List(elements) = ast {
+    if let AST::List(elements, _) = ast {
         match &elements[0] {
-            AST::Function(func)          => func(&elements[1..]),
+            AST::Function(func, _)       => func(&elements[1..]),
+            AST::Set(set)                => AST::set_lookup(set, &elements[1..]),
             AST::Symbol(symbol)          => Err(SymbolNotFound(symbol.to_string())),
             ast @ _                      => Err(NotEvaluable(ast.clone())),
         }
@@ -59,15 +70,15 @@ fn eval_apply(ast: &AST) -> Result<AST, ASTError> {
 
 macro_rules! expect_arity {
     ($args:expr, $($expected:expr),*) => {
-        #[allow(unused_assignments, unused_variables)] {
+        #[allow(unused_assignments)] {
+            let mut matched = false;
+            let mut expected = 0;
             $(
-                let mut expected = Some($expected);
-                if $args.len() == $expected {
-                    expected = None;
-                }
+                matched |= $args.len() == $expected;
+                expected = $expected;
             )*
 
-            if let Some(expected) = expected {
+            if !matched {
                 return Err(ArityError {
                     expected: expected,
                     reached: $args.len(),
@@ -79,9 +90,9 @@ macro_rules! expect_arity {
 
 fn eval(mut ast: AST, mut env: EnvRef) -> Result<AST, ASTError> {
     loop {
-        if let AST::List(elements) = &ast {
+        if let AST::List(elements, _) = &ast {
             if elements.is_empty() {
-                return Ok(AST::List(vec![]));
+                return Ok(AST::list(vec![]));
             }
 
             if let AST::Symbol(symbol) = &elements[0] {
@@ -111,7 +122,10 @@ fn eval(mut ast: AST, mut env: EnvRef) -> Result<AST, ASTError> {
 
                         let mut new_env = EnvRef::refer_to(env);
                         match let_symbol {
-                            AST::List(bindings) | AST::Vector(bindings) => {
+                            AST::List(bindings, _) | AST::Vector(bindings, _) if bindings.len() % 2 != 0 => {
+                                return Err(OddBindings(let_symbol.clone()))
+                            }
+                            AST::List(bindings, _) | AST::Vector(bindings, _) => {
                                 for let_args in bindings.chunks(2) {
                                     let binding_symbol = let_args[0].clone();
                                     let binding_value = let_args[1].clone();
@@ -166,43 +180,17 @@ fn eval(mut ast: AST, mut env: EnvRef) -> Result<AST, ASTError> {
                     "fn*" => {
                         expect_arity!(args, 2);
 
-                        let fn_symbol = args[0].clone();
+                        let params = Params::parse(&args[0])?;
                         let fn_body = args[1].clone();
 
-                        match fn_symbol.clone() {
-                            AST::List(bindings) | AST::Vector(bindings) => {
-                                let mut symbols = vec![];
-                                for value in bindings {
-                                    if let AST::Symbol(symbol) = value {
-                                        symbols.push(symbol.clone());
-                                    } else {
-                                        return Err(CanOnlyDefineSymbols(fn_symbol));
-                                    }
-                                }
-
-                                let captured_env = env.clone();
-                                let new_ast = make_function!(move |args: &[AST]| -> Result<AST, ASTError> {
-                                    if args.len() != symbols.len() {
-                                        return Err(ArityError {
-                                            expected: symbols.len(),
-                                            reached: args.len(),
-                                        });
-                                    }
-
-                                    let mut call_env = EnvRef::refer_to(captured_env.clone());
-                                    for (symbol, value) in symbols.iter().zip(args.iter()) {
-                                        call_env.set(symbol.clone(), value.clone());
-                                    }
-
-                                    eval(fn_body.clone(), call_env)
-                                });
+                        let captured_env = env.clone();
+                        let new_ast = make_function!(move |args: &[AST]| -> Result<AST, ASTError> {
+                            let call_env = EnvRef::bind(captured_env.clone(), &params, args)?;
+                            eval(fn_body.clone(), call_env)
+                        });
 
-                                ast = new_ast;
-                                continue; // don't run the `apply` phase just yet
-                            }
-
-                            _ => return Err(CannotBindArguments(fn_symbol))
-                        }
+                        ast = new_ast;
+                        continue; // don't run the `apply` phase just yet
                     }
 
                     _ => {} // run the `apply` phase
@@ -223,28 +211,10 @@ fn print(ast: &AST) -> String {
     printer::pr_str(&ast, true)
 }
 
-fn print_error(ast_error: &ASTError) -> String {
-    match ast_error {
-        CanOnlyDefineSymbols(ast)        => format!("can only define symbols (not '{}')", print(&ast)),
-        CannotBindArguments(ast)         => format!("cannot bind arguments using '{}', expected a list", print(&ast)),
-        NotEvaluable(ast)                => format!("cannot evaluate '{}'", print(&ast)),
-        SymbolNotFound(symbol)           => format!("symbol '{}' not found", symbol),
-        TypeCheckFailed {}               => format!("typecheck failed"),
-        ArityError { expected, reached } => format!("arity error, tried to call symbol expecting {} arguments with {}", expected, reached),
-        UnbalancedString                 => "unbalanced string".to_string(),
-        UnbalancedList                   => "unbalanced list".to_string(),
-        NotHashable(ast)                 => format!("{} is not hashable", print(&ast)),
-        OddMapEntries                    => "odd number of entries in map".to_string(),
-        DuplicateKey(ast)                => format!("duplicate key {}", print(&ast)),
-        LoneDeref                        => "'@' must be followed by a value".to_string(),
-        IOError(e)                       => format!("I/O error: {:?}", e),
-    }
-}
-
 fn eval_print(ast: AST, env: EnvRef) -> String {
     match eval(ast, env) {
         Ok(ast) => print(&ast),
-        Err(e)  => print_error(&e),
+        Err(e)  => e.to_string(),
     }
 }
 
@@ -252,7 +222,7 @@ fn rep(s: &str, env: EnvRef) -> String {
     match read(s) {
         Ok(Some(ast)) => eval_print(ast, env),
         Ok(None)      => "EOF".to_string(),
-        Err(e)        => print_error(&e),
+        Err(e)        => e.to_string(),
     }
 }
 
@@ -274,7 +244,7 @@ fn main() -> io::Result<()> {
         eval(ast.clone(), captured_env.clone())
     }));
 
-    rep("(def! load-file (fn* (f) (eval (read-string (str \"(do \" (slurp f) \")\")))))", env.clone());
+    rep("(def! load-file (fn* (f) (eval (read-string (str \"(do \" (slurp f) \")\") f))))", env.clone());
 
     loop {
         match rl.readline("user> ") {
@@ -0,0 +1,403 @@
+// #![deny(warnings)]
+
+use std::collections::HashSet;
+use std::io;
+use std::iter::FromIterator;
+
+#[macro_use] extern crate lazy_static;
+
+use rustyline::{Editor, error::ReadlineError};
+
+#[macro_use] mod macros;
+
+mod core;
+mod env;
+mod printer;
+mod reader;
+mod types;
+
+use env::{Env, EnvRef, Params};
+
+use types::{
+    MalType as AST,
+    MalError as ASTError,
+    MalHashable,
+};
+
+fn read(s: &str) -> Result<Option<AST>, ASTError> {
+    reader::read_str(s)
+}
+
+use ASTError::*;
+
+fn eval_ast_list<T>(elements: T, env: &mut EnvRef) -> Result<Vec<AST>, ASTError>
+    where T: IntoIterator<Item=AST> + FromIterator<AST>
+{
+    elements.into_iter()
+        .map(|x| eval(x, env.clone()))
+        .collect()
+}
+
+fn eval_ast_set(elements: HashSet<MalHashable>, env: &mut EnvRef) -> Result<HashSet<MalHashable>, ASTError> {
+    elements.into_iter()
+        .map(|x| eval(x.into(), env.clone()))
+        .map(|x| x.and_then(|x| MalHashable::try_from(x).map_err(NotHashable)))
+        .collect()
+}
+
+fn eval_ast(ast: AST, env: &mut EnvRef) -> Result<AST, ASTError> {
+    match ast.clone() {
+        AST::Symbol(symbol)      => env.get(&symbol[..]).ok_or(SymbolNotFound(symbol)),
+        AST::List(elements, _)   => eval_ast_list(elements, env).map(AST::list),
+        AST::Vector(elements, _) => eval_ast_list(elements, env).map(AST::vector),
+        AST::Set(elements)       => eval_ast_set(elements, env).map(AST::Set),
+        AST::Dict(elements, _)   => {
+            elements.into_iter()
+                .map(|(key, value)| eval(value, env.clone()).map(|value| (key, value)))
+                .collect::<Result<_, _>>()
+                .map(AST::dict)
+        }
+        ast @ _                  => Ok(ast),
+    }
+}
+
+fn eval_apply(ast: &AST) -> Result<AST, ASTError> {
+    if let AST::List(elements, _) = ast {
+        match &elements[0] {
+            AST::Function(func, _)       => func(&elements[1..]),
+            AST::Set(set)                => AST::set_lookup(set, &elements[1..]),
+            AST::Symbol(symbol)          => Err(SymbolNotFound(symbol.to_string())),
+            ast @ _                      => Err(NotEvaluable(ast.clone())),
+        }
+    } else {
+        unreachable!()
+    }
+}
+
+macro_rules! expect_arity {
+    ($args:expr, $($expected:expr),*) => {
+        #[allow(unused_assignments)] {
+            let mut matched = false;
+            let mut expected = 0;
+            $(
+                matched |= $args.len() == $expected;
+                expected = $expected;
+            )*
+
+            if !matched {
+                return Err(ArityError {
+                    expected: expected,
+                    reached: $args.len(),
+                });
+            }
+        }
+    }
+}
+
+fn is_pair(ast: &AST) -> bool {
+    match ast {
+        AST::List(elements, _) | AST::Vector(elements, _) => !elements.is_empty(),
+        _                                                 => false,
+    }
+}
+
+fn quasiquote(ast: AST) -> Result<AST, ASTError> {
+    if !is_pair(&ast) {
+        return Ok(AST::list(vec![AST::Symbol("quote".to_string()), ast]));
+    }
+
+    let elements = match ast {
+        AST::List(elements, _) | AST::Vector(elements, _) => elements,
+        _                                                 => unreachable!(),
+    };
+
+    if let AST::Symbol(symbol) = &elements[0] {
+        if symbol == "unquote" {
+            expect_arity!(elements[1..], 1);
+            return Ok(elements[1].clone());
+        }
+    }
+
+    let rest = quasiquote(AST::list(elements[1..].to_vec()))?;
+
+    if let AST::List(inner, _) | AST::Vector(inner, _) = &elements[0] {
+        if let Some(AST::Symbol(symbol)) = inner.first() {
+            if symbol == "splice-unquote" {
+                expect_arity!(inner[1..], 1);
+                return Ok(AST::list(vec![AST::Symbol("concat".to_string()), inner[1].clone(), rest]));
+            }
+        }
+    }
+
+    let first = quasiquote(elements[0].clone())?;
+    Ok(AST::list(vec![AST::Symbol("cons".to_string()), first, rest]))
+}
+
+fn macro_call(ast: &AST, env: &EnvRef) -> Option<AST> {
+    if let AST::List(elements, _) = ast {
+        if let Some(AST::Symbol(symbol)) = elements.first() {
+            if let Some(value @ AST::Macro(_)) = env.get(&symbol[..]) {
+                return Some(value);
+            }
+        }
+    }
+    None
+}
+
+fn macroexpand(mut ast: AST, env: &EnvRef) -> Result<AST, ASTError> {
+    while let Some(AST::Macro(func)) = macro_call(&ast, env) {
+        ast = if let AST::List(elements, _) = &ast {
+            func(&elements[1..])?
+        } else {
+            unreachable!()
+        };
+    }
+    Ok(ast)
+}
+
+fn eval(mut ast: AST, mut env: EnvRef) -> Result<AST, ASTError> {
+    loop {
+        ast = macroexpand(ast, &env)?;
+
+        if let AST::List(elements, _) = &ast {
+            if elements.is_empty() {
+                return Ok(AST::list(vec![]));
+            }
+
+            if let AST::Symbol(symbol) = &elements[0] {
+                let args = (&elements[1..]).to_owned();
+
+                match symbol.as_str() {
+                    "def!" => {
+                        expect_arity!(args, 2);
+                        let def_symbol = &args[0];
+                        let def_value = args[1].clone();
+
+                        if let AST::Symbol(symbol) = def_symbol {
+                            let new_ast = eval(def_value, env.clone())?;
+                            env.set(symbol.to_string(), new_ast.clone());
+
+                            return Ok(new_ast);
+                        } else {
+                            return Err(CanOnlyDefineSymbols(def_symbol.clone()));
+                        }
+                    }
+
+                    "let*" => {
+                        expect_arity!(args, 2);
+                        let let_symbol = &args[0];
+                        let let_value = args[1].clone();
+
+                        let mut new_env = EnvRef::refer_to(env);
+                        match let_symbol {
+                            AST::List(bindings, _) | AST::Vector(bindings, _) if bindings.len() % 2 != 0 => {
+                                return Err(OddBindings(let_symbol.clone()))
+                            }
+                            AST::List(bindings, _) | AST::Vector(bindings, _) => {
+                                for let_args in bindings.chunks(2) {
+                                    let binding_symbol = let_args[0].clone();
+                                    let binding_value = let_args[1].clone();
+
+                                    if let AST::Symbol(symbol) = &binding_symbol {
+                                        let value = eval(binding_value.clone(), new_env.clone())?;
+                                        new_env.set(symbol.to_string(), value);
+                                    } else {
+                                        return Err(CanOnlyDefineSymbols(binding_symbol.clone()));
+                                    }
+                                }
+                            }
+                            _ => return Err(CannotBindArguments(let_symbol.clone()))
+                        }
+
+                        ast = let_value;
+                        env = new_env;
+                        continue; // don't run the `apply` phase just yet
+                    }
+
+                    "do" => {
+                        let mut args = args;
+                        let last = args.pop().unwrap_or(AST::Nil);
+                        for arg in args {
+                            eval(arg, env.clone())?;
+                        }
+
+                        ast = last;
+                        continue; // evaluate the last form in tail position
+                    }
+
+                    "quote" => {
+                        expect_arity!(args, 1);
+                        return Ok(args[0].clone());
+                    }
+
+                    "quasiquote" => {
+                        expect_arity!(args, 1);
+                        ast = quasiquote(args[0].clone())?;
+                        continue; // evaluate the expanded form
+                    }
+
+                    "if" => {
+                        expect_arity!(args, 2, 3);
+
+                        let if_predicate = args[0].clone();
+                        let if_true_branch = args[1].clone();
+                        let if_false_branch = if args.len() > 2 { args[2].clone() } else { AST::Nil };
+
+                        // A temporary env is used to prevent mutation when evaluating the condition
+                        let condition_env = EnvRef::refer_to(env.clone());
+
+                        let new_ast = {
+                            match eval(if_predicate, condition_env)? {
+                                AST::Nil | AST::Bool(false) => if_false_branch,
+                                _                           => if_true_branch,
+                            }
+                        };
+
+                        ast = new_ast;
+                        continue; // don't run the `apply` phase just yet
+                    }
+
+                    "defmacro!" => {
+                        expect_arity!(args, 2);
+                        let def_symbol = &args[0];
+                        let def_value = args[1].clone();
+
+                        if let AST::Symbol(symbol) = def_symbol {
+                            let new_ast = match eval(def_value, env.clone())? {
+                                AST::Function(func, _) => AST::Macro(func),
+                                _                      => return Err(TypeCheckFailed{}),
+                            };
+                            env.set(symbol.to_string(), new_ast.clone());
+
+                            return Ok(new_ast);
+                        } else {
+                            return Err(CanOnlyDefineSymbols(def_symbol.clone()));
+                        }
+                    }
+
+                    "macroexpand" => {
+                        expect_arity!(args, 1);
+                        return macroexpand(args[0].clone(), &env);
+                    }
+
+                    "try*" => {
+                        expect_arity!(args, 1, 2);
+
+                        // validate the `catch*` block before evaluating anything
+                        let catch = match args.get(1) {
+                            Some(AST::List(catch_args, _)) => {
+                                match &catch_args[..] {
+                                    [AST::Symbol(catch), AST::Symbol(symbol), body] if catch == "catch*" => {
+                                        Some((symbol.clone(), body.clone()))
+                                    }
+                                    _ => return Err(CannotBindArguments(args[1].clone())),
+                                }
+                            }
+                            Some(catch_block) => return Err(CannotBindArguments(catch_block.clone())),
+                            None              => None,
+                        };
+
+                        match (eval(args[0].clone(), env.clone()), catch) {
+                            (Err(error), Some((symbol, body))) => {
+                                let mut catch_env = EnvRef::refer_to(env);
+                                catch_env.set(symbol, error.into());
+
+                                ast = body;
+                                env = catch_env;
+                                continue; // evaluate the `catch*` body in tail position
+                            }
+                            (result, _) => return result,
+                        }
+                    }
+
+                    "fn*" => {
+                        expect_arity!(args, 2);
+
+                        let params = Params::parse(&args[0])?;
+                        let fn_body = args[1].clone();
+
+                        let captured_env = env.clone();
+                        let new_ast = make_function!(move |args: &[AST]| -> Result<AST, ASTError> {
+                            let call_env = EnvRef::bind(captured_env.clone(), &params, args)?;
+                            eval(fn_body.clone(), call_env)
+                        });
+
+                        ast = new_ast;
+                        continue; // don't run the `apply` phase just yet
+                    }
+
+                    _ => {} // run the `apply` phase
+                }
+            } else {
+                // run the `apply` phase
+            }
+
+            // `apply` phase
+            return eval_apply(&eval_ast(ast, &mut env)?);
+        } else {
+            return eval_ast(ast, &mut env);
+        }
+    }
+}
+
+fn print(ast: &AST) -> String {
+    printer::pr_str(&ast, true)
+}
+
+fn eval_print(ast: AST, env: EnvRef) -> String {
+    match eval(ast, env) {
+        Ok(ast) => print(&ast),
+        Err(e)  => e.to_string(),
+    }
+}
+
+fn rep(s: &str, env: EnvRef) -> String {
+    match read(s) {
+        Ok(Some(ast)) => eval_print(ast, env),
+        Ok(None)      => "EOF".to_string(),
+        Err(e)        => e.to_string(),
+    }
+}
+
+fn main() -> io::Result<()> {
+    // `()` can be used when no completer is required
+    let mut rl = Editor::<()>::new();
+    let _ = rl.load_history(".mal-history");
+
+    // initialize repl environment
+    let mut env = Env::new();
+    for (symbol, value) in core::ns() {
+        env.set(symbol, value);
+    }
+
+    // add `eval` method to repl environment
+    let mut env = EnvRef::new(env);
+    let captured_env = env.clone();
+    env.set("eval".to_string(), function!(ast {
+        eval(ast.clone(), captured_env.clone())
+    }));
+
+    rep("(def! load-file (fn* (f) (eval (read-string (str \"(do \" (slurp f) \")\") f))))", env.clone());
+    rep("(defmacro! cond (fn* (& xs) (if (> (count xs) 0) (list 'if (first xs) (if (> (count xs) 1) (nth xs 1) (throw \"odd number of forms to cond\")) (cons 'cond (rest (rest xs)))))))", env.clone());
+    rep("(defmacro! or (fn* (& xs) (if (empty? xs) nil (if (= 1 (count xs)) (first xs) `(let* (or_FIXME ~(first xs)) (if or_FIXME or_FIXME (or ~@(rest xs))))))))", env.clone());
+
+    loop {
+        match rl.readline("user> ") {
+            Ok(line) => {
+                rl.add_history_entry(line.to_string());
+                rl.save_history(".mal-history").unwrap();
+                if line.len() > 0 {
+                    println!("{}", rep(&line, env.clone()));
+                }
+            },
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof)         => break,
+            Err(err) => {
+                eprintln!("readline error: {:?}", err);
+                break
+            }
+        }
+    }
+
+    Ok(())
+}
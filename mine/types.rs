@@ -1,7 +1,7 @@
 #![allow(unused)] // TODO remove this
 
 use std::cmp;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
 use std::hash::{Hash, Hasher};
 use std::io;
@@ -9,24 +9,81 @@ use std::ops::{Add, Sub, Mul, Div};
 use std::rc::Rc;
 use std::cell::RefCell;
 
+use num_rational::Rational64;
+use num_traits::{CheckedAdd, CheckedSub, CheckedMul, CheckedDiv};
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+use serde::de::{self, Visitor, SeqAccess, MapAccess, DeserializeSeed};
+use serde::ser::{Error as _, SerializeMap};
+
 #[derive(Clone)]
 pub(crate) enum MalType {
     Atom(Rc<RefCell<MalType>>),
     Bool(bool),
-    Dict(HashMap<MalHashable, MalType>),
-    List(Vec<MalType>),
+    Dict(HashMap<MalHashable, MalType>, Rc<MalType>),
+    List(Vec<MalType>, Rc<MalType>),
     Nil,
     Number(MalNumber),
+    Set(HashSet<MalHashable>),
     Str(String),
     Symbol(String),
-    Vector(Vec<MalType>),
-    Function(Rc<dyn Fn(&[MalType]) -> MalResult>),
+    Keyword(String),
+    Vector(Vec<MalType>, Rc<MalType>),
+    Function(Rc<dyn Fn(&[MalType]) -> MalResult>, Rc<MalType>),
+    Macro(Rc<dyn Fn(&[MalType]) -> MalResult>),
 }
 
 impl MalType {
     pub fn atom(inner: Self) -> Self {
         MalType::Atom(Rc::new(RefCell::new(inner)))
     }
+
+    pub fn function<F>(f: F) -> Self
+        where F: Fn(&[MalType]) -> MalResult + 'static,
+    {
+        MalType::Function(Rc::new(f), Rc::new(MalType::Nil))
+    }
+
+    pub fn list(elements: Vec<MalType>) -> Self {
+        MalType::List(elements, Rc::new(MalType::Nil))
+    }
+
+    pub fn vector(elements: Vec<MalType>) -> Self {
+        MalType::Vector(elements, Rc::new(MalType::Nil))
+    }
+
+    pub fn dict(map: HashMap<MalHashable, MalType>) -> Self {
+        MalType::Dict(map, Rc::new(MalType::Nil))
+    }
+
+    /// The metadata attached with `with-meta`, `nil` if there is none.
+    pub fn meta(&self) -> MalType {
+        match self {
+            MalType::List(_, meta) | MalType::Vector(_, meta) |
+            MalType::Dict(_, meta) | MalType::Function(_, meta) => (**meta).clone(),
+            _                                                   => MalType::Nil,
+        }
+    }
+
+    /// A copy of a list, vector, map or function carrying `meta`.
+    pub fn with_meta(&self, meta: &MalType) -> Option<Self> {
+        let meta = Rc::new(meta.clone());
+        match self {
+            MalType::List(x, _)     => Some(MalType::List(x.clone(), meta)),
+            MalType::Vector(x, _)   => Some(MalType::Vector(x.clone(), meta)),
+            MalType::Dict(x, _)     => Some(MalType::Dict(x.clone(), meta)),
+            MalType::Function(f, _) => Some(MalType::Function(f.clone(), meta)),
+            _                       => None,
+        }
+    }
+
+    /// Sets are callable as membership tests: `(#{1 2} 1)` is `1` and `(#{1 2} 3)` is `nil`.
+    pub fn set_lookup(set: &HashSet<MalHashable>, args: &[MalType]) -> MalResult {
+        if args.len() != 1 {
+            return Err(MalError::ArityError { expected: 1, reached: args.len() });
+        }
+        let found = MalHashable::try_from(args[0].clone()).map_or(false, |x| set.contains(&x));
+        Ok(if found { args[0].clone() } else { MalType::Nil })
+    }
 }
 
 #[derive(Debug)]
@@ -40,6 +97,10 @@ pub(crate) enum MalError {
         expected: usize,
         reached: usize,
     },
+    VariadicArityError {
+        minimum: usize,
+        reached: usize,
+    },
     NotEvaluable(MalType),
     CanOnlyDefineSymbols(MalType),
     CannotBindArguments(MalType),
@@ -47,10 +108,166 @@ pub(crate) enum MalError {
     UnbalancedString,
     UnbalancedList,
     OddMapEntries,
+    OddBindings(MalType),
     NotHashable(MalType),
     DuplicateKey(MalType),
     LoneDeref,
+    UnexpectedToken(String),
+    LoneQuote(String),
+    IndexOutOfRange(i64),
+    DivisionByZero,
+    IntegerOverflow,
+    NotRepresentableAsInt(f64),
     IOError(io::Error),
+    Exception(MalType),
+    At(Box<MalError>, Span),
+}
+
+/// Where a form was read from: the origin name (e.g. a file) and 1-based line and column.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Span {
+    pub origin: Rc<str>,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Span {
+    /// The span as `:file`, `:line` and `:col` entries, for list metadata and error maps.
+    fn entries(&self) -> Vec<(MalHashable, MalType)> {
+        let int = |x: usize| MalType::Number(MalNumber::Int(x as i64));
+        vec![
+            (MalHashable::Keyword("file".to_string()), MalType::Str(self.origin.to_string())),
+            (MalHashable::Keyword("line".to_string()), int(self.line)),
+            (MalHashable::Keyword("col".to_string()), int(self.column)),
+        ]
+    }
+
+    /// The metadata the reader gives a list read from a named origin.
+    pub fn to_meta(&self) -> MalType {
+        MalType::dict(self.entries().into_iter().collect())
+    }
+
+    /// The span the reader recorded in a list's metadata, if it is still there.
+    pub fn of(form: &MalType) -> Option<Span> {
+        let map = match form {
+            MalType::List(_, meta) => match &**meta {
+                MalType::Dict(map, _) => map,
+                _                     => return None,
+            },
+            _                      => return None,
+        };
+        let get = |key: &str| map.get(&MalHashable::Keyword(key.to_string()));
+        match (get("file"), get("line"), get("col")) {
+            (Some(MalType::Str(origin)), Some(MalType::Number(MalNumber::Int(line))), Some(MalType::Number(MalNumber::Int(column)))) => {
+                Some(Span { origin: origin.as_str().into(), line: *line as usize, column: *column as usize })
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}:{}:{}", self.origin, self.line, self.column)
+    }
+}
+
+impl MalError {
+    /// Attach a location, keeping the innermost one if the error already has it.
+    pub fn at(self, span: Span) -> Self {
+        match self {
+            MalError::At(..) => self,
+            error            => MalError::At(Box::new(error), span),
+        }
+    }
+
+    fn to_dict(kind: &str, message: &str, entries: Vec<(&str, MalType)>) -> MalType {
+        let mut map = HashMap::with_capacity(entries.len() + 2);
+        map.insert(MalHashable::Keyword("type".to_string()), MalType::Keyword(kind.to_string()));
+        map.insert(MalHashable::Keyword("message".to_string()), MalType::Str(message.to_string()));
+        for (key, value) in entries {
+            map.insert(MalHashable::Keyword(key.to_string()), value);
+        }
+        MalType::dict(map)
+    }
+}
+
+/// How the REPL reports an error that reached the top level.
+impl fmt::Display for MalError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        use MalError::*;
+        let print = |x: &MalType| crate::printer::pr_str(x, true);
+        match self {
+            CanOnlyDefineSymbols(x)                 => write!(fmt, "can only define symbols (not '{}')", print(x)),
+            CannotBindArguments(x)                  => write!(fmt, "cannot bind arguments using '{}', expected a list", print(x)),
+            NotEvaluable(x)                         => write!(fmt, "cannot evaluate '{}'", print(x)),
+            SymbolNotFound(symbol)                  => write!(fmt, "symbol '{}' not found", symbol),
+            TypeCheckFailed {}                      => write!(fmt, "typecheck failed"),
+            ArityError { expected, reached }        => write!(fmt, "arity error, tried to call symbol expecting {} arguments with {}", expected, reached),
+            VariadicArityError { minimum, reached } => write!(fmt, "arity error, tried to call symbol expecting at least {} arguments with {}", minimum, reached),
+            UnbalancedString                        => write!(fmt, "unbalanced string"),
+            UnbalancedList                          => write!(fmt, "unbalanced list"),
+            NotHashable(x)                          => write!(fmt, "{} is not hashable", print(x)),
+            OddMapEntries                           => write!(fmt, "odd number of entries in map"),
+            OddBindings(x)                          => write!(fmt, "odd number of forms in bindings '{}'", print(x)),
+            DuplicateKey(x)                         => write!(fmt, "duplicate key {}", print(x)),
+            LoneDeref                               => write!(fmt, "'@' must be followed by a value"),
+            UnexpectedToken(token)                  => write!(fmt, "unexpected '{}'", token),
+            LoneQuote(token)                        => write!(fmt, "'{}' must be followed by a value", token),
+            IndexOutOfRange(index)                  => write!(fmt, "index {} out of range", index),
+            DivisionByZero                          => write!(fmt, "division by zero"),
+            IntegerOverflow                         => write!(fmt, "integer overflow"),
+            NotRepresentableAsInt(f)                => write!(fmt, "{:?} cannot be represented as an integer", f),
+            IOError(e)                              => write!(fmt, "I/O error: {:?}", e),
+            Exception(value)                        => write!(fmt, "uncaught exception: {}", print(value)),
+            At(e, span)                             => write!(fmt, "{} at {}", e, span),
+        }
+    }
+}
+
+/// Caught errors are exposed to `catch*` as values: thrown values are passed through as-is,
+/// native errors become a map describing the failure, e.g.
+/// `{:type :arity-error :message "arity error, ..." :expected 2 :reached 3}`, plus `:file`, `:line`
+/// and `:col` when the error is located. An undefined symbol is the exception the mal spec mandates
+/// instead, the string `"'abc' not found"`.
+impl From<MalError> for MalType {
+    fn from(error: MalError) -> Self {
+        use MalError::*;
+        let int = |x: usize| MalType::Number(MalNumber::Int(x as i64));
+        let message = error.to_string();
+        let to_dict = |kind, entries| MalError::to_dict(kind, &message, entries);
+        match error {
+            TypeCheckFailed {}                      => to_dict("type-check-failed", vec![]),
+            ArityError { expected, reached }        => to_dict("arity-error", vec![("expected", int(expected)), ("reached", int(reached))]),
+            VariadicArityError { minimum, reached } => to_dict("arity-error", vec![("minimum", int(minimum)), ("reached", int(reached))]),
+            NotEvaluable(x)                         => to_dict("not-evaluable", vec![("value", x)]),
+            CanOnlyDefineSymbols(x)                 => to_dict("can-only-define-symbols", vec![("value", x)]),
+            CannotBindArguments(x)                  => to_dict("cannot-bind-arguments", vec![("value", x)]),
+            SymbolNotFound(x)                       => MalType::Str(format!("'{}' not found", x)),
+            UnbalancedString                        => to_dict("unbalanced-string", vec![]),
+            UnbalancedList                          => to_dict("unbalanced-list", vec![]),
+            OddMapEntries                           => to_dict("odd-map-entries", vec![]),
+            OddBindings(x)                          => to_dict("odd-bindings", vec![("value", x)]),
+            NotHashable(x)                          => to_dict("not-hashable", vec![("value", x)]),
+            DuplicateKey(x)                         => to_dict("duplicate-key", vec![("value", x)]),
+            LoneDeref                               => to_dict("lone-deref", vec![]),
+            UnexpectedToken(x)                      => to_dict("unexpected-token", vec![("token", MalType::Str(x))]),
+            LoneQuote(x)                            => to_dict("lone-quote", vec![("token", MalType::Str(x))]),
+            IndexOutOfRange(x)                      => to_dict("index-out-of-range", vec![("index", MalType::Number(MalNumber::Int(x)))]),
+            DivisionByZero                          => to_dict("division-by-zero", vec![]),
+            IntegerOverflow                         => to_dict("integer-overflow", vec![]),
+            NotRepresentableAsInt(f)                => to_dict("not-representable-as-int", vec![("value", MalType::Number(MalNumber::Float(f)))]),
+            IOError(e)                              => to_dict("io-error", vec![("cause", MalType::Str(e.to_string()))]),
+            Exception(x)                            => x,
+            At(e, span)                             => match (*e).into() {
+                MalType::Dict(mut map, meta) => {
+                    map.extend(span.entries());
+                    MalType::Dict(map, meta)
+                }
+                value                        => value,
+            },
+        }
+    }
 }
 
 pub(crate) type MalResult = Result<MalType, MalError>;
@@ -59,16 +276,19 @@ impl fmt::Debug for MalType {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         use MalType::*;
         match self {
-            Atom(x)     => write!(fmt, "Atom {{ {:?} }}", x),
-            List(x)     => write!(fmt, "List {{ {:?} }}", x),
-            Vector(x)   => write!(fmt, "Vector {{ {:?} }}", x),
-            Dict(x)     => write!(fmt, "Dict {{ {:?} }}", x),
-            Symbol(x)   => write!(fmt, "Symbol {{ {:?} }}", x),
-            Number(x)   => write!(fmt, "{:?}", x),
-            Bool(x)     => write!(fmt, "Bool {{ {:?} }}", x),
-            Str(x)      => write!(fmt, "Str {{ {:?} }}", x),
-            Nil         => write!(fmt, "Nil"),
-            Function(_) => write!(fmt, "Function(...)"),
+            Atom(x)      => write!(fmt, "Atom {{ {:?} }}", x),
+            List(x, _)   => write!(fmt, "List {{ {:?} }}", x),
+            Vector(x, _) => write!(fmt, "Vector {{ {:?} }}", x),
+            Dict(x, _)   => write!(fmt, "Dict {{ {:?} }}", x),
+            Symbol(x)    => write!(fmt, "Symbol {{ {:?} }}", x),
+            Keyword(x)   => write!(fmt, "Keyword {{ {:?} }}", x),
+            Number(x)    => write!(fmt, "{:?}", x),
+            Set(x)       => write!(fmt, "Set {{ {:?} }}", x),
+            Bool(x)      => write!(fmt, "Bool {{ {:?} }}", x),
+            Str(x)       => write!(fmt, "Str {{ {:?} }}", x),
+            Nil          => write!(fmt, "Nil"),
+            Function(..) => write!(fmt, "Function(...)"),
+            Macro(_)     => write!(fmt, "Macro(...)"),
         }
     }
 }
@@ -77,14 +297,19 @@ impl PartialEq for MalType {
     fn eq(&self, other: &Self) -> bool {
         use MalType::*;
         match (self, other) {
-            (Number(a), Number(b)) => a == b,
-            (Bool(a), Bool(b))     => a == b,
-            (Str(a), Str(b))       => a == b,
-            (Vector(a), Vector(b)) => a == b,
-            (List(a), List(b))     => a == b,
-            (Symbol(a), Symbol(b)) => a == b,
-            (Nil, Nil)             => true,
-            _                      => false,
+            (Number(a), Number(b))       => a == b,
+            (Bool(a), Bool(b))           => a == b,
+            (Str(a), Str(b))             => a == b,
+            (Vector(a, _), Vector(b, _)) |
+            (List(a, _), List(b, _))     |
+            (List(a, _), Vector(b, _))   |
+            (Vector(a, _), List(b, _))   => a == b,
+            (Dict(a, _), Dict(b, _))     => a == b,
+            (Set(a), Set(b))             => a == b,
+            (Symbol(a), Symbol(b))       => a == b,
+            (Keyword(a), Keyword(b))     => a == b,
+            (Nil, Nil)                   => true,
+            _                            => false,
         }
     }
 }
@@ -92,17 +317,51 @@ impl PartialEq for MalType {
 #[derive(Debug, Copy, Clone)]
 pub(crate) enum MalNumber {
     Int(i64),
+    Ratio(Rational64), // never a whole number, see `MalNumber::normalize`
     Float(f64),
 }
 
+impl MalNumber {
+    /// Lift both operands to the wider of their two kinds: Int < Ratio < Float.
+    fn promote(self, other: Self) -> (Self, Self) {
+        use MalNumber::*;
+        match (self, other) {
+            (Int(a), Ratio(_))   => (Ratio(a.into()), other),
+            (Ratio(_), Int(b))   => (self, Ratio(b.into())),
+            (Float(_), Int(_))   |
+            (Float(_), Ratio(_)) => (self, Float(other.to_f64())),
+            (Int(_), Float(_))   |
+            (Ratio(_), Float(_)) => (Float(self.to_f64()), other),
+            _                    => (self, other),
+        }
+    }
+
+    /// Ratios that turn out to be whole numbers become Ints again.
+    pub fn normalize(self) -> Self {
+        match self {
+            MalNumber::Ratio(r) if r.is_integer() => MalNumber::Int(r.to_integer()),
+            _                                     => self,
+        }
+    }
+
+    pub fn to_f64(self) -> f64 {
+        use MalNumber::*;
+        match self {
+            Int(i)   => i as f64,
+            Ratio(r) => *r.numer() as f64 / *r.denom() as f64,
+            Float(f) => f,
+        }
+    }
+}
+
 impl PartialOrd for MalNumber {
     fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
         use MalNumber::*;
-        match (self, other) {
-            (Float(a), Float(b)) => a.partial_cmp(b),
-            (Int(a), Int(b))     => a.partial_cmp(b),
-            (Float(a), Int(b))   => a.partial_cmp(&(*b as f64)),
-            (Int(a), Float(b))   => (*a as f64).partial_cmp(b),
+        match self.promote(*other) {
+            (Int(a), Int(b))     => a.partial_cmp(&b),
+            (Ratio(a), Ratio(b)) => a.partial_cmp(&b),
+            (Float(a), Float(b)) => a.partial_cmp(&b),
+            _                    => unreachable!(),
         }
     }
 }
@@ -110,43 +369,63 @@ impl PartialOrd for MalNumber {
 impl PartialEq for MalNumber {
     fn eq(&self, other: &Self) -> bool {
         use MalNumber::*;
-        match (self, other) {
-            (Float(a), Float(b)) => a == b,
+        match self.promote(*other) {
             (Int(a), Int(b))     => a == b,
-            (Float(a), Int(b))   => *a == (*b as f64),
-            (Int(a), Float(b))   => (*a as f64) == *b,
+            (Ratio(a), Ratio(b)) => a == b,
+            (Float(a), Float(b)) => a == b,
+            _                    => false,
         }
     }
 }
 
 macro_rules! impl_number_op {
-    ($trait:tt, $method:tt) => {
+    ($trait:tt, $method:tt, $checked:tt) => {
         impl $trait for MalNumber {
-            type Output = Self;
+            type Output = Result<Self, MalError>;
 
             fn $method(self, other: Self) -> Self::Output {
                 use MalNumber::*;
-                match (self, other) {
-                    (Int(a), Int(b))     => Int(a.$method(b)),
-                    (Float(a), Float(b)) => Float(a.$method(b)),
-                    (Float(a), Int(b))   => Float(a.$method(b as f64)),
-                    (Int(a), Float(b))   => Float((a as f64).$method(b)),
-                }
+                let result = match self.promote(other) {
+                    (Int(a), Int(b))     => a.$checked(b).map(Int),
+                    (Ratio(a), Ratio(b)) => a.$checked(&b).map(|r| Ratio(r).normalize()),
+                    (Float(a), Float(b)) => Some(Float(a.$method(b))),
+                    _                    => unreachable!(),
+                };
+                result.ok_or(MalError::IntegerOverflow)
             }
         }
-    }
+    };
 }
 
-impl_number_op!(Add, add);
-impl_number_op!(Sub, sub);
-impl_number_op!(Mul, mul);
-impl_number_op!(Div, div);
+impl_number_op!(Add, add, checked_add);
+impl_number_op!(Sub, sub, checked_sub);
+impl_number_op!(Mul, mul, checked_mul);
+
+impl Div for MalNumber {
+    type Output = Result<Self, MalError>;
+
+    /// Dividing exact numbers gives an exact result, only floats may divide by zero.
+    fn div(self, other: Self) -> Self::Output {
+        use MalNumber::*;
+        let (a, b) = match self.promote(other) {
+            (Int(a), Int(b))     => (Rational64::from(a), Rational64::from(b)),
+            (Ratio(a), Ratio(b)) => (a, b),
+            (Float(a), Float(b)) => return Ok(Float(a / b)),
+            _                    => unreachable!(),
+        };
+        if *b.numer() == 0 {
+            return Err(MalError::DivisionByZero);
+        }
+        a.checked_div(&b).map(|r| Ratio(r).normalize()).ok_or(MalError::IntegerOverflow)
+    }
+}
 
 impl ToString for MalNumber {
     fn to_string(&self) -> String {
         use MalNumber::*;
         match self {
             Int(i)   => i.to_string(),
+            Ratio(r) => r.to_string(),
             Float(f) => f.to_string(),
         }
     }
@@ -160,18 +439,20 @@ pub(crate) enum MalHashable {
     Nil,
     Str(String),
     Symbol(String),
+    Keyword(String),
 }
 
 impl MalHashable {
     pub fn try_from(value: MalType) -> Result<MalHashable, MalType> {
         use MalHashable::*;
         match value {
-            MalType::List(elements)            => {
+            MalType::List(elements, meta)      => {
                 Self::try_from_list(elements.clone())
                     .map(List)
-                    .ok_or(MalType::List(elements))
+                    .ok_or(MalType::List(elements, meta))
             }
             MalType::Symbol(x)                 => Ok(Symbol(x)),
+            MalType::Keyword(x)                => Ok(Keyword(x)),
             MalType::Number(MalNumber::Int(x)) => Ok(Int(x)),
             MalType::Bool(x)                   => Ok(Bool(x)),
             MalType::Nil                       => Ok(Nil),
@@ -192,12 +473,13 @@ impl Into<MalType> for MalHashable {
     fn into(self) -> MalType {
         use MalHashable::*;
         match self {
-            List(x)   => MalType::List(x.into_iter().map(Self::into).collect()),
-            Symbol(x) => MalType::Symbol(x),
-            Int(x)    => MalType::Number(MalNumber::Int(x)),
-            Bool(x)   => MalType::Bool(x),
-            Nil       => MalType::Nil,
-            Str(x)    => MalType::Str(x),
+            List(x)    => MalType::list(x.into_iter().map(Self::into).collect()),
+            Symbol(x)  => MalType::Symbol(x),
+            Keyword(x) => MalType::Keyword(x),
+            Int(x)     => MalType::Number(MalNumber::Int(x)),
+            Bool(x)    => MalType::Bool(x),
+            Nil        => MalType::Nil,
+            Str(x)     => MalType::Str(x),
         }
     }
 }
@@ -209,16 +491,204 @@ impl Hash for MalHashable {
     fn hash<H: Hasher>(&self, state: &mut H) {
         use MalHashable::*;
         match self {
-            List(x)   => Hash::hash(&(1,  x), state),
-            Symbol(x) => Hash::hash(&(3,  x), state),
-            Int(x)    => Hash::hash(&(5,  x), state),
-            Bool(x)   => Hash::hash(&(7,  x), state),
-            Nil       => Hash::hash(&(9, ()), state),
-            Str(x)    => Hash::hash(&(13, x), state),
+            List(x)    => Hash::hash(&(1,  x), state),
+            Symbol(x)  => Hash::hash(&(3,  x), state),
+            Keyword(x) => Hash::hash(&(11, x), state),
+            Int(x)     => Hash::hash(&(5,  x), state),
+            Bool(x)    => Hash::hash(&(7,  x), state),
+            Nil        => Hash::hash(&(9, ()), state),
+            Str(x)     => Hash::hash(&(13, x), state),
         }
     }
 }
 
+// Mal data goes to and from any serde format. `MalType`'s own impls write what a program on the
+// other end expects: keywords and symbols as their names, ratios as floats, lists and sets as
+// sequences and maps as maps. Reading that back gives strings, floats, vectors and maps.
+//
+// `Tagged(value)` round-trips instead. Values a format has no type for are written as a map with
+// a single "mal/..." key, and read back from one: `:k` as `{"mal/keyword": "k"}`, `1/3` as
+// `{"mal/ratio": [1, 3]}`, and a map with keys other than strings (or one that would look like a
+// tagged value) as `{"mal/map": [[key, value], ...]}`. Nothing is guessed from the content of a
+// string. Functions, macros and atoms have no serialized form either way.
+
+pub(crate) struct Tagged<T>(pub T);
+
+const TAG_KEYWORD: &str = "mal/keyword";
+const TAG_RATIO: &str = "mal/ratio";
+const TAG_MAP: &str = "mal/map";
+
+fn serialize_tagged<S: Serializer, T: Serialize>(serializer: S, tag: &str, value: &T) -> Result<S::Ok, S::Error> {
+    let mut map = serializer.serialize_map(Some(1))?;
+    map.serialize_entry(tag, value)?;
+    map.end()
+}
+
+/// A map written as a plain map: string keys, and not mistaken for a tagged value.
+fn is_plain_map(map: &HashMap<MalHashable, MalType>) -> bool {
+    let is_tag = |s: &str| s == TAG_KEYWORD || s == TAG_RATIO || s == TAG_MAP;
+    map.keys().all(|key| matches!(key, MalHashable::Str(_)))
+        && !(map.len() == 1 && map.keys().any(|key| matches!(key, MalHashable::Str(s) if is_tag(s))))
+}
+
+/// A value and whether to write it tagged, shared by both impls and passed down to elements.
+struct Form<T>(T, bool);
+
+impl Serialize for Form<&MalType> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use MalType::*;
+        let Form(value, tagged) = *self;
+        let form = |x| Form(x, tagged);
+        match value {
+            Nil                                     => serializer.serialize_unit(),
+            Bool(b)                                 => serializer.serialize_bool(*b),
+            Number(MalNumber::Int(i))               => serializer.serialize_i64(*i),
+            Number(MalNumber::Ratio(r)) if tagged   => serialize_tagged(serializer, TAG_RATIO, &(r.numer(), r.denom())),
+            Number(n)                               => serializer.serialize_f64(n.to_f64()),
+            Str(s) | Symbol(s)                      => serializer.serialize_str(s),
+            Keyword(k) if tagged                    => serialize_tagged(serializer, TAG_KEYWORD, k),
+            Keyword(k)                              => serializer.serialize_str(k),
+            List(xs, _) | Vector(xs, _)             => serializer.collect_seq(xs.iter().map(form)),
+            Set(xs)                                 => serializer.collect_seq(xs.iter().map(|x| Form(x, tagged))),
+            Dict(map, _) if !tagged || is_plain_map(map) => {
+                serializer.collect_map(map.iter().map(|(k, v)| (Form(k, tagged), form(v))))
+            },
+            Dict(map, _)                            => {
+                let entries = map.iter().map(|(k, v)| (Form(k, tagged), form(v))).collect::<Vec<_>>();
+                serialize_tagged(serializer, TAG_MAP, &entries)
+            },
+            Atom(_)                                 => Err(S::Error::custom("cannot serialize an atom")),
+            Function(..)                            => Err(S::Error::custom("cannot serialize a function")),
+            Macro(_)                                => Err(S::Error::custom("cannot serialize a macro")),
+        }
+    }
+}
+
+impl Serialize for Form<&MalHashable> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let value: MalType = self.0.clone().into();
+        Form(&value, self.1).serialize(serializer)
+    }
+}
+
+impl Serialize for MalType {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        Form(self, false).serialize(serializer)
+    }
+}
+
+impl Serialize for Tagged<&MalType> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        Form(self.0, true).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for MalType {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(MalTypeVisitor { tagged: false })
+    }
+}
+
+impl<'de> Deserialize<'de> for Tagged<MalType> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(MalTypeVisitor { tagged: true }).map(Tagged)
+    }
+}
+
+#[derive(Clone, Copy)]
+struct MalTypeVisitor {
+    tagged: bool,
+}
+
+impl<'de> DeserializeSeed<'de> for MalTypeVisitor {
+    type Value = MalType;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<MalType, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for MalTypeVisitor {
+    type Value = MalType;
+
+    fn expecting(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.write_str("a Mal value")
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<MalType, E> { Ok(MalType::Nil) }
+    fn visit_none<E: de::Error>(self) -> Result<MalType, E> { Ok(MalType::Nil) }
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<MalType, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+    fn visit_bool<E: de::Error>(self, b: bool) -> Result<MalType, E> { Ok(MalType::Bool(b)) }
+    fn visit_i64<E: de::Error>(self, i: i64) -> Result<MalType, E> { Ok(MalType::Number(MalNumber::Int(i))) }
+    fn visit_f64<E: de::Error>(self, f: f64) -> Result<MalType, E> { Ok(MalType::Number(MalNumber::Float(f))) }
+
+    fn visit_u64<E: de::Error>(self, u: u64) -> Result<MalType, E> {
+        if u > i64::max_value() as u64 {
+            return Err(E::custom(format!("{} is too big for an integer", u)));
+        }
+        Ok(MalType::Number(MalNumber::Int(u as i64)))
+    }
+
+    fn visit_str<E: de::Error>(self, s: &str) -> Result<MalType, E> { Ok(MalType::Str(s.to_string())) }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<MalType, A::Error> {
+        let mut elements = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(x) = seq.next_element_seed(self)? {
+            elements.push(x);
+        }
+        Ok(MalType::vector(elements))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<MalType, A::Error> {
+        let mut entries = Vec::with_capacity(map.size_hint().unwrap_or(0));
+        while let Some(entry) = map.next_entry_seed(self, self)? {
+            entries.push(entry);
+        }
+        if let (true, [(MalType::Str(tag), value)]) = (self.tagged, &entries[..]) {
+            if let Some(value) = untag(tag, value).map_err(de::Error::custom)? {
+                return Ok(value);
+            }
+        }
+        dict_from_entries(entries).map_err(de::Error::custom)
+    }
+}
+
+fn dict_from_entries(entries: Vec<(MalType, MalType)>) -> Result<MalType, String> {
+    let mut dict = HashMap::with_capacity(entries.len());
+    for (key, value) in entries {
+        let key = MalHashable::try_from(key).map_err(|key| format!("{:?} cannot be a map key", key))?;
+        dict.insert(key, value);
+    }
+    Ok(MalType::dict(dict))
+}
+
+/// The value a `{"mal/...": value}` map stands for, or `None` if it is just a map.
+fn untag(tag: &str, value: &MalType) -> Result<Option<MalType>, String> {
+    use MalNumber::Int;
+    match (tag, value) {
+        (TAG_KEYWORD, MalType::Str(k))                => Ok(Some(MalType::Keyword(k.clone()))),
+        (TAG_RATIO, MalType::Vector(nd, _))           => match &nd[..] {
+            [MalType::Number(Int(n)), MalType::Number(Int(d))] if *d > 0 => {
+                Ok(Some(MalType::Number(MalNumber::Ratio(Rational64::new(*n, *d)).normalize())))
+            },
+            _                                         => Ok(None),
+        },
+        (TAG_MAP, MalType::Vector(entries, _))        => {
+            let mut pairs = Vec::with_capacity(entries.len());
+            for entry in entries {
+                match entry {
+                    MalType::Vector(kv, _) if kv.len() == 2 => pairs.push((kv[0].clone(), kv[1].clone())),
+                    _                                       => return Ok(None),
+                }
+            }
+            dict_from_entries(pairs).map(Some)
+        },
+        _                                             => Ok(None),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -234,4 +704,81 @@ mod tests {
     fn it_works() {
         assert!(true);
     }
+
+    #[test]
+    fn test_ratio_arithmetic() {
+        use MalNumber::*;
+        let ratio = |n, d| Ratio(Rational64::new(n, d));
+        assert_eq!((Int(1) / Int(3)).unwrap().to_string(), "1/3");
+        assert_eq!((Int(6) / Int(4)).unwrap().to_string(), "3/2");
+        assert!(if let Ok(Int(2)) = Int(6) / Int(3) { true } else { false });
+        assert!(if let Ok(Int(1)) = ratio(1, 3) + ratio(2, 3) { true } else { false });
+        assert_eq!((ratio(1, 2) + Int(1)).unwrap().to_string(), "3/2");
+        assert_eq!((ratio(1, 2) * Float(0.5)).unwrap().to_string(), "0.25");
+        assert!(ratio(1, 3) < ratio(1, 2));
+        assert!(ratio(1, 2) == Float(0.5));
+        assert!(ratio(4, 2) == Int(2));
+    }
+
+    #[test]
+    fn test_checked_arithmetic() {
+        use MalNumber::*;
+        let ratio = |n, d| Ratio(Rational64::new(n, d));
+        assert!(if let Err(MalError::DivisionByZero) = Int(1) / Int(0) { true } else { false });
+        assert!(if let Err(MalError::DivisionByZero) = ratio(1, 2) / Int(0) { true } else { false });
+        assert!(if let Ok(Float(f)) = Float(1.0) / Int(0) { f.is_infinite() } else { false });
+        assert!(if let Err(MalError::IntegerOverflow) = Int(i64::MAX) + Int(1) { true } else { false });
+        assert!(if let Err(MalError::IntegerOverflow) = Int(i64::MIN) / Int(-1) { true } else { false });
+        assert!(if let Err(MalError::IntegerOverflow) = ratio(1, i64::MAX) * ratio(1, 2) { true } else { false });
+    }
+
+    #[test]
+    fn test_serde_round_trip() {
+        let keyword = |k: &str| MalType::Keyword(k.to_string());
+        let mut dict = HashMap::new();
+        dict.insert(MalHashable::Keyword("a".to_string()), MalType::list(vec![MalType::Number(MalNumber::Int(1)), MalType::Nil]));
+        dict.insert(MalHashable::Str("b".to_string()), keyword("c"));
+        let value = MalType::vector(vec![MalType::dict(dict), MalType::Bool(true), MalType::Str("s".to_string())]);
+
+        let json = serde_json::to_string(&Tagged(&value)).unwrap();
+        assert!(json.contains(r#"["b",{"mal/keyword":"c"}]"#));
+        assert_eq!(serde_json::from_str::<Tagged<MalType>>(&json).unwrap().0, value);
+
+        assert!(serde_json::to_string(&MalType::atom(MalType::Nil)).is_err());
+        assert!(serde_json::to_string(&Tagged(&MalType::atom(MalType::Nil))).is_err());
+    }
+
+    #[test]
+    fn test_serde_plain() {
+        let mut dict = HashMap::new();
+        dict.insert(MalHashable::Keyword("a".to_string()), MalType::Keyword("b".to_string()));
+        let ratio = MalType::Number(MalNumber::Ratio(Rational64::new(1, 4)));
+        let value = MalType::list(vec![MalType::dict(dict), ratio, MalType::Symbol("s".to_string())]);
+
+        assert_eq!(serde_json::to_string(&value).unwrap(), r#"[{"a":"b"},0.25,"s"]"#);
+        assert!(matches!(serde_json::from_str::<MalType>(r#"{"mal/keyword":"k"}"#).unwrap(), MalType::Dict(..)));
+    }
+
+    #[test]
+    fn test_serde_tags() {
+        let to_json = |value: &MalType| serde_json::to_string(&Tagged(value)).unwrap();
+        let from_json = |json: &str| serde_json::from_str::<Tagged<MalType>>(json).unwrap().0;
+        let ratio = MalType::Number(MalNumber::Ratio(Rational64::new(-2, 6)));
+        let mut plain = HashMap::new();
+        plain.insert(MalHashable::Str("a".to_string()), MalType::Nil);
+        let mut lookalike = HashMap::new();
+        lookalike.insert(MalHashable::Str("mal/keyword".to_string()), MalType::Str("k".to_string()));
+
+        assert_eq!(to_json(&MalType::Keyword("k".to_string())), r#"{"mal/keyword":"k"}"#);
+        assert_eq!(to_json(&MalType::Str(":k".to_string())), r#"":k""#);
+        assert_eq!(to_json(&ratio), r#"{"mal/ratio":[-1,3]}"#);
+        assert_eq!(to_json(&MalType::dict(plain)), r#"{"a":null}"#);
+        assert_eq!(to_json(&MalType::dict(lookalike.clone())), r#"{"mal/map":[["mal/keyword","k"]]}"#);
+
+        assert_eq!(from_json(r#"":k""#), MalType::Str(":k".to_string()));
+        assert_eq!(from_json(r#"{"mal/ratio":[-1,3]}"#), ratio);
+        assert_eq!(from_json(r#"{"mal/map":[["mal/keyword","k"]]}"#), MalType::dict(lookalike));
+        assert!(matches!(from_json(r#"{"mal/keyword":1}"#), MalType::Dict(..)));
+        assert!(matches!(from_json(r#"{"mal/ratio":[1,0]}"#), MalType::Dict(..)));
+    }
 }
@@ -0,0 +1,190 @@
+use std::collections::HashMap;
+use std::hash::{self,BuildHasher};
+use std::rc::Rc;
+
+use num_bigint::BigInt;
+use num_traits::ToPrimitive;
+
+use types::{MalVal,MalKey,MalMap,MalRet,MalErr,bignum};
+use types::MalVal::{Nil,Bool,Int,Bignum,Float,Str,Char,List,Vector,Hash,LazySeq};
+use types::MalErr::ErrString;
+
+// Rust values that can be handed to Mal code. Like TryFrom, the conversion
+// can fail: a map whose keys Mal can't hash, say.
+pub trait IntoMal {
+  fn into_mal(self) -> MalRet;
+}
+
+// Rust values that can be taken back out of a Mal value. A value of the
+// wrong type is an error, not a panic.
+pub trait FromMal: Sized {
+  fn from_mal(v: &MalVal) -> Result<Self,MalErr>;
+}
+
+fn mismatch<T>(expected: &str, v: &MalVal) -> Result<T,MalErr> {
+  Err(ErrString(format!("expected {}, got {}", expected, v.pr_str(true))))
+}
+
+impl IntoMal for MalVal {
+  fn into_mal(self) -> MalRet { Ok(self) }
+}
+
+impl FromMal for MalVal {
+  fn from_mal(v: &MalVal) -> Result<MalVal,MalErr> { Ok(v.clone()) }
+}
+
+impl IntoMal for () {
+  fn into_mal(self) -> MalRet { Ok(Nil) }
+}
+
+impl IntoMal for bool {
+  fn into_mal(self) -> MalRet { Ok(Bool(self)) }
+}
+
+impl FromMal for bool {
+  fn from_mal(v: &MalVal) -> Result<bool,MalErr> {
+    match *v {
+      Bool(b) => Ok(b),
+      _ => mismatch("a boolean", v),
+    }
+  }
+}
+
+// integers too big for an Int become a Bignum, and come back out of one if
+// they fit the Rust type
+macro_rules! int_conversions {
+  ($($t:ident $to:ident),*) => {$(
+    impl IntoMal for $t {
+      fn into_mal(self) -> MalRet {
+        Ok(match self.to_i64() {
+          Some(i) => Int(i),
+          None    => bignum(BigInt::from(self)),
+        })
+      }
+    }
+
+    impl FromMal for $t {
+      fn from_mal(v: &MalVal) -> Result<$t,MalErr> {
+        let n = match *v {
+          Int(i)        => i.$to(),
+          Bignum(ref b) => b.$to(),
+          _ => return mismatch("an integer", v),
+        };
+        n.ok_or_else(|| ErrString(format!("{} does not fit in {}", v.pr_str(true), stringify!($t))))
+      }
+    }
+  )*};
+}
+
+int_conversions!(i8 to_i8, i16 to_i16, i32 to_i32, i64 to_i64, isize to_isize,
+                 u8 to_u8, u16 to_u16, u32 to_u32, u64 to_u64, usize to_usize);
+
+impl IntoMal for f64 {
+  fn into_mal(self) -> MalRet { Ok(Float(self)) }
+}
+
+// any number, as mixed arithmetic would promote it
+impl FromMal for f64 {
+  fn from_mal(v: &MalVal) -> Result<f64,MalErr> {
+    v.to_f64().or_else(|_| mismatch("a number", v))
+  }
+}
+
+impl IntoMal for String {
+  fn into_mal(self) -> MalRet { Ok(Str(self)) }
+}
+
+impl IntoMal for &str {
+  fn into_mal(self) -> MalRet { Ok(Str(self.to_string())) }
+}
+
+impl FromMal for String {
+  fn from_mal(v: &MalVal) -> Result<String,MalErr> {
+    match *v {
+      Str(ref s) => Ok(s.clone()),
+      _ => mismatch("a string", v),
+    }
+  }
+}
+
+impl IntoMal for char {
+  fn into_mal(self) -> MalRet { Ok(Char(self)) }
+}
+
+impl FromMal for char {
+  fn from_mal(v: &MalVal) -> Result<char,MalErr> {
+    match *v {
+      Char(c) => Ok(c),
+      _ => mismatch("a character", v),
+    }
+  }
+}
+
+// None is nil
+impl<T: IntoMal> IntoMal for Option<T> {
+  fn into_mal(self) -> MalRet {
+    match self {
+      Some(x) => x.into_mal(),
+      None    => Ok(Nil),
+    }
+  }
+}
+
+impl<T: FromMal> FromMal for Option<T> {
+  fn from_mal(v: &MalVal) -> Result<Option<T>,MalErr> {
+    match *v {
+      Nil => Ok(None),
+      _   => T::from_mal(v).map(Some),
+    }
+  }
+}
+
+impl<T: IntoMal> IntoMal for Vec<T> {
+  fn into_mal(self) -> MalRet {
+    Ok(vector!(self.into_iter().map(IntoMal::into_mal).collect::<Result<_,_>>()?))
+  }
+}
+
+// from a list, vector or lazy seq (realized to its end), or nil for empty
+impl<T: FromMal> FromMal for Vec<T> {
+  fn from_mal(v: &MalVal) -> Result<Vec<T>,MalErr> {
+    match *v {
+      List(..) | Vector(..) | LazySeq(..) | Nil => {
+        v.seq_vals()?.iter().map(T::from_mal).collect()
+      },
+      _ => mismatch("a list or vector", v),
+    }
+  }
+}
+
+// An error if a key converts to something Mal can't use as a key, a
+// function say. Keys of the types above are all fine.
+impl<K, V, S> IntoMal for HashMap<K, V, S>
+  where K: IntoMal + Eq + hash::Hash, V: IntoMal, S: BuildHasher {
+  fn into_mal(self) -> MalRet {
+    let mut hm = MalMap::default();
+    for (k, v) in self {
+      hm.insert(MalKey::new(&k.into_mal()?)?, v.into_mal()?);
+    }
+    Ok(Hash(Rc::new(hm), Rc::new(Nil)))
+  }
+}
+
+impl<K, V, S> FromMal for HashMap<K, V, S>
+  where K: FromMal + Eq + hash::Hash, V: FromMal, S: BuildHasher + Default {
+  fn from_mal(v: &MalVal) -> Result<HashMap<K, V, S>,MalErr> {
+    match *v {
+      Hash(ref hm,_) => {
+        let mut map = HashMap::with_hasher(S::default());
+        for (k, v) in hm.iter() {
+          map.insert(K::from_mal(k.val())?, V::from_mal(v)?);
+        }
+        Ok(map)
+      },
+      Nil => Ok(HashMap::with_hasher(S::default())),
+      _ => mismatch("a hash map", v),
+    }
+  }
+}
+
+// vim: ts=2:sw=2:expandtab
@@ -1,25 +1,61 @@
 use std::rc::Rc;
+use std::cell::RefCell;
+use std::cmp::Ordering;
 use std::fs::File;
-use std::io::Read;
-use std::sync::Mutex;
+use std::io::{Read,Write};
+use std::iter::Peekable;
+use std::str::Chars;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 extern crate rustyline;
 use rustyline::error::ReadlineError;
 use rustyline::Editor;
 
-use types::{MalVal,MalArgs,MalRet,error,func,hash_map,_assoc,_dissoc,atom};
-use types::MalVal::{Nil,Bool,Int,Str,Sym,List,Vector,Hash,Func,MalFunc,Atom};
-use types::MalErr::{ErrMalVal};
-use reader::read_str;
+use num_bigint::{BigInt,Sign};
+use num_rational::BigRational;
+use num_traits::{Zero,FromPrimitive};
+use fnv::{FnvHashMap,FnvHashSet};
+use regex::Captures;
+use serde_json;
+
+use types::{MalVal,MalKey,MalRegex,MalSeq,MalMap,MalSet,MalArgs,MalRet,MalErr,Arity,error,native_fn,hash_map,_assoc,_dissoc,hash_set,_conj_set,atom,bignum,ratio,lazy_seq,lazy_cons,re_pattern,keyword};
+use types::MalVal::{Nil,Bool,Int,Bignum,Ratio,Float,Str,Char,Bytes,Keyword,Sym,Regex,List,Vector,Hash,Set,LazySeq,Func,MalFunc,Atom};
+use types::MalErr::{ErrString,ErrMalVal};
+use reader::{read_str,read_str_from};
 use printer::pr_seq;
 
-macro_rules! fn_t_int_int {
+// $fn is expanded once per operand type: anything with a float compares as
+// floats, then anything with a ratio as ratios, bignums against ints as bignums
+macro_rules! fn_t_num_num {
   ($ret:ident, $fn:expr) => {{
     |a:MalArgs| {
       match (a[0].clone(), a[1].clone()) {
         (Int(a0), Int(a1)) => Ok($ret($fn(a0, a1))),
-        _ => error("expecting (int,int) args"),
+        (Float(a0), a1)    => Ok($ret($fn(a0, a1.to_f64()?))),
+        (a0, Float(a1))    => Ok($ret($fn(a0.to_f64()?, a1))),
+        (Ratio(a0), a1)    => Ok($ret($fn(a0, a1.to_ratio()?))),
+        (a0, Ratio(a1))    => Ok($ret($fn(a0.to_ratio()?, a1))),
+        (a0, a1)           => Ok($ret($fn(a0.to_bigint()?, a1.to_bigint()?))),
+      }
+    }
+  }};
+}
+
+// like fn_t_num_num, but two ints go through $checked and overflow into a
+// Bignum instead of wrapping
+macro_rules! fn_arith {
+  ($checked:ident, $fn:expr) => {{
+    |a:MalArgs| {
+      match (a[0].clone(), a[1].clone()) {
+        (Int(a0), Int(a1)) => Ok(match a0.$checked(a1) {
+          Some(i) => Int(i),
+          None    => bignum($fn(BigInt::from(a0), BigInt::from(a1))),
+        }),
+        (Float(a0), a1)    => Ok(Float($fn(a0, a1.to_f64()?))),
+        (a0, Float(a1))    => Ok(Float($fn(a0.to_f64()?, a1))),
+        (Ratio(a0), a1)    => Ok(ratio($fn(a0, a1.to_ratio()?))),
+        (a0, Ratio(a1))    => Ok(ratio($fn(a0.to_ratio()?, a1))),
+        (a0, a1)           => Ok(bignum($fn(a0.to_bigint()?, a1.to_bigint()?))),
       }
     }
   }};
@@ -55,16 +91,10 @@ fn symbol(a: MalArgs) -> MalRet {
   }
 }
 
-fn readline(a: MalArgs) -> MalRet {
-  lazy_static! {
-    static ref RL: Mutex<Editor<()>> = Mutex::new(Editor::<()>::new());
-  }
-  //let mut rl = Editor::<()>::new();
-
+fn readline(rl: &RefCell<Editor<()>>, a: MalArgs) -> MalRet {
   match a[0] {
     Str(ref p) => {
-      //match rl.readline(p) {
-      match RL.lock().unwrap().readline(p) {
+      match rl.borrow_mut().readline(p) {
         Ok(line)                => Ok(Str(line)),
         Err(ReadlineError::Eof) => Ok(Nil),
         Err(e)                  => error(&format!("{:?}", e))
@@ -74,6 +104,91 @@ fn readline(a: MalArgs) -> MalRet {
   }
 }
 
+fn read_string(a: MalArgs) -> MalRet {
+  match (a.get(0), a.get(1)) {
+    (Some(Str(s)), None)            => read_str(s.to_string()),
+    (Some(Str(s)), Some(Str(name))) => read_str_from(s.to_string(), name),
+    _ => error("read-string expects a string and an optional origin name"),
+  }
+}
+
+// Mal data as JSON and back, through MalVal's serde impls
+fn to_json(a: MalArgs) -> MalRet {
+  match serde_json::to_string(&a[0]) {
+    Ok(s)  => Ok(Str(s)),
+    Err(e) => error(&format!("to-json: {}", e)),
+  }
+}
+
+fn from_json(a: MalArgs) -> MalRet {
+  let s = str_arg(&a[0], "from-json")?;
+  serde_json::from_str(s).map_err(|e| ErrString(format!("from-json: {}", e)))
+}
+
+// json/*: plain JSON through MalVal's serde impls, as with to-json. Keywords
+// are written as their names and ratios as floats. Objects read as hash maps,
+// arrays as vectors and null as nil, and integers keep all their digits.
+// Options come in a trailing map: {:keywordize? true} reads object keys as
+// keywords, {:pretty? true} indents the output.
+
+fn json_opt(opts: Option<&MalVal>, name: &str, caller: &str) -> Result<bool,MalErr> {
+  match opts {
+    None | Some(Nil) => Ok(false),
+    Some(Hash(ref hm,_)) => {
+      Ok(hm.get(&MalKey::new(&keyword(name))?).map_or(false, truthy))
+    },
+    Some(v) => Err(ErrString(format!("{}: options must be a map, got {}", caller, v.pr_str(true)))),
+  }
+}
+
+// string keys as keywords, all the way down
+fn keywordize(v: &MalVal) -> MalRet {
+  match *v {
+    Vector(ref xs,_) => Ok(vector!(xs.iter().map(keywordize).collect::<Result<_,_>>()?)),
+    Hash(ref hm,_) => {
+      let mut new_hm = MalMap::default();
+      for (k, v) in hm.iter() {
+        let k = match *k.val() {
+          Str(ref s) => MalKey::new(&keyword(s))?,
+          _ => k.clone(),
+        };
+        new_hm.insert(k, keywordize(v)?);
+      }
+      Ok(Hash(Rc::new(new_hm),Rc::new(Nil)))
+    },
+    _ => Ok(v.clone()),
+  }
+}
+
+// serde_json's parse errors already end in "at line N column M"
+fn json_read(s: &str, opts: Option<&MalVal>, caller: &str) -> MalRet {
+  let keywordize_keys = json_opt(opts, "keywordize?", caller)?;
+  let v: MalVal = serde_json::from_str(s).map_err(|e| ErrString(format!("{}: {}", caller, e)))?;
+  if keywordize_keys { keywordize(&v) } else { Ok(v) }
+}
+
+fn json_read_str(a: MalArgs) -> MalRet {
+  json_read(str_arg(&a[0], "json/read-str")?, a.get(1), "json/read-str")
+}
+
+fn json_read_file(a: MalArgs) -> MalRet {
+  let f = str_arg(&a[0], "json/read-file")?;
+  let mut s = String::new();
+  match File::open(f).and_then(|mut f| f.read_to_string(&mut s)) {
+    Ok(_) => json_read(&s, a.get(1), &format!("json/read-file {}", f)),
+    Err(e) => error(&format!("json/read-file {}: {}", f, e)),
+  }
+}
+
+fn json_write_str(a: MalArgs) -> MalRet {
+  let s = if json_opt(a.get(1), "pretty?", "json/write-str")? {
+    serde_json::to_string_pretty(&a[0])
+  } else {
+    serde_json::to_string(&a[0])
+  };
+  s.map(Str).or_else(|e| error(&format!("json/write-str: {}", e)))
+}
+
 fn slurp(f: String) -> MalRet {
   let mut s = String::new();
   match File::open(f).and_then(|mut f| f.read_to_string(&mut s)) {
@@ -82,6 +197,377 @@ fn slurp(f: String) -> MalRet {
   }
 }
 
+fn slurp_bytes(f: String) -> MalRet {
+  let mut b = vec![];
+  match File::open(f).and_then(|mut f| f.read_to_end(&mut b)) {
+    Ok(_) => Ok(Bytes(Rc::new(b))),
+    Err(e) => error(&e.to_string()),
+  }
+}
+
+fn spit_bytes(a: MalArgs) -> MalRet {
+  let f = str_arg(&a[0], "spit-bytes")?;
+  let b = match a[1] {
+    Bytes(ref b) => b,
+    _ => return error("spit-bytes: expected bytes"),
+  };
+  match File::create(f).and_then(|mut f| f.write_all(b)) {
+    Ok(_) => Ok(Nil),
+    Err(e) => error(&e.to_string()),
+  }
+}
+
+// what str, pr-str, prn and println have in common: lazy seqs are realized
+// first, so an error doing that is thrown from here
+fn pr_args(a: &[MalVal], print_readably: bool, join: &str) -> Result<String,MalErr> {
+  for x in a {
+    x.realize()?;
+  }
+  Ok(pr_seq(a, print_readably, "", "", join))
+}
+
+// strings: indexes and lengths count characters, not bytes
+
+fn str_arg<'a>(v: &'a MalVal, name: &str) -> Result<&'a str,MalErr> {
+  match *v {
+    Str(ref s) => Ok(s),
+    _ => Err(ErrString(format!("{} expects a string, got {}", name, v.pr_str(true)))),
+  }
+}
+
+// the byte offset of character i, or None past the end
+fn char_offset(s: &str, i: i64) -> Option<usize> {
+  if i < 0 {
+    return None;
+  }
+  s.char_indices().map(|(b, _)| b).chain(Some(s.len())).nth(i as usize)
+}
+
+// (subs s start) or (subs s start end)
+fn subs(a: MalArgs) -> MalRet {
+  let s = str_arg(&a[0], "subs")?;
+  let start = char_offset(s, int_arg(&a[1], "subs")?);
+  let end = match a.get(2) {
+    Some(end) => char_offset(s, int_arg(end, "subs")?),
+    None      => Some(s.len()),
+  };
+  match (start, end) {
+    (Some(start), Some(end)) if start <= end => Ok(Str(s[start..end].to_string())),
+    _ => error("subs: index out of range"),
+  }
+}
+
+// (index-of s sub) or (index-of s sub from): where sub first occurs, or nil
+fn index_of(a: MalArgs) -> MalRet {
+  let s = str_arg(&a[0], "index-of")?;
+  let sub = str_arg(&a[1], "index-of")?;
+  let from = match a.get(2) {
+    Some(from) => int_arg(from, "index-of")?.max(0),
+    None       => 0,
+  };
+  let start = match char_offset(s, from) {
+    Some(start) => start,
+    None        => return Ok(Nil),
+  };
+  Ok(match s[start..].find(sub) {
+    Some(i) => Int(s[..start + i].chars().count() as i64),
+    None    => Nil,
+  })
+}
+
+// Splits on every occurrence of sep, dropping empty strings at the end as
+// Clojure does. An empty sep splits into characters.
+fn split(a: MalArgs) -> MalRet {
+  let s = str_arg(&a[0], "str/split")?;
+  let mut parts: Vec<&str> = if let Regex(ref re) = a[1] {
+    re.split(s).collect()
+  } else if str_arg(&a[1], "str/split")?.is_empty() {
+    s.char_indices().map(|(i, c)| &s[i..i + c.len_utf8()]).collect()
+  } else {
+    s.split(str_arg(&a[1], "str/split")?).collect()
+  };
+  while parts.len() > 1 && parts[parts.len() - 1].is_empty() {
+    parts.pop();
+  }
+  Ok(vector!(parts.into_iter().map(|p| Str(p.to_string())).collect()))
+}
+
+// (str/join coll) or (str/join sep coll), printing elements as str does
+fn join(a: MalArgs) -> MalRet {
+  let (sep, coll) = match a.len() {
+    1 => ("", &a[0]),
+    2 => (str_arg(&a[0], "str/join")?, &a[1]),
+    _ => return error("str/join takes an optional separator and a seq"),
+  };
+  Ok(Str(pr_args(&coll.seq_vals()?, false, sep)?))
+}
+
+// a literal match is replaced literally; a regex match may be replaced
+// by a string with $1/${name} references or by a function of the match
+fn replace(a: MalArgs) -> MalRet {
+  let s = str_arg(&a[0], "str/replace")?;
+  let re = match a[1] {
+    Regex(ref re) => re,
+    _ => {
+      let from = str_arg(&a[1], "str/replace")?;
+      let to = str_arg(&a[2], "str/replace")?;
+      if from.is_empty() {
+        return error("str/replace: cannot replace an empty string");
+      }
+      return Ok(Str(s.replace(from, to)));
+    }
+  };
+  match a[2] {
+    Str(ref to) => Ok(Str(re.replace_all(s, to.as_str()).into_owned())),
+    Func(..) | MalFunc{..} => {
+      let mut err = None;
+      let res = re.replace_all(s, |caps: &Captures| {
+        match a[2].apply(vec![match_val(caps)]).and_then(|v| pr_args(&[v], false, "")) {
+          Ok(s) => s,
+          Err(e) => { err.get_or_insert(e); String::new() }
+        }
+      }).into_owned();
+      match err {
+        Some(e) => Err(e),
+        None    => Ok(Str(res)),
+      }
+    },
+    _ => error("str/replace: replacement must be a string or a function"),
+  }
+}
+
+// a character from its code or from a one-character string
+fn char_of(a: MalArgs) -> MalRet {
+  match a[0] {
+    Char(_) => return Ok(a[0].clone()),
+    Str(ref s) if s.chars().count() == 1 => return Ok(Char(s.chars().next().unwrap())),
+    _ => (),
+  }
+  let c = int_arg(&a[0], "char")?;
+  if c < 0 || c > u32::max_value() as i64 {
+    return error("char: not a character code");
+  }
+  match ::std::char::from_u32(c as u32) {
+    Some(c) => Ok(Char(c)),
+    None    => error("char: not a character code"),
+  }
+}
+
+// the UTF-8 encoding of a string, or a seq of numbers from 0 to 255
+fn bytes(a: MalArgs) -> MalRet {
+  match a[0] {
+    Bytes(_) => Ok(a[0].clone()),
+    Str(ref s) => Ok(Bytes(Rc::new(s.as_bytes().to_vec()))),
+    _ => {
+      let mut b = vec![];
+      for x in a[0].seq_vals()? {
+        match x {
+          Int(i) if i >= 0 && i <= 255 => b.push(i as u8),
+          _ => return error(&format!("bytes: {} is not a byte", x.pr_str(true))),
+        }
+      }
+      Ok(Bytes(Rc::new(b)))
+    },
+  }
+}
+
+fn from_utf8(a: MalArgs) -> MalRet {
+  match a[0] {
+    Bytes(ref b) => match String::from_utf8(b.to_vec()) {
+      Ok(s)  => Ok(Str(s)),
+      Err(e) => error(&format!("str/from-utf8: {}", e)),
+    },
+    _ => error("str/from-utf8: expected bytes"),
+  }
+}
+
+// the number's digits, with the sign and zero padding flags applied
+fn sign_flags(digits: String, flags: &str) -> String {
+  if digits.starts_with('-') {
+    digits
+  } else if flags.contains('+') {
+    format!("+{}", digits)
+  } else if flags.contains(' ') {
+    format!(" {}", digits)
+  } else {
+    digits
+  }
+}
+
+fn pad(body: String, width: usize, flags: &str, numeric: bool) -> String {
+  let len = body.chars().count();
+  if len >= width {
+    return body;
+  }
+  let fill = width - len;
+  if flags.contains('-') {
+    format!("{}{}", body, " ".repeat(fill))
+  } else if numeric && flags.contains('0') {
+    // zeros go after any sign
+    let sign_len = if body.starts_with(|c| c == '-' || c == '+' || c == ' ') { 1 } else { 0 };
+    format!("{}{}{}", &body[..sign_len], "0".repeat(fill), &body[sign_len..])
+  } else {
+    format!("{}{}", " ".repeat(fill), body)
+  }
+}
+
+// %e as C writes it: a sign and at least two digits in the exponent
+fn exp_format(x: f64, precision: usize) -> String {
+  let s = format!("{:.*e}", precision, x);
+  match s.find('e') {
+    Some(i) => {
+      let (mantissa, exp) = (&s[..i], &s[i + 1..]);
+      let (sign, digits) = if exp.starts_with('-') { ("-", &exp[1..]) } else { ("+", exp) };
+      format!("{}e{}{:0>2}", mantissa, sign, digits)
+    },
+    None => s,
+  }
+}
+
+fn integer_digits(v: &MalVal, radix: u32) -> Result<String,MalErr> {
+  match *v {
+    // through BigInt so a negative Int prints with a sign, like a Bignum,
+    // instead of as two's complement
+    Int(i) => Ok(BigInt::from(i).to_str_radix(radix)),
+    Bignum(ref b) => Ok(b.to_str_radix(radix)),
+    _ => Err(ErrString(format!("format: {} is not an integer", v.pr_str(true)))),
+  }
+}
+
+// a width or precision only pads, so one past FORMAT_MAX is taken as a
+// mistake instead of allocated
+const FORMAT_MAX: usize = 10_000;
+
+fn format_digits(chars: &mut Peekable<Chars>, what: &str) -> Result<usize,MalErr> {
+  let mut n: usize = 0;
+  while let Some(d) = chars.peek().and_then(|c| c.to_digit(10)) {
+    n = match n.checked_mul(10).and_then(|n| n.checked_add(d as usize)) {
+      Some(n) if n <= FORMAT_MAX => n,
+      _ => return Err(ErrString(format!("format: {} larger than {}", what, FORMAT_MAX))),
+    };
+    chars.next();
+  }
+  Ok(n)
+}
+
+// printf-style formatting: %[flags][width][.precision]conversion, with the
+// flags - + 0 and space. s prints as str does; d, x, X and o take integers;
+// f and e take any number; c a character, its code or a one-character string.
+fn format(a: MalArgs) -> MalRet {
+  let fmt = str_arg(&a[0], "format")?;
+  let mut args = a[1..].iter();
+  let mut out = String::new();
+  let mut chars = fmt.chars().peekable();
+  while let Some(c) = chars.next() {
+    if c != '%' {
+      out.push(c);
+      continue;
+    }
+    let mut flags = String::new();
+    while let Some(&f) = chars.peek() {
+      if !"-+0 ".contains(f) { break; }
+      flags.push(f);
+      chars.next();
+    }
+    let width = format_digits(&mut chars, "width")?;
+    let mut precision = None;
+    if chars.peek() == Some(&'.') {
+      chars.next();
+      precision = Some(format_digits(&mut chars, "precision")?);
+    }
+    let conv = match chars.next() {
+      Some('%') => { out.push('%'); continue; },
+      Some(conv) => conv,
+      None => return error("format: incomplete % at end of string"),
+    };
+    let arg = match args.next() {
+      Some(arg) => arg,
+      None => return error("format: not enough arguments"),
+    };
+    let (body, numeric) = match conv {
+      's' => {
+        let s = pr_args(::std::slice::from_ref(arg), false, "")?;
+        (match precision {
+          Some(p) => s.chars().take(p).collect(),
+          None    => s,
+        }, false)
+      },
+      'd' => (sign_flags(integer_digits(arg, 10)?, &flags), true),
+      'o' => (integer_digits(arg, 8)?, true),
+      'x' => (integer_digits(arg, 16)?, true),
+      'X' => (integer_digits(arg, 16)?.to_uppercase(), true),
+      'f' => (sign_flags(format!("{:.*}", precision.unwrap_or(6), arg.to_f64()?), &flags), true),
+      'e' => (sign_flags(exp_format(arg.to_f64()?, precision.unwrap_or(6)), &flags), true),
+      'c' => match *arg {
+        Char(c) => (c.to_string(), false),
+        Str(ref s) if s.chars().count() == 1 => (s.clone(), false),
+        Int(i) if i >= 0 && i <= u32::MAX as i64 => match ::std::char::from_u32(i as u32) {
+          Some(c) => (c.to_string(), false),
+          None    => return error("format: %c expects a character"),
+        },
+        _ => return error("format: %c expects a character"),
+      },
+      _ => return error(&format!("format: unknown conversion %{}", conv)),
+    };
+    out.push_str(&pad(body, width, &flags, numeric));
+  }
+  Ok(Str(out))
+}
+
+fn regex_arg<'a>(v: &'a MalVal, name: &str) -> Result<&'a MalRegex,MalErr> {
+  match *v {
+    Regex(ref re) => Ok(re),
+    _ => Err(ErrString(format!("{}: expected a regex, got {}", name, v.pr_str(true)))),
+  }
+}
+
+// [whole group1 ...], with nil for groups that did not take part
+fn groups(caps: &Captures) -> MalVal {
+  vector!(caps.iter().map(|g| match g {
+    Some(m) => Str(m.as_str().to_string()),
+    None    => Nil,
+  }).collect())
+}
+
+// the matched string, or its groups when the pattern has any
+fn match_val(caps: &Captures) -> MalVal {
+  if caps.len() == 1 {
+    Str(caps[0].to_string())
+  } else {
+    groups(caps)
+  }
+}
+
+fn re_find(a: MalArgs) -> MalRet {
+  let re = regex_arg(&a[0], "re-find")?;
+  let s = str_arg(&a[1], "re-find")?;
+  Ok(re.captures(s).map(|c| match_val(&c)).unwrap_or(Nil))
+}
+
+fn re_matches(a: MalArgs) -> MalRet {
+  let re = regex_arg(&a[0], "re-matches")?;
+  let s = str_arg(&a[1], "re-matches")?;
+  Ok(re.whole()?.captures(s).map(|c| match_val(&c)).unwrap_or(Nil))
+}
+
+fn re_seq(a: MalArgs) -> MalRet {
+  let re = regex_arg(&a[0], "re-seq")?;
+  let s = str_arg(&a[1], "re-seq")?;
+  let matches: Vec<MalVal> = re.captures_iter(s).map(|c| match_val(&c)).collect();
+  if matches.is_empty() {
+    Ok(Nil)
+  } else {
+    Ok(list!(matches))
+  }
+}
+
+// always a vector of groups, even for a pattern without any
+fn re_groups(a: MalArgs) -> MalRet {
+  let re = regex_arg(&a[0], "re-groups")?;
+  let s = str_arg(&a[1], "re-groups")?;
+  Ok(re.captures(s).map(|c| groups(&c)).unwrap_or(Nil))
+}
+
 fn time_ms(_a: MalArgs) -> MalRet {
   let ms_e = match SystemTime::now().duration_since(UNIX_EPOCH) {
     Ok(d) => d,
@@ -94,12 +580,13 @@ fn time_ms(_a: MalArgs) -> MalRet {
 fn get(a: MalArgs) -> MalRet {
   match (a[0].clone(), a[1].clone()) {
     (Nil, _) => Ok(Nil),
-    (Hash(ref hm,_), Str(ref s)) => {
-      match hm.get(s) {
-        Some(mv) => Ok(mv.clone()),
+    (Hash(ref hm,_), ref k) => {
+      match MalKey::new(k).ok().and_then(|k| hm.get(&k).cloned()) {
+        Some(mv) => Ok(mv),
         None     => Ok(Nil),
       }
     },
+    (Set(_,_), _) => a[0].apply(a[1..2].to_vec()),
     _ => error("illegal get args")
   }
 }
@@ -120,8 +607,11 @@ fn dissoc(a: MalArgs) -> MalRet {
 
 fn contains_q(a: MalArgs) -> MalRet {
   match (a[0].clone(), a[1].clone()) {
-    (Hash(ref hm,_), Str(ref s)) => {
-      Ok(Bool(hm.contains_key(s)))
+    (Hash(ref hm,_), ref k) => {
+      Ok(Bool(MalKey::new(k).map_or(false, |k| hm.contains_key(&k))))
+    },
+    (Set(ref hs,_), ref k) => {
+      Ok(Bool(MalKey::new(k).map_or(false, |k| hs.contains(&k))))
     },
     _ => error("illegal get args")
   }
@@ -130,7 +620,7 @@ fn contains_q(a: MalArgs) -> MalRet {
 fn keys(a: MalArgs) -> MalRet {
   match a[0] {
     Hash(ref hm,_) => {
-      Ok(list!(hm.keys().map(|k|{Str(k.to_string())}).collect()))
+      Ok(list!(hm.keys().map(|k|{k.val().clone()}).collect()))
     },
     _ => error("keys requires Hash Map")
   }
@@ -146,25 +636,27 @@ fn vals(a: MalArgs) -> MalRet {
 }
 
 fn cons(a: MalArgs) -> MalRet {
-  match a[1].clone() {
-    List(v,_) | Vector(v,_) => {
-      let mut new_v = vec![a[0].clone()];
-      new_v.extend_from_slice(&v);
-      Ok(list!(new_v.to_vec()))
+  match a[1] {
+    List(ref v,_) | Vector(ref v,_) => {
+      let mut new_v = (**v).clone();
+      new_v.push_front(a[0].clone());
+      Ok(List(Rc::new(new_v),Rc::new(Nil)))
     },
+    LazySeq(..) => Ok(lazy_cons(a[0].clone(), a[1].clone())),
     _ => error("cons expects seq as second arg"),
   }
 }
 
 fn concat(a: MalArgs) -> MalRet {
-  let mut new_v = vec![];
+  let mut new_v = MalSeq::new();
   for seq in a.iter() {
     match seq {
-      List(v,_) | Vector(v,_) => new_v.extend_from_slice(v),
+      List(v,_) | Vector(v,_) => new_v.append(v),
+      LazySeq(..) => new_v.extend(seq.seq_vals()?),
       _ => return error("non-seq passed to concat"),
     }
   }
-  Ok(list!(new_v.to_vec()))
+  Ok(List(Rc::new(new_v),Rc::new(Nil)))
 }
 
 fn nth(a: MalArgs) -> MalRet {
@@ -175,31 +667,33 @@ fn nth(a: MalArgs) -> MalRet {
       }
       Ok(seq[idx as usize].clone())
     }
+    (_, Int(idx)) if idx >= 0 => {
+      let mut s = a[0].clone();
+      for _ in 0..idx {
+        match s.uncons()? {
+          Some((_, rest)) => s = rest,
+          None => return error("nth: index out of range"),
+        }
+      }
+      match s.uncons()? {
+        Some((x, _)) => Ok(x),
+        None => error("nth: index out of range"),
+      }
+    }
+    (_, Int(_)) => error("nth: index out of range"),
     _ => error("invalid args to nth"),
   }
 }
 
 fn first(a: MalArgs) -> MalRet {
-  match a[0].clone() {
-    List(ref seq,_) | Vector(ref seq,_) if seq.len() == 0 => Ok(Nil),
-    List(ref seq,_) | Vector(ref seq,_) => Ok(seq[0].clone()),
-    Nil => Ok(Nil),
-    _ => error("invalid args to first"),
+  match a[0] {
+    List(ref seq,_) | Vector(ref seq,_) => Ok(seq.iter().next().cloned().unwrap_or(Nil)),
+    _ => Ok(a[0].uncons()?.map_or(Nil, |(x, _)| x)),
   }
 }
 
 fn rest(a: MalArgs) -> MalRet {
-  match a[0].clone() {
-    List(ref seq,_) | Vector(ref seq,_) => {
-      if seq.len() > 1 {
-        Ok(list!(seq[1..].to_vec()))
-      } else {
-        Ok(list![])
-      }
-    },
-    Nil => Ok(list![]),
-    _ => error("invalid args to first"),
-  }
+  Ok(a[0].uncons()?.map_or(list![], |(_, rest)| rest))
 }
 
 fn apply(a: MalArgs) -> MalRet {
@@ -207,120 +701,1226 @@ fn apply(a: MalArgs) -> MalRet {
     List(ref v,_) | Vector(ref v,_) => {
       let f = &a[0];
       let mut fargs = a[1..a.len()-1].to_vec();
-      fargs.extend_from_slice(&v);
+      fargs.extend(v.iter().cloned());
       f.apply(fargs)
     },
+    LazySeq(..) => {
+      let mut fargs = a[1..a.len()-1].to_vec();
+      fargs.extend(a[a.len()-1].seq_vals()?);
+      a[0].apply(fargs)
+    },
     _ => error("apply called with non-seq"),
   }
 }
 
+fn truthy(v: &MalVal) -> bool {
+  match *v {
+    Bool(false) | Nil => false,
+    _ => true,
+  }
+}
+
+// (f x y ...) for the first elements of each seq, then the second, and so on
+// until one of them runs out
+fn map_seqs(f: MalVal, seqs: MalArgs) -> MalVal {
+  lazy_seq(move || {
+    let mut args = vec![];
+    let mut rests = vec![];
+    for s in seqs.iter() {
+      match s.uncons()? {
+        Some((x, rest)) => { args.push(x); rests.push(rest) },
+        None => return Ok(Nil),
+      }
+    }
+    Ok(lazy_cons(f.apply(args)?, map_seqs(f, rests)))
+  })
+}
+
+// Over lists and vectors map and filter are eager, as mal specifies: errors
+// surface where they are called, and self-hosted mal relies on the side
+// effects happening. Given a lazy seq they are lazy too.
+fn lazy_args(seqs: &[MalVal]) -> bool {
+  seqs.iter().any(|s| match *s { LazySeq(..) => true, _ => false })
+}
+
 fn map(a: MalArgs) -> MalRet {
-  match a[1] {
-    List(ref v,_) | Vector(ref v,_) => {
-      let mut res = vec![];
-      for mv in v.iter() {
-        res.push(a[0].apply(vec![mv.clone()])?)
+  if lazy_args(&a[1..]) {
+    return Ok(map_seqs(a[0].clone(), a[1..].to_vec()));
+  }
+  let seqs = a[1..].iter().map(|s| s.seq_vals()).collect::<Result<Vec<MalArgs>,MalErr>>()?;
+  let n = seqs.iter().map(|s| s.len()).min().unwrap_or(0);
+  let mut res = Vec::with_capacity(n);
+  for i in 0..n {
+    res.push(a[0].apply(seqs.iter().map(|s| s[i].clone()).collect())?);
+  }
+  Ok(list!(res))
+}
+
+// filter when keep is true, remove when it is false
+fn filter_with(a: MalArgs, keep: bool) -> MalRet {
+  if lazy_args(&a[1..]) {
+    return Ok(filter_seq(a[0].clone(), a[1].clone(), keep));
+  }
+  let mut res = vec![];
+  for x in a[1].seq_vals()? {
+    if truthy(&a[0].apply(vec![x.clone()])?) == keep {
+      res.push(x);
+    }
+  }
+  Ok(list!(res))
+}
+
+fn filter_seq(pred: MalVal, mut s: MalVal, keep: bool) -> MalVal {
+  lazy_seq(move || {
+    while let Some((x, rest)) = s.uncons()? {
+      if truthy(&pred.apply(vec![x.clone()])?) == keep {
+        return Ok(lazy_cons(x, filter_seq(pred, rest, keep)));
+      }
+      s = rest;
+    }
+    Ok(Nil)
+  })
+}
+
+fn int_arg(v: &MalVal, name: &str) -> Result<i64,MalErr> {
+  match *v {
+    Int(i) => Ok(i),
+    _ => Err(ErrString(format!("{} expects an integer, got {}", name, v.pr_str(true)))),
+  }
+}
+
+// start, start + step, ... up to but not including end. Any numbers will do:
+// (range 0 1 0.25) steps by a float and (range 0 1 1/3) by a ratio.
+fn range_from(start: MalVal, end: Option<MalVal>, step: MalVal) -> MalVal {
+  lazy_seq(move || {
+    if let Some(ref end) = end {
+      let past = match compare(&step, &Int(0))? {
+        Ordering::Less => Ordering::Greater,
+        _              => Ordering::Less,
+      };
+      if compare(&start, end)? != past {
+        return Ok(Nil);
       }
-      Ok(list!(res))
+    }
+    let next = fn_arith!(checked_add,|i,j|{i+j})(vec![start.clone(), step.clone()])?;
+    Ok(lazy_cons(start.clone(), range_from(next, end.clone(), step.clone())))
+  })
+}
+
+// (range), (range end), (range start end) or (range start end step)
+fn range(a: MalArgs) -> MalRet {
+  if let Some(v) = a.iter().find(|v| !is_number(v)) {
+    return error(&format!("range expects a number, got {}", v.pr_str(true)));
+  }
+  match a[..] {
+    []                             => Ok(range_from(Int(0), None, Int(1))),
+    [ref end]                      => Ok(range_from(Int(0), Some(end.clone()), Int(1))),
+    [ref start, ref end]           => Ok(range_from(start.clone(), Some(end.clone()), Int(1))),
+    [ref start, ref end, ref step] => Ok(range_from(start.clone(), Some(end.clone()), step.clone())),
+    _ => error("range takes at most three arguments"),
+  }
+}
+
+fn iterate_from(f: MalVal, x: MalVal) -> MalVal {
+  lazy_cons(x.clone(), lazy_seq(move || {
+    let next = f.apply(vec![x])?;
+    Ok(iterate_from(f, next))
+  }))
+}
+
+// n copies of x, or endless ones when n is None
+fn repeat_n(n: Option<i64>, x: MalVal) -> MalVal {
+  lazy_seq(move || match n {
+    Some(n) if n <= 0 => Ok(Nil),
+    _ => Ok(lazy_cons(x.clone(), repeat_n(n.map(|n| n - 1), x))),
+  })
+}
+
+fn repeat(a: MalArgs) -> MalRet {
+  match a.len() {
+    1 => Ok(repeat_n(None, a[0].clone())),
+    2 => Ok(repeat_n(Some(int_arg(&a[0], "repeat")?), a[1].clone())),
+    _ => error("repeat takes an optional count and a value"),
+  }
+}
+
+// the elements of s, then those of coll over and over
+fn cycle_from(coll: MalVal, s: MalVal) -> MalVal {
+  lazy_seq(move || {
+    let (x, rest) = match s.uncons()? {
+      Some(next) => next,
+      None => match coll.uncons()? {
+        Some(next) => next,
+        None => return Ok(Nil),
+      },
+    };
+    Ok(lazy_cons(x, cycle_from(coll, rest)))
+  })
+}
+
+fn take_seq(n: i64, s: MalVal) -> MalVal {
+  lazy_seq(move || {
+    if n <= 0 {
+      return Ok(Nil);
+    }
+    match s.uncons()? {
+      Some((x, rest)) => Ok(lazy_cons(x, take_seq(n - 1, rest))),
+      None => Ok(Nil),
+    }
+  })
+}
+
+fn drop_seq(n: i64, mut s: MalVal) -> MalVal {
+  lazy_seq(move || {
+    for _ in 0..n {
+      match s.uncons()? {
+        Some((_, rest)) => s = rest,
+        None => return Ok(Nil),
+      }
+    }
+    Ok(s)
+  })
+}
+
+fn take_while_seq(pred: MalVal, s: MalVal) -> MalVal {
+  lazy_seq(move || {
+    match s.uncons()? {
+      Some((x, rest)) => {
+        if truthy(&pred.apply(vec![x.clone()])?) {
+          Ok(lazy_cons(x, take_while_seq(pred, rest)))
+        } else {
+          Ok(Nil)
+        }
+      },
+      None => Ok(Nil),
+    }
+  })
+}
+
+fn drop_while_seq(pred: MalVal, mut s: MalVal) -> MalVal {
+  lazy_seq(move || {
+    while let Some((x, rest)) = s.uncons()? {
+      if !truthy(&pred.apply(vec![x])?) {
+        break;
+      }
+      s = rest;
+    }
+    Ok(s)
+  })
+}
+
+// realizes the whole of a lazy seq
+fn dorun(s: &MalVal) -> Result<(),MalErr> {
+  let mut s = s.clone();
+  while let Some((_, rest)) = s.uncons()? {
+    s = rest;
+  }
+  Ok(())
+}
+
+// sequence library: these take anything seq_vals does, so maps give
+// [key value] vectors and strings one-character strings
+
+// (reduce f coll) or (reduce f init coll)
+fn reduce(a: MalArgs) -> MalRet {
+  let (f, init, coll) = match a.len() {
+    2 => (&a[0], None, &a[1]),
+    3 => (&a[0], Some(a[1].clone()), &a[2]),
+    _ => return error("reduce takes a function, an optional initial value and a seq"),
+  };
+  let mut xs = coll.seq_vals()?.into_iter();
+  let mut acc = match init.or_else(|| xs.next()) {
+    Some(acc) => acc,
+    None => return f.apply(vec![]),
+  };
+  for x in xs {
+    acc = f.apply(vec![acc, x])?;
+  }
+  Ok(acc)
+}
+
+// the first truthy (pred x), stopping there
+fn some(a: MalArgs) -> MalRet {
+  let mut s = a[1].clone();
+  while let Some((x, rest)) = s.uncons()? {
+    let res = a[0].apply(vec![x])?;
+    if truthy(&res) {
+      return Ok(res);
+    }
+    s = rest;
+  }
+  Ok(Nil)
+}
+
+fn every_q(a: MalArgs) -> MalRet {
+  let mut s = a[1].clone();
+  while let Some((x, rest)) = s.uncons()? {
+    if !truthy(&a[0].apply(vec![x])?) {
+      return Ok(Bool(false));
+    }
+    s = rest;
+  }
+  Ok(Bool(true))
+}
+
+fn is_number(v: &MalVal) -> bool {
+  match *v {
+    Int(_) | Bignum(_) | Ratio(_) | Float(_) => true,
+    _ => false,
+  }
+}
+
+// The order sort uses without a comparator: nil first, numbers by value,
+// strings, keywords and symbols alphabetically, and lists and vectors element
+// by element. Anything else can't be compared.
+fn compare(a: &MalVal, b: &MalVal) -> Result<Ordering,MalErr> {
+  match (a, b) {
+    (&Int(x), &Int(y)) => Ok(x.cmp(&y)),
+    (&Float(_), _) | (_, &Float(_)) if is_number(a) && is_number(b) => {
+      a.to_f64()?.partial_cmp(&b.to_f64()?)
+        .ok_or_else(|| ErrString("cannot compare NaN".to_string()))
+    },
+    _ if is_number(a) && is_number(b) => Ok(a.to_ratio()?.cmp(&b.to_ratio()?)),
+    (&Nil, &Nil) => Ok(Ordering::Equal),
+    (&Nil, _) => Ok(Ordering::Less),
+    (_, &Nil) => Ok(Ordering::Greater),
+    (&Bool(x), &Bool(y)) => Ok(x.cmp(&y)),
+    (&Str(ref x), &Str(ref y)) | (&Sym(ref x), &Sym(ref y)) => Ok(x.cmp(y)),
+    (&Keyword(ref x), &Keyword(ref y)) => Ok(x.cmp(y)),
+    (&Char(x), &Char(y)) => Ok(x.cmp(&y)),
+    (&List(ref x,_), &List(ref y,_)) | (&List(ref x,_), &Vector(ref y,_)) |
+    (&Vector(ref x,_), &List(ref y,_)) | (&Vector(ref x,_), &Vector(ref y,_)) => {
+      for (p, q) in x.iter().zip(y.iter()) {
+        match compare(p, q)? {
+          Ordering::Equal => (),
+          o => return Ok(o),
+        }
+      }
+      Ok(x.len().cmp(&y.len()))
+    },
+    _ => Err(ErrString(format!("cannot compare {} and {}", a.pr_str(true), b.pr_str(true)))),
+  }
+}
+
+// A comparator either returns a number, negative when a comes first, or is a
+// predicate like < that says whether a comes before b.
+fn compare_with(f: &MalVal, a: &MalVal, b: &MalVal) -> Result<Ordering,MalErr> {
+  match f.apply(vec![a.clone(), b.clone()])? {
+    ref n if is_number(n) => compare(n, &Int(0)),
+    ref r if truthy(r) => Ok(Ordering::Less),
+    _ if truthy(&f.apply(vec![b.clone(), a.clone()])?) => Ok(Ordering::Greater),
+    _ => Ok(Ordering::Equal),
+  }
+}
+
+// a stable sort with a comparison that can fail; sort_by can't stop early, so
+// the first error is kept and returned at the end
+fn sort_vals<T, F>(v: &mut Vec<T>, mut cmp: F) -> Result<(),MalErr>
+  where F: FnMut(&T, &T) -> Result<Ordering,MalErr> {
+  let mut err = None;
+  v.sort_by(|x, y| {
+    cmp(x, y).unwrap_or_else(|e| {
+      err = err.take().or(Some(e));
+      Ordering::Equal
+    })
+  });
+  match err {
+    Some(e) => Err(e),
+    None    => Ok(()),
+  }
+}
+
+// (sort coll) or (sort comparator coll)
+fn sort(a: MalArgs) -> MalRet {
+  let (comp, coll) = match a.len() {
+    1 => (None, &a[0]),
+    2 => (Some(&a[0]), &a[1]),
+    _ => return error("sort takes an optional comparator and a seq"),
+  };
+  let mut vals = coll.seq_vals()?;
+  sort_vals(&mut vals, |x, y| match comp {
+    Some(f) => compare_with(f, x, y),
+    None    => compare(x, y),
+  })?;
+  Ok(list!(vals))
+}
+
+// (sort-by keyfn coll) or (sort-by keyfn comparator coll)
+fn sort_by(a: MalArgs) -> MalRet {
+  let (keyfn, comp, coll) = match a.len() {
+    2 => (&a[0], None, &a[1]),
+    3 => (&a[0], Some(&a[1]), &a[2]),
+    _ => return error("sort-by takes a key function, an optional comparator and a seq"),
+  };
+  let mut keyed = vec![];
+  for x in coll.seq_vals()? {
+    keyed.push((keyfn.apply(vec![x.clone()])?, x));
+  }
+  sort_vals(&mut keyed, |x, y| match comp {
+    Some(f) => compare_with(f, &x.0, &y.0),
+    None    => compare(&x.0, &y.0),
+  })?;
+  Ok(list!(keyed.into_iter().map(|(_, x)| x).collect()))
+}
+
+// a map from each (f x) to a vector of the xs that gave it, in order
+fn group_by(a: MalArgs) -> MalRet {
+  let mut groups: FnvHashMap<MalKey,MalArgs> = FnvHashMap::default();
+  for x in a[1].seq_vals()? {
+    let k = MalKey::new(&a[0].apply(vec![x.clone()])?)?;
+    groups.entry(k).or_insert_with(Vec::new).push(x);
+  }
+  let hm: MalMap = groups.into_iter().map(|(k, xs)| (k, vector!(xs))).collect();
+  Ok(Hash(Rc::new(hm),Rc::new(Nil)))
+}
+
+fn frequencies(a: MalArgs) -> MalRet {
+  let mut counts: FnvHashMap<MalKey,i64> = FnvHashMap::default();
+  for x in a[0].seq_vals()? {
+    *counts.entry(MalKey::new(&x)?).or_insert(0) += 1;
+  }
+  let hm: MalMap = counts.into_iter().map(|(k, n)| (k, Int(n))).collect();
+  Ok(Hash(Rc::new(hm),Rc::new(Nil)))
+}
+
+// (partition n coll), (partition n step coll) or (partition n step pad coll):
+// lists of n elements starting every step elements. A short last one is
+// dropped, unless pad is given to fill it up from.
+fn partition(a: MalArgs) -> MalRet {
+  let (n, step, pad, coll) = match a.len() {
+    2 => (int_arg(&a[0], "partition")?, int_arg(&a[0], "partition")?, None, &a[1]),
+    3 => (int_arg(&a[0], "partition")?, int_arg(&a[1], "partition")?, None, &a[2]),
+    4 => (int_arg(&a[0], "partition")?, int_arg(&a[1], "partition")?, Some(a[2].seq_vals()?), &a[3]),
+    _ => return error("partition takes a size, an optional step and pad, and a seq"),
+  };
+  if n <= 0 || step <= 0 {
+    return error("partition size and step must be positive");
+  }
+  let (n, step) = (n as usize, step as usize);
+  let vals = coll.seq_vals()?;
+  let mut parts = vec![];
+  let mut i = 0;
+  while i < vals.len() {
+    let mut part = vals[i..vals.len().min(i + n)].to_vec();
+    if part.len() < n {
+      if let Some(ref pad) = pad {
+        part.extend(pad.iter().take(n - part.len()).cloned());
+        parts.push(list!(part));
+      }
+      break;
+    }
+    parts.push(list!(part));
+    i += step;
+  }
+  Ok(list!(parts))
+}
+
+// the first element of each seq, then the second of each, until one runs
+// out; only that far is realized, so all but one may be infinite
+fn interleave(a: MalArgs) -> MalRet {
+  if a.is_empty() {
+    return Ok(list![]);
+  }
+  let mut seqs = a;
+  let mut res = vec![];
+  loop {
+    let mut firsts = vec![];
+    for s in seqs.iter_mut() {
+      match s.uncons()? {
+        Some((x, rest)) => { firsts.push(x); *s = rest; },
+        None => return Ok(list!(res)),
+      }
+    }
+    res.extend(firsts);
+  }
+}
+
+fn distinct(a: MalArgs) -> MalRet {
+  let mut seen = FnvHashSet::default();
+  let mut res: MalArgs = vec![];
+  for x in a[0].seq_vals()? {
+    let first = match MalKey::new(&x) {
+      Ok(k) => seen.insert(k),
+      // floats and functions can't be hashed, so look for those by hand
+      Err(_) => !res.contains(&x),
+    };
+    if first {
+      res.push(x);
+    }
+  }
+  Ok(list!(res))
+}
+
+fn butlast(a: MalArgs) -> MalRet {
+  let mut vals = a[0].seq_vals()?;
+  vals.pop();
+  Ok(if vals.is_empty() { Nil } else { list!(vals) })
+}
+
+// conj every element of from onto to; into a map they are [key value] pairs
+fn into(a: MalArgs) -> MalRet {
+  let items = a[1].seq_vals()?;
+  match a[0] {
+    Hash(ref hm,_) => {
+      let mut kvs = vec![];
+      for x in items {
+        match x {
+          List(ref kv,_) | Vector(ref kv,_) if kv.len() == 2 => {
+            kvs.push(kv[0].clone());
+            kvs.push(kv[1].clone());
+          },
+          _ => return error("into a map takes [key value] pairs"),
+        }
+      }
+      _assoc((**hm).clone(), kvs)
     },
-    _ => error("map called with non-seq"),
+    Nil => Ok(list!(items.into_iter().rev().collect())),
+    _ => {
+      let mut args = vec![a[0].clone()];
+      args.extend(items);
+      conj(args)
+    },
+  }
+}
+
+// keys paired with vals, up to the shorter of the two
+fn zipmap(a: MalArgs) -> MalRet {
+  let (mut ks, mut vs) = (a[0].clone(), a[1].clone());
+  let mut kvs = vec![];
+  while let (Some((k, krest)), Some((v, vrest))) = (ks.uncons()?, vs.uncons()?) {
+    kvs.push(k);
+    kvs.push(v);
+    ks = krest;
+    vs = vrest;
   }
+  hash_map(kvs)
 }
 
 fn conj(a: MalArgs) -> MalRet {
   match a[0] {
     List(ref v,_) => {
-      let sl = a[1..].iter().rev().map(|a|{a.clone()}).collect::<Vec<MalVal>>();
-      Ok(list!([&sl[..],v].concat()))
+      let mut new_v = (**v).clone();
+      for x in a[1..].iter() {
+        new_v.push_front(x.clone());
+      }
+      Ok(List(Rc::new(new_v),Rc::new(Nil)))
     },
-    Vector(ref v,_) => Ok(vector!([v,&a[1..]].concat())),
+    Vector(ref v,_) => {
+      let mut new_v = (**v).clone();
+      new_v.extend(a[1..].iter().cloned());
+      Ok(Vector(Rc::new(new_v),Rc::new(Nil)))
+    },
+    Set(ref hs,_) => _conj_set((**hs).clone(), &a[1..]),
+    LazySeq(..) => Ok(a[1..].iter().fold(a[0].clone(), |s, x| lazy_cons(x.clone(), s))),
     _ => error("conj: called with non-seq"),
   }
 }
 
+fn set(a: MalArgs) -> MalRet {
+  match a[0] {
+    List(ref v,_) | Vector(ref v,_) => hash_set(v.iter().cloned().collect()),
+    Set(_,_) => Ok(a[0].clone()),
+    LazySeq(..) => hash_set(a[0].seq_vals()?),
+    Nil => hash_set(vec![]),
+    _ => error("set: called with non-seq"),
+  }
+}
+
+fn disj(a: MalArgs) -> MalRet {
+  match a[0] {
+    Set(ref hs,_) => {
+      let mut hs = (**hs).clone();
+      for x in a[1..].iter() {
+        if let Ok(k) = MalKey::new(x) {
+          hs.remove(&k);
+        }
+      }
+      Ok(Set(Rc::new(hs),Rc::new(Nil)))
+    },
+    _ => error("disj: called with non-set"),
+  }
+}
+
+// the sets passed to union, intersection, difference and subset?
+fn set_args(a: &MalArgs) -> Result<Vec<&MalSet>,MalErr> {
+  a.iter().map(|s| match *s {
+    Set(ref hs,_) => Ok(&**hs),
+    _             => Err(ErrString("expecting set args".to_string())),
+  }).collect()
+}
+
+fn union(a: MalArgs) -> MalRet {
+  let mut res = MalSet::default();
+  for hs in set_args(&a)? {
+    res.extend(hs.iter().cloned());
+  }
+  Ok(Set(Rc::new(res),Rc::new(Nil)))
+}
+
+// keep the members of the first set for which keep(member, other sets) holds
+fn filter_set(a: MalArgs, keep: fn(&MalKey, &[&MalSet]) -> bool) -> MalRet {
+  let sets = set_args(&a)?;
+  match sets.split_first() {
+    Some((first, rest)) => {
+      let res = first.iter().filter(|k| keep(k, rest)).cloned().collect();
+      Ok(Set(Rc::new(res),Rc::new(Nil)))
+    },
+    None => error("expecting at least one set"),
+  }
+}
+
+fn subset_q(a: MalArgs) -> MalRet {
+  let sets = set_args(&a)?;
+  Ok(Bool(sets[0].is_subset(sets[1])))
+}
+
+// A string seqs as one-character strings, not Chars: the step A tests that
+// every implementation shares expect (seq "abc") to be ("a" "b" "c").
+// (map char s) gives the Chars.
 fn seq(a: MalArgs) -> MalRet {
   match a[0] {
     List(ref v,_) | Vector(ref v,_) if v.len() == 0 => Ok(Nil),
-    List(ref v,_) | Vector(ref v,_) => Ok(list!(v.to_vec())),
+    List(ref v,_) | Vector(ref v,_) => Ok(List(v.clone(),Rc::new(Nil))),
+    Set(ref hs,_) if hs.is_empty() => Ok(Nil),
+    Set(ref hs,_) => Ok(list!(hs.iter().map(|k|{k.val().clone()}).collect())),
+    LazySeq(..) if a[0].uncons()?.is_none() => Ok(Nil),
+    LazySeq(..) => Ok(a[0].clone()),
     Str(ref s) if s.len() == 0 => Ok(Nil),
-    Str(ref s) if !a[0].keyword_q() => {
-      Ok(list!(s.chars().map(|c|{Str(c.to_string())}).collect()))
-    },
+    Bytes(ref b) if b.is_empty() => Ok(Nil),
+    Str(_) | Bytes(_) => Ok(list!(a[0].seq_vals()?)),
     Nil => Ok(Nil),
     _ => error("seq: called with non-seq"),
   }
 }
 
+// / is exact: integers that don't divide evenly give a Ratio
+fn divide(a: MalArgs) -> MalRet {
+  match (a[0].clone(), a[1].clone()) {
+    (Float(x), y) => Ok(Float(x / y.to_f64()?)),
+    (x, Float(y)) => Ok(Float(x.to_f64()? / y)),
+    (Int(i), Int(j)) if j != 0 && i.checked_rem(j) == Some(0) => Ok(Int(i / j)),
+    (x, y) => {
+      let d = y.to_ratio()?;
+      if d.is_zero() {
+        return error("division by zero");
+      }
+      Ok(ratio(x.to_ratio()? / d))
+    },
+  }
+}
+
+// quot, rem and mod: an integer for two integers, a float otherwise.
+// Only i64::MIN / -1 overflows, and then big_op gives the exact answer.
+fn divide_with(a: MalArgs, int_op: fn(i64, i64) -> Option<i64>,
+               big_op: fn(BigInt, BigInt) -> BigInt,
+               float_op: fn(f64, f64) -> f64) -> MalRet {
+  match (a[0].clone(), a[1].clone()) {
+    (Int(_), Int(0)) |
+    (Bignum(_), Int(0)) => error("division by zero"),
+    (Int(i), Int(j))    => Ok(match int_op(i, j) {
+      Some(r) => Int(r),
+      None    => bignum(big_op(BigInt::from(i), BigInt::from(j))),
+    }),
+    (Float(x), y)       => Ok(Float(float_op(x, y.to_f64()?))),
+    (x, Float(y))       => Ok(Float(float_op(x.to_f64()?, y))),
+    (x, y)              => Ok(bignum(big_op(x.to_bigint()?, y.to_bigint()?))),
+  }
+}
+
+// mod takes the sign of the divisor, rem that of the dividend
+fn mod_int(i: i64, j: i64) -> Option<i64> {
+  i.checked_rem(j).map(|r| if r != 0 && (r < 0) != (j < 0) { r + j } else { r })
+}
+
+fn mod_big(i: BigInt, j: BigInt) -> BigInt {
+  let r = i % &j;
+  if !r.is_zero() && (r.sign() == Sign::Minus) != (j.sign() == Sign::Minus) { r + j } else { r }
+}
+
+fn mod_float(x: f64, y: f64) -> f64 {
+  let r = x % y;
+  if r != 0.0 && (r < 0.0) != (y < 0.0) { r + y } else { r }
+}
+
+// floor and ceil leave integers as they are and round ratios to integers
+fn round_with(a: MalArgs, f: fn(f64) -> f64,
+              r: fn(&BigRational) -> BigRational) -> MalRet {
+  match a[0] {
+    Int(_) | Bignum(_) => Ok(a[0].clone()),
+    Ratio(ref x)       => Ok(ratio(r(x))),
+    Float(x)           => Ok(Float(f(x))),
+    _                  => error("expecting number arg"),
+  }
+}
+
+fn numerator(a: MalArgs) -> MalRet {
+  match a[0] {
+    Int(_) | Bignum(_) => Ok(a[0].clone()),
+    Ratio(ref r)       => Ok(bignum(r.numer().clone())),
+    _                  => error("expecting rational arg"),
+  }
+}
+
+fn denominator(a: MalArgs) -> MalRet {
+  match a[0] {
+    Int(_) | Bignum(_) => Ok(Int(1)),
+    Ratio(ref r)       => Ok(bignum(r.denom().clone())),
+    _                  => error("expecting rational arg"),
+  }
+}
+
+// int truncates towards zero, like quot
+fn int(a: MalArgs) -> MalRet {
+  match a[0] {
+    Int(_) | Bignum(_) => Ok(a[0].clone()),
+    // a character, or a one-character string, gives its character code
+    Char(c)            => Ok(Int(c as i64)),
+    Str(ref s) if s.chars().count() == 1 => Ok(Int(s.chars().next().unwrap() as i64)),
+    Ratio(ref r)       => Ok(bignum(r.to_integer())),
+    Float(x)           => match BigInt::from_f64(x.trunc()) {
+      Some(i) => Ok(bignum(i)),
+      None    => error("int: cannot convert non-finite float"),
+    },
+    _                  => error("expecting number arg"),
+  }
+}
+
+// pow refuses results wider than this instead of exhausting memory
+const POW_MAX_BITS: u64 = 1 << 24;
+
+fn pow(a: MalArgs) -> MalRet {
+  match (a[0].clone(), a[1].clone()) {
+    (Int(i), Int(j)) if j >= 0 && j <= u32::max_value() as i64 => {
+      match i.checked_pow(j as u32) {
+        Some(r) => Ok(Int(r)),
+        None    => big_pow(BigInt::from(i), j as u32),
+      }
+    },
+    (Bignum(i), Int(j)) if j >= 0 && j <= u32::max_value() as i64 => {
+      big_pow(i, j as u32)
+    },
+    (x, y) => Ok(Float(x.to_f64()?.powf(y.to_f64()?))),
+  }
+}
+
+fn big_pow(base: BigInt, exp: u32) -> MalRet {
+  // |base| >= 2^(bits - 1), so the result takes at least exp * (bits - 1) bits
+  if base.bits().saturating_sub(1).saturating_mul(exp as u64) > POW_MAX_BITS {
+    return error("pow: result too large");
+  }
+  Ok(bignum(base.pow(exp)))
+}
+
+// Every builtin declares how many arguments it takes. NativeFn::call checks
+// the count first, so a body can index a[0]..a[n-1] without a bounds check.
+fn exactly<F>(n: usize, f: F) -> MalVal where F: Fn(MalArgs) -> MalRet + 'static {
+  native_fn(None, Arity::Exactly(n), f)
+}
+
+fn at_least<F>(n: usize, f: F) -> MalVal where F: Fn(MalArgs) -> MalRet + 'static {
+  native_fn(None, Arity::AtLeast(n), f)
+}
+
+fn between<F>(lo: usize, hi: usize, f: F) -> MalVal where F: Fn(MalArgs) -> MalRet + 'static {
+  native_fn(None, Arity::Between(lo, hi), f)
+}
+
 pub fn ns() -> Vec<(&'static str, MalVal)> {
-  vec![
-    ("=",        func(|a|{Ok(Bool(a[0] == a[1]))})),
-    ("throw",    func(|a|{Err(ErrMalVal(a[0].clone()))})),
-
-    ("nil?",     func(fn_is_type!(Nil))),
-    ("true?",    func(fn_is_type!(Bool(true)))),
-    ("false?",   func(fn_is_type!(Bool(false)))),
-    ("symbol",   func(symbol)),
-    ("symbol?",  func(fn_is_type!(Sym(_)))),
-    ("string?",  func(fn_is_type!(Str(ref s) if !s.starts_with("\u{29e}")))),
-    ("keyword",  func(|a|{a[0].keyword()})),
-    ("keyword?", func(fn_is_type!(Str(ref s) if s.starts_with("\u{29e}")))),
-    ("number?",  func(fn_is_type!(Int(_)))),
-    ("fn?",      func(fn_is_type!(MalFunc{is_macro,..} if !is_macro,Func(_,_)))),
-    ("macro?",   func(fn_is_type!(MalFunc{is_macro,..} if is_macro))),
-
-    ("pr-str",   func(|a|Ok(Str(pr_seq(&a, true, "", "", " "))))),
-    ("str",      func(|a|Ok(Str(pr_seq(&a, false, "", "", ""))))),
-    ("prn",      func(|a|{println!("{}", pr_seq(&a, true, "", "", " ")); Ok(Nil)})),
-    ("println",  func(|a|{println!("{}", pr_seq(&a, false, "", "", " ")); Ok(Nil)})),
-    ("read-string", func(fn_str!(|s|{read_str(s)}))),
-    ("readline", func(readline)),
-    ("slurp",    func(fn_str!(|f|{slurp(f)}))),
-
-    ("<",  func(fn_t_int_int!(Bool,|i,j|{i<j}))),
-    ("<=", func(fn_t_int_int!(Bool,|i,j|{i<=j}))),
-    (">",  func(fn_t_int_int!(Bool,|i,j|{i>j}))),
-    (">=", func(fn_t_int_int!(Bool,|i,j|{i>=j}))),
-    ("+",  func(fn_t_int_int!(Int,|i,j|{i+j}))),
-    ("-",  func(fn_t_int_int!(Int,|i,j|{i-j}))),
-    ("*",  func(fn_t_int_int!(Int,|i,j|{i*j}))),
-    ("/",  func(fn_t_int_int!(Int,|i,j|{i/j}))),
-    ("time-ms", func(time_ms)),
-
-    ("sequential?", func(fn_is_type!(List(_,_),Vector(_,_)))),
-    ("list",     func(|a|{Ok(list!(a))})),
-    ("list?",    func(fn_is_type!(List(_,_)))),
-    ("vector",   func(|a|{Ok(vector!(a))})),
-    ("vector?",  func(fn_is_type!(Vector(_,_)))),
-    ("hash-map", func(|a|{hash_map(a)})),
-    ("map?",     func(fn_is_type!(Hash(_,_)))),
-    ("assoc",    func(assoc)),
-    ("dissoc",   func(dissoc)),
-    ("get",      func(get)),
-    ("contains?", func(contains_q)),
-    ("keys",     func(keys)),
-    ("vals",     func(vals)),
-
-    ("cons",   func(cons)),
-    ("concat", func(concat)),
-    ("empty?", func(|a|{a[0].empty_q()})),
-    ("nth",    func(nth)),
-    ("first",  func(first)),
-    ("rest",   func(rest)),
-    ("count",  func(|a|{a[0].count()})),
-    ("apply",  func(apply)),
-    ("map",    func(map)),
-
-    ("conj",   func(conj)),
-    ("seq",    func(seq)),
-
-    ("meta",   func(|a|{a[0].get_meta()})),
-    ("with-meta", func(|a|{a[0].clone().with_meta(&a[1])})),
-    ("atom",   func(|a|{Ok(atom(&a[0]))})),
-    ("atom?",  func(fn_is_type!(Atom(_)))),
-    ("deref",  func(|a|{a[0].deref()})),
-    ("reset!", func(|a|{a[0].reset_bang(&a[1])})),
-    ("swap!",  func(|a|{a[0].swap_bang(&a[1..].to_vec())})),
-  ]
+  let rl = RefCell::new(Editor::<()>::new());
+  let ns = vec![
+    ("=",        exactly(2, |a|{Ok(Bool(a[0] == a[1]))})),
+    ("throw",    exactly(1, |a|{Err(ErrMalVal(a[0].clone()))})),
+
+    ("nil?",     exactly(1, fn_is_type!(Nil))),
+    ("true?",    exactly(1, fn_is_type!(Bool(true)))),
+    ("false?",   exactly(1, fn_is_type!(Bool(false)))),
+    ("symbol",   exactly(1, symbol)),
+    ("symbol?",  exactly(1, fn_is_type!(Sym(_)))),
+    ("string?",  exactly(1, fn_is_type!(Str(_)))),
+    ("char?",    exactly(1, fn_is_type!(Char(_)))),
+    ("bytes?",   exactly(1, fn_is_type!(Bytes(_)))),
+    ("keyword",  exactly(1, |a|{a[0].keyword()})),
+    ("keyword?", exactly(1, fn_is_type!(Keyword(_)))),
+    ("number?",  exactly(1, fn_is_type!(Int(_),Bignum(_),Ratio(_),Float(_)))),
+    ("fn?",      exactly(1, fn_is_type!(MalFunc{is_macro,..} if !is_macro,Func(_,_)))),
+    ("macro?",   exactly(1, fn_is_type!(MalFunc{is_macro,..} if is_macro))),
+
+    ("pr-str",   at_least(0, |a|Ok(Str(pr_args(&a, true, " ")?)))),
+    ("str",      at_least(0, |a|Ok(Str(pr_args(&a, false, "")?)))),
+    ("prn",      at_least(0, |a|{println!("{}", pr_args(&a, true, " ")?); Ok(Nil)})),
+    ("println",  at_least(0, |a|{println!("{}", pr_args(&a, false, " ")?); Ok(Nil)})),
+    ("read-string", between(1, 2, read_string)),
+    ("to-json",  exactly(1, to_json)),
+    ("from-json", exactly(1, from_json)),
+    ("json/read-str",  between(1, 2, json_read_str)),
+    ("json/write-str", between(1, 2, json_write_str)),
+    ("json/read-file", between(1, 2, json_read_file)),
+    ("readline", exactly(1, move |a|{readline(&rl, a)})),
+    ("slurp",    exactly(1, fn_str!(|f|{slurp(f)}))),
+    ("slurp-bytes", exactly(1, fn_str!(|f|{slurp_bytes(f)}))),
+    ("spit-bytes",  exactly(2, spit_bytes)),
+
+    ("subs",         between(2, 3, subs)),
+    ("str/split",    exactly(2, split)),
+    ("str/join",     between(1, 2, join)),
+    ("str/replace",  exactly(3, replace)),
+    ("str/trim",     exactly(1, |a|{Ok(Str(str_arg(&a[0], "str/trim")?.trim().to_string()))})),
+    ("upper-case",   exactly(1, |a|{Ok(Str(str_arg(&a[0], "upper-case")?.to_uppercase()))})),
+    ("lower-case",   exactly(1, |a|{Ok(Str(str_arg(&a[0], "lower-case")?.to_lowercase()))})),
+    ("starts-with?", exactly(2, |a|{Ok(Bool(str_arg(&a[0], "starts-with?")?.starts_with(str_arg(&a[1], "starts-with?")?)))})),
+    ("ends-with?",   exactly(2, |a|{Ok(Bool(str_arg(&a[0], "ends-with?")?.ends_with(str_arg(&a[1], "ends-with?")?)))})),
+    ("includes?",    exactly(2, |a|{Ok(Bool(str_arg(&a[0], "includes?")?.contains(str_arg(&a[1], "includes?")?)))})),
+    ("index-of",     between(2, 3, index_of)),
+    ("format",       at_least(1, format)),
+    ("char",         exactly(1, char_of)),
+    ("bytes",        exactly(1, bytes)),
+    ("str/from-utf8", exactly(1, from_utf8)),
+
+    ("re-pattern",   exactly(1, |a|{match a[0] {
+                       Regex(_) => Ok(a[0].clone()),
+                       _ => re_pattern(str_arg(&a[0], "re-pattern")?),
+                     }})),
+    ("re-find",      exactly(2, re_find)),
+    ("re-matches",   exactly(2, re_matches)),
+    ("re-seq",       exactly(2, re_seq)),
+    ("re-groups",    exactly(2, re_groups)),
+    ("regex?",       exactly(1, fn_is_type!(Regex(_)))),
+
+    ("<",  exactly(2, fn_t_num_num!(Bool,|i,j|{i<j}))),
+    ("<=", exactly(2, fn_t_num_num!(Bool,|i,j|{i<=j}))),
+    (">",  exactly(2, fn_t_num_num!(Bool,|i,j|{i>j}))),
+    (">=", exactly(2, fn_t_num_num!(Bool,|i,j|{i>=j}))),
+    ("+",  exactly(2, fn_arith!(checked_add,|i,j|{i+j}))),
+    ("-",  exactly(2, fn_arith!(checked_sub,|i,j|{i-j}))),
+    ("*",  exactly(2, fn_arith!(checked_mul,|i,j|{i*j}))),
+    ("/",     exactly(2, divide)),
+    ("quot",  exactly(2, |a|{divide_with(a, i64::checked_div, |i,j|{i/j}, |x,y|{(x/y).trunc()})})),
+    ("rem",   exactly(2, |a|{divide_with(a, i64::checked_rem, |i,j|{i%j}, |x,y|{x%y})})),
+    ("mod",   exactly(2, |a|{divide_with(a, mod_int, mod_big, mod_float)})),
+    ("floor", exactly(1, |a|{round_with(a, f64::floor, BigRational::floor)})),
+    ("ceil",  exactly(1, |a|{round_with(a, f64::ceil, BigRational::ceil)})),
+    ("sqrt",  exactly(1, |a|{Ok(Float(a[0].to_f64()?.sqrt()))})),
+    ("pow",   exactly(2, pow)),
+    ("numerator",   exactly(1, numerator)),
+    ("denominator", exactly(1, denominator)),
+    ("double",      exactly(1, |a|{Ok(Float(a[0].to_f64()?))})),
+    ("int",         exactly(1, int)),
+    ("time-ms", exactly(0, time_ms)),
+
+    ("sequential?", exactly(1, fn_is_type!(List(_,_),Vector(_,_),LazySeq(_,_)))),
+    ("list",     at_least(0, |a|{Ok(list!(a))})),
+    ("list?",    exactly(1, fn_is_type!(List(_,_)))),
+    ("vector",   at_least(0, |a|{Ok(vector!(a))})),
+    ("vector?",  exactly(1, fn_is_type!(Vector(_,_)))),
+    ("hash-map", at_least(0, |a|{hash_map(a)})),
+    ("map?",     exactly(1, fn_is_type!(Hash(_,_)))),
+    ("assoc",    at_least(1, assoc)),
+    ("dissoc",   at_least(1, dissoc)),
+    ("get",      exactly(2, get)),
+    ("contains?", exactly(2, contains_q)),
+    ("keys",     exactly(1, keys)),
+    ("vals",     exactly(1, vals)),
+    ("set",      exactly(1, set)),
+    ("set?",     exactly(1, fn_is_type!(Set(_,_)))),
+    ("disj",     at_least(1, disj)),
+    ("union",    at_least(0, union)),
+    ("intersection", at_least(1, |a|{filter_set(a, |k,rest|{rest.iter().all(|hs| hs.contains(k))})})),
+    ("difference",   at_least(1, |a|{filter_set(a, |k,rest|{!rest.iter().any(|hs| hs.contains(k))})})),
+    ("subset?",  exactly(2, subset_q)),
+
+    ("cons",   exactly(2, cons)),
+    ("concat", at_least(0, concat)),
+    ("empty?", exactly(1, |a|{a[0].empty_q()})),
+    ("nth",    exactly(2, nth)),
+    ("first",  exactly(1, first)),
+    ("rest",   exactly(1, rest)),
+    ("count",  exactly(1, |a|{a[0].count()})),
+    ("apply",  at_least(2, apply)),
+    ("map",    at_least(2, map)),
+    ("filter", exactly(2, |a|{filter_with(a, true)})),
+    ("remove", exactly(2, |a|{filter_with(a, false)})),
+    ("reduce", between(2, 3, reduce)),
+    ("some",   exactly(2, some)),
+    ("every?", exactly(2, every_q)),
+    ("sort",   between(1, 2, sort)),
+    ("sort-by", between(2, 3, sort_by)),
+    ("group-by", exactly(2, group_by)),
+    ("frequencies", exactly(1, frequencies)),
+    ("partition",   between(2, 4, partition)),
+    ("interleave",  at_least(0, interleave)),
+    ("distinct",    exactly(1, distinct)),
+    ("reverse", exactly(1, |a|{let mut v = a[0].seq_vals()?; v.reverse(); Ok(list!(v))})),
+    ("last",    exactly(1, |a|{Ok(a[0].seq_vals()?.pop().unwrap_or(Nil))})),
+    ("butlast", exactly(1, butlast)),
+    ("into",    exactly(2, into)),
+    ("zipmap",  exactly(2, zipmap)),
+
+    ("lazy-seq*",  exactly(1, |a|{let f = a[0].clone(); Ok(lazy_seq(move || f.apply(vec![])))})),
+    ("range",      between(0, 3, range)),
+    ("iterate",    exactly(2, |a|{Ok(iterate_from(a[0].clone(), a[1].clone()))})),
+    ("repeat",     between(1, 2, repeat)),
+    ("cycle",      exactly(1, |a|{Ok(cycle_from(a[0].clone(), a[0].clone()))})),
+    ("take",       exactly(2, |a|{Ok(take_seq(int_arg(&a[0], "take")?, a[1].clone()))})),
+    ("drop",       exactly(2, |a|{Ok(drop_seq(int_arg(&a[0], "drop")?, a[1].clone()))})),
+    ("take-while", exactly(2, |a|{Ok(take_while_seq(a[0].clone(), a[1].clone()))})),
+    ("drop-while", exactly(2, |a|{Ok(drop_while_seq(a[0].clone(), a[1].clone()))})),
+    ("doall",      exactly(1, |a|{dorun(&a[0])?; Ok(a[0].clone())})),
+    ("dorun",      exactly(1, |a|{dorun(&a[0])?; Ok(Nil)})),
+
+    ("conj",   at_least(1, conj)),
+    ("seq",    exactly(1, seq)),
+
+    ("meta",   exactly(1, |a|{a[0].get_meta()})),
+    ("with-meta", exactly(2, |a|{a[0].clone().with_meta(&a[1])})),
+    ("atom",   exactly(1, |a|{Ok(atom(&a[0]))})),
+    ("atom?",  exactly(1, fn_is_type!(Atom(_)))),
+    ("deref",  exactly(1, |a|{a[0].deref()})),
+    ("reset!", exactly(2, |a|{a[0].reset_bang(&a[1])})),
+    ("swap!",  at_least(2, |a|{a[0].swap_bang(&a[1..].to_vec())})),
+  ];
+  ns.into_iter().map(|(k, v)| (k, v.named(&Sym(k.to_string())))).collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use interpreter::Interpreter;
+
+  fn pr(r: MalRet) -> String {
+    let v = r.expect("a value");
+    v.realize().expect("a realized value");
+    v.pr_str(true)
+  }
+
+  // src evaluated with the whole core and prelude, printed
+  fn rep(src: &str) -> String {
+    match Interpreter::new().eval_str(src).and_then(|v| { v.realize()?; Ok(v) }) {
+      Ok(v)  => v.pr_str(true),
+      Err(e) => format!("error: {}", e),
+    }
+  }
+
+  #[test]
+  fn floats_mix_with_ints() {
+    assert_eq!(rep("(+ 1 1.5)"), "2.5");
+    assert_eq!(rep("(* 2 0.5)"), "1.0");
+    assert_eq!(rep("(/ 7.0 2)"), "3.5");
+    assert_eq!(rep("(< 1 1.5)"), "true");
+    assert_eq!(rep("(= 1 1.0)"), "true");
+    assert_eq!(rep("(= [1] [1.0])"), "true");
+  }
+
+  #[test]
+  fn range_takes_any_numbers() {
+    assert_eq!(rep("(range 0 1 0.25)"), "(0 0.25 0.5 0.75)");
+    assert_eq!(rep("(range 1.5 4)"), "(1.5 2.5 3.5)");
+    assert_eq!(rep("(range 0 1 1/3)"), "(0 1/3 2/3)");
+    assert_eq!(rep("(range 1 -1 -0.5)"), "(1 0.5 0.0 -0.5)");
+    assert_eq!(rep("(range 3)"), "(0 1 2)");
+    assert!(rep("(range \"a\")").starts_with("error: range expects a number"));
+  }
+
+  #[test]
+  fn json_integers_read_as_ints() {
+    assert_eq!(rep(r#"(json/read-str "[-0, 0, -0.0, 2.0]")"#), "[0 0 -0.0 2.0]");
+  }
+
+  #[test]
+  fn math_functions() {
+    assert_eq!(rep("[(floor 2.5) (ceil -2.5) (floor 3)]"), "[2.0 -2.0 3]");
+    assert_eq!(rep("(sqrt 16)"), "4.0");
+    assert_eq!(rep("(pow 2 0.5)"), "1.4142135623730951");
+    assert_eq!(rep("[(quot 7 -2) (rem -7 2) (mod -7 2)]"), "[-3 -1 1]");
+    assert_eq!(rep("(mod 7.5 2)"), "1.5");
+  }
+
+  #[test]
+  fn integer_overflow_promotes_to_a_bignum() {
+    assert_eq!(rep("(+ 9223372036854775807 1)"), "9223372036854775808");
+    assert_eq!(rep("(- -9223372036854775808 1)"), "-9223372036854775809");
+    assert_eq!(rep("(* 3037000500 3037000500)"), "9223372037000250000");
+    assert_eq!(rep("(def! fact (fn* (n) (if (<= n 1) 1 (* n (fact (- n 1)))))) (fact 25)"),
+               "15511210043330985984000000");
+    // and back down when the result fits
+    assert_eq!(rep("(- (+ 9223372036854775807 1) 1)"), "9223372036854775807");
+    assert_eq!(rep("(< 9223372036854775807 9223372036854775808)"), "true");
+  }
+
+  #[test]
+  fn division_by_zero_is_a_catchable_error() {
+    assert_eq!(rep("(/ 1 0)"), "error: division by zero at <input>:1:1");
+    assert_eq!(rep("(try* (quot 1 0) (catch* e e))"), "\"division by zero\"");
+    assert_eq!(rep("(try* (mod (* 9223372036854775807 2) 0) (catch* e e))"), "\"division by zero\"");
+  }
+
+  #[test]
+  fn exact_division_gives_ratios() {
+    assert_eq!(rep("(/ 6 4)"), "3/2");
+    assert_eq!(rep("(/ 6 3)"), "2");
+    assert_eq!(rep("(+ 1/3 2/3)"), "1");
+    assert_eq!(rep("(* 1/2 1.0)"), "0.5");
+    assert_eq!(rep("[(< 1/3 0.5) (= 1/2 0.5) (= 2/4 1/2)]"), "[true true true]");
+    assert_eq!(rep("(/ (+ 9223372036854775807 1) 3)"), "9223372036854775808/3");
+  }
+
+  #[test]
+  fn ratio_conversions() {
+    assert_eq!(rep("[(numerator 3/4) (denominator 3/4)]"), "[3 4]");
+    assert_eq!(rep("[(numerator 5) (denominator 5)]"), "[5 1]");
+    assert_eq!(rep("(double 1/4)"), "0.25");
+    assert_eq!(rep("[(int 7/2) (int -7/2)]"), "[3 -3]");
+  }
+
+  #[test]
+  fn maps_with_keys_of_any_type() {
+    assert_eq!(rep("(get {[1 2] :x} '(1 2))"), ":x");
+    assert_eq!(rep("(get {1 :one \"1\" :str} 1)"), ":one");
+    assert_eq!(rep("[(contains? {nil 1} nil) (contains? {nil 1} false)]"), "[true false]");
+    assert_eq!(rep("(keys (dissoc {1 2 3 4} 1))"), "(3)");
+    assert_eq!(rep("(get (assoc {} 'sym 1 :kw 2) 'sym)"), "1");
+    assert_eq!(rep("(= {1 2 [3] 4} (hash-map [3] 4 1 2))"), "true");
+    assert_eq!(rep("{[1] nil}"), "{[1] nil}");
+    assert_eq!(rep("(assoc {} 1.5 :f)"), "error: 1.5 cannot be a hash map key at <input>:1:1");
+  }
+
+  #[test]
+  fn keywords_have_their_own_type() {
+    assert_eq!(rep("[(keyword? :a) (string? :a) (keyword? \"\u{29e}a\") (string? \"\u{29e}a\")]"),
+               "[true false false true]");
+    assert_eq!(rep("[(keyword \"a\") (= :a (keyword \"a\")) (= :a \"a\")]"), "[:a true false]");
+    assert_eq!(rep("(count (seq \"\u{29e}ab\"))"), "3");
+  }
+
+  #[test]
+  fn keywords_and_maps_are_callable() {
+    assert_eq!(rep("(:name {:name \"n\"})"), "\"n\"");
+    assert_eq!(rep("({:name \"n\"} :name)"), "\"n\"");
+    assert_eq!(rep("[(:missing {} 42) ({} :missing 42) (:a nil)]"), "[42 42 nil]");
+    assert_eq!(rep("(map :a [{:a 1} {:a 2}])"), "(1 2)");
+  }
+
+  #[test]
+  fn sets() {
+    assert_eq!(rep("#{(+ 1 2)}"), "#{3}");
+    assert_eq!(rep("[(conj #{1} 2 1) (disj #{1 2 3} 2 4) (set [1 1])]"), "[#{1 2} #{1 3} #{1}]");
+    assert_eq!(rep("[(contains? #{[1]} (list 1)) (contains? #{1} 2) (set? #{}) (count #{1 2})]"),
+               "[true false true 2]");
+    assert_eq!(rep("[(#{1 2} 1) (#{1 2} 3) (get #{1} 1)]"), "[1 nil 1]");
+    assert_eq!(rep("[(= #{1 2} #{2 1}) (= #{1} [1])]"), "[true false]");
+    assert_eq!(rep("(seq #{})"), "nil");
+  }
+
+  #[test]
+  fn updates_leave_the_original_alone() {
+    assert_eq!(rep("(def! v [1 2 3]) [(conj v 4) (rest v) (cons 0 v) v]"),
+               "[[1 2 3 4] (2 3) (0 1 2 3) [1 2 3]]");
+    assert_eq!(rep("(def! m {:a 1}) [(assoc m :b 2) (dissoc m :a) m]"), "[{:a 1 :b 2} {} {:a 1}]");
+    assert_eq!(rep("(def! big (reduce conj [] (range 10000))) [(count big) (nth big 9999) (count (conj big 1)) (count big)]"),
+               "[10000 9999 10001 10000]");
+  }
+
+  #[test]
+  fn infinite_lazy_seqs() {
+    assert_eq!(rep("(take 5 (range))"), "(0 1 2 3 4)");
+    assert_eq!(rep("(range 2 10 3)"), "(2 5 8)");
+    assert_eq!(rep("(take 3 (iterate (fn* (x) (* 2 x)) 1))"), "(1 2 4)");
+    assert_eq!(rep("[(take 4 (cycle [1 2])) (take 2 (repeat :x)) (drop 2 (range 5))]"),
+               "[(1 2 1 2) (:x :x) (2 3 4)]");
+    assert_eq!(rep("(take-while (fn* (x) (< x 3)) (range))"), "(0 1 2)");
+    assert_eq!(rep("(drop-while (fn* (x) (< x 3)) (range 6))"), "(3 4 5)");
+    assert_eq!(rep("[(first (map (fn* (x) (* x x)) (range))) (nth (filter (fn* (x) (= 0 (mod x 7))) (range)) 3)]"),
+               "[0 21]");
+    assert_eq!(rep("(def! fib (fn* (a b) (lazy-seq (cons a (fib b (+ a b)))))) (nth (fib 0 1) 90)"),
+               "2880067194370816120");
+  }
+
+  #[test]
+  fn printing_throws_what_realizing_throws() {
+    assert_eq!(rep("(try* (str (take 2 (map (fn* [x] (/ 1 x)) (range)))) (catch* e :caught))"), ":caught");
+    assert_eq!(rep("(try* (pr-str (lazy-seq (throw 1))) (catch* e e))"), "1");
+    assert_eq!(rep("(try* (prn [{:a (lazy-seq (throw 2))}]) (catch* e e))"), "2");
+    assert_eq!(rep("(str (take 2 (range)) [(map (fn* [x] (+ x 1)) [1])])"), "\"(0 1)[(2)]\"");
+    // an error message does not run what has not been realized yet
+    assert_eq!(rep("(subs (range) 0)"), "error: subs expects a string, got (...) at <input>:1:1");
+  }
+
+  #[test]
+  fn lazy_seqs_realize_on_demand() {
+    let mal = Interpreter::new();
+    mal.eval_str("(def! calls (atom 0)) (def! s (map (fn* (x) (do (swap! calls (fn* (n) (+ n 1))) x)) (range 10)))").unwrap();
+    assert_eq!(pr(mal.eval_str("@calls")), "0");
+    assert_eq!(pr(mal.eval_str("(first s)")), "0");
+    assert_eq!(pr(mal.eval_str("@calls")), "1");
+    assert_eq!(pr(mal.eval_str("[(dorun (take 3 s)) @calls]")), "[nil 3]");
+    assert_eq!(pr(mal.eval_str("[(doall s) @calls]")), "[(0 1 2 3 4 5 6 7 8 9) 10]");
+    assert_eq!(pr(mal.eval_str("[(count s) (nth s 4) (seq (lazy-seq nil)) (rest (take 1 s))]")), "[10 4 nil ()]");
+  }
+
+  #[test]
+  fn sequence_library() {
+    assert_eq!(rep("[(filter number? [1 :a 2]) (remove nil? [1 nil 2]) (reduce + 10 [1 2 3]) (reduce + [1 2])]"),
+               "[(1 2) (1 2) 16 3]");
+    assert_eq!(rep("[(some (fn* (x) (if (> x 1) (* 10 x))) [1 2 3]) (every? number? [1 2]) (every? number? [])]"),
+               "[20 true true]");
+    assert_eq!(rep("[(sort [3 1/2 2.5]) (sort [\"b\" \"a\"]) (sort-by count [\"ccc\" \"a\" \"bb\"])]"),
+               "[(1/2 2.5 3) (\"a\" \"b\") (\"a\" \"bb\" \"ccc\")]");
+    assert_eq!(rep("[(group-by count [\"a\" \"bb\" \"c\"]) (frequencies [:a :b :a])]"),
+               "[{1 [\"a\" \"c\"] 2 [\"bb\"]} {:a 2 :b 1}]");
+    assert_eq!(rep("[(partition 2 (range 5)) (interleave [1 2] [:a :b :c]) (interleave) (distinct [1 2 1 3])]"),
+               "[((0 1) (2 3)) (1 :a 2 :b) () (1 2 3)]");
+    assert_eq!(rep("[(reverse [1 2 3]) (last [1 2]) (last []) (butlast [1 2 3])]"), "[(3 2 1) 2 nil (1 2)]");
+    assert_eq!(rep("[(into [] '(1 2)) (into (list) [1 2]) (into #{} [1 1]) (into {} [[:a 1]])]"),
+               "[[1 2] (2 1) #{1} {:a 1}]");
+    assert_eq!(rep("(zipmap [:a :b :c] [1 2])"), "{:a 1 :b 2}");
+  }
+
+  #[test]
+  fn strings_count_characters_not_bytes() {
+    assert_eq!(rep("[(count \"h\u{e9}llo\") (count \"\u{1f642}a\") (count \"\")]"), "[5 2 0]");
+    assert_eq!(rep("[(subs \"h\u{e9}llo\" 1 3) (subs \"h\u{e9}llo\" 2)]"), "[\"\u{e9}l\" \"llo\"]");
+    assert_eq!(rep("(subs \"abc\" 2 5)"), "error: subs: index out of range at <input>:1:1");
+    assert_eq!(rep("[(index-of \"h\u{e9}llo\" \"l\") (index-of \"h\u{e9}llo\" \"z\")]"), "[2 nil]");
+    assert_eq!(rep("[(upper-case \"stra\u{df}e\") (lower-case \"\u{c0}B\")]"), "[\"STRASSE\" \"\u{e0}b\"]");
+  }
+
+  #[test]
+  fn string_functions() {
+    assert_eq!(rep("[(str/split \"a,b,,c\" \",\") (str/split \"a1b22c\" #\"\\d+\")]"),
+               "[[\"a\" \"b\" \"\" \"c\"] [\"a\" \"b\" \"c\"]]");
+    assert_eq!(rep("[(str/join [1 2]) (str/join \"-\" [1 2 3])]"), "[\"12\" \"1-2-3\"]");
+    assert_eq!(rep("[(str/replace \"aXbX\" \"X\" \"y\") (str/replace \"a1b22\" #\"\\d+\" \"#\")]"),
+               "[\"ayby\" \"a#b#\"]");
+    assert_eq!(rep("(str/trim \"  x \\n\")"), "\"x\"");
+    assert_eq!(rep("[(starts-with? \"h\u{e9}llo\" \"h\u{e9}\") (ends-with? \"ab\" \"a\") (includes? \"abc\" \"bc\")]"),
+               "[true false true]");
+    assert_eq!(rep("(format \"%5.2f|%s|%d|%%\" 3.14159 :k 42)"), "\" 3.14|:k|42|%\"");
+    assert_eq!(rep("(format \"%d\" \"x\")"), "error: format: \"x\" is not an integer at <input>:1:1");
+  }
+
+  #[test]
+  fn regex_functions() {
+    assert_eq!(rep(r#"[(re-find #"(\d)(\w)" "a1b") (re-find #"z" "abc")]"#), r#"[["1b" "1" "b"] nil]"#);
+    assert_eq!(rep(r#"[(re-seq #"\d" "a1b2") (re-seq #"(\d)x" "1x2x")]"#), r#"[("1" "2") (["1x" "1"] ["2x" "2"])]"#);
+    assert_eq!(rep(r#"(re-matches #"(a)(b)" "ab")"#), r#"["ab" "a" "b"]"#);
+    assert_eq!(rep(r#"[(re-pattern "a+") (regex? (re-pattern #"a")) (regex? "a")]"#), r#"[#"a+" true false]"#);
+    assert!(rep(r#"(re-pattern "(")"#).starts_with("error: invalid regex"));
+  }
+
+  #[test]
+  fn chars() {
+    assert_eq!(rep("[(str \\a \\space) (pr-str \\newline) (= \\a \"a\") (char? \\a) (char? \"a\")]"),
+               "[\"a \" \"\\\\newline\" false true false]");
+    assert_eq!(rep("[(int \\a) (char 955)]"), "[97 \\\u{3bb}]");
+    assert_eq!(rep("(char -1)"), "error: char: not a character code at <input>:1:1");
+  }
+
+  #[test]
+  fn bytes_and_binary_files() {
+    assert_eq!(rep("[(bytes [104 105]) (bytes \"hi\") (seq (bytes [1 2])) (count (bytes [1 2 3]))]"),
+               "[#<bytes 68 69> #<bytes 68 69> (1 2) 3]");
+    assert_eq!(rep("[(str/from-utf8 (bytes [104 105])) (bytes? (bytes [])) (= (bytes [1]) (bytes [1]))]"),
+               "[\"hi\" true true]");
+    assert_eq!(rep("[(first (bytes \"ab\")) (rest (bytes \"ab\")) (nth (bytes \"ab\") 1) (first (bytes []))]"),
+               "[97 (98) 98 nil]");
+    assert_eq!(rep("(nth (bytes \"ab\") 2)"), "error: nth: index out of range at <input>:1:1");
+    assert_eq!(rep("(bytes [256])"), "error: bytes: 256 is not a byte at <input>:1:1");
+    assert!(rep("(str/from-utf8 (bytes [255]))").starts_with("error: str/from-utf8: invalid utf-8"));
+
+    let path = ::std::env::temp_dir().join(format!("mal-bytes-{}.bin", ::std::process::id()));
+    let src = format!("(do (spit-bytes {:?} (bytes [0 1 255])) (slurp-bytes {:?}))", path, path);
+    assert_eq!(rep(&src), "#<bytes 00 01 ff>");
+    let _ = ::std::fs::remove_file(path);
+  }
+
+  #[test]
+  fn format_negative_integers_with_a_sign() {
+    let big = bignum(-(BigInt::from(1) << 70usize));
+    let fmt = Str("%x %x %o %d".to_string());
+    assert_eq!(pr(format(vec![fmt, Int(-255), big, Int(-8), Int(-8)])),
+               "\"-ff -400000000000000000 -10 -8\"");
+  }
+
+  #[test]
+  fn format_c_takes_a_char_or_its_code() {
+    assert_eq!(rep("[(format \"%c\" \\a) (format \"%c\" 97) (format \"%c\" \"a\") (format \"%3c\" \\a)]"),
+               "[\"a\" \"a\" \"a\" \"  a\"]");
+    assert_eq!(rep("(format \"%c\" 55296)"), "error: format: %c expects a character at <input>:1:1");
+    assert_eq!(rep("(format \"%c\" -1)"), "error: format: %c expects a character at <input>:1:1");
+  }
+
+  #[test]
+  fn format_bounds_width_and_precision() {
+    assert_eq!(rep("(format \"%99999999999999999999d\" 1)"),
+               "error: format: width larger than 10000 at <input>:1:1");
+    assert_eq!(rep("(format \"%.99999999999999999999f\" 1.0)"),
+               "error: format: precision larger than 10000 at <input>:1:1");
+    assert_eq!(rep("(count (format \"%10000d\" 1))"), "10000");
+  }
+
+  #[test]
+  fn pow_promotes_and_bounds_the_result() {
+    assert_eq!(pr(pow(vec![Int(2), Int(10)])), "1024");
+    assert_eq!(pr(pow(vec![Int(2), Int(100)])), "1267650600228229401496703205376");
+    assert_eq!(pr(pow(vec![Int(-1), Int(4000000001)])), "-1");
+    assert_eq!(pr(pow(vec![Int(0), Int(4000000000)])), "0");
+    assert!(pow(vec![Int(2), Int(4000000000)]).is_err());
+    assert!(pow(vec![bignum(BigInt::from(1) << 70usize), Int(1 << 20)]).is_err());
+  }
+
+  #[test]
+  fn strings_seq_as_strings_and_map_to_chars() {
+    let s = Str("h\u{e9}".to_string());
+    assert_eq!(pr(seq(vec![s.clone()])), "(\"h\" \"\u{e9}\")");
+    let chars = s.seq_vals().unwrap().into_iter().map(|c| char_of(vec![c]));
+    assert_eq!(chars.map(pr).collect::<Vec<_>>(), vec!["\\h", "\\\u{e9}"]);
+    assert_eq!(pr(char_of(vec![Int(10)])), "\\newline");
+  }
+
+  #[test]
+  fn re_matches_only_whole_strings() {
+    let re = read_str("#\"a|ab\"".to_string()).expect("a regex");
+    assert_eq!(pr(re_matches(vec![re.clone(), Str("ab".to_string())])), "\"ab\"");
+    assert_eq!(pr(re_matches(vec![re.clone(), Str("abc".to_string())])), "nil");
+    assert_eq!(pr(re_find(vec![re, Str("abc".to_string())])), "\"a\"");
+  }
+
+  #[test]
+  fn json_fns_share_the_serde_form() {
+    let read = |s: &str| read_str(s.to_string()).expect("a form");
+    let v = read("{\"a\" [1 nil \"k\" 0.5]}");
+    let json = pr(json_write_str(vec![v.clone()]));
+    assert_eq!(json, pr(to_json(vec![v.clone()])));
+    assert_eq!(pr(json_read_str(vec![json_write_str(vec![v.clone()]).unwrap()])), v.pr_str(true));
+    assert_eq!(pr(json_read_str(vec![Str("\":k\"".to_string())])), "\":k\"");
+  }
+
+  #[test]
+  fn json_is_plain_json() {
+    assert_eq!(rep("(json/write-str {:a 1})"), r#""{\"a\":1}""#);
+    assert_eq!(rep("(json/write-str [:k 'sym 1/2])"), r#""[\"k\",\"sym\",0.5]""#);
+    assert_eq!(rep("(json/write-str {:a [:b]} {:pretty? true})"), r#""{\n  \"a\": [\n    \"b\"\n  ]\n}""#);
+    let s = r#""{\"a\": {\"b\": [1, \"c\"]}}""#;
+    assert_eq!(rep(&format!("(json/write-str (json/read-str {} {{:keywordize? true}}))", s)),
+               r#""{\"a\":{\"b\":[1,\"c\"]}}""#);
+    assert_eq!(rep(r#"(json/read-str "12345678901234567890123")"#), "12345678901234567890123");
+  }
+
+  #[test]
+  fn json_options() {
+    let src = Str("{\"a\": {\"b\": [{\"c\": null}]}}".to_string());
+    let kw = read_str("{:keywordize? true}".to_string()).expect("a map");
+    assert_eq!(pr(json_read_str(vec![src, kw])), "{:a {:b [{:c nil}]}}");
+    let pretty = read_str("{:pretty? true}".to_string()).expect("a map");
+    assert_eq!(pr(json_write_str(vec![read_str("[1]".to_string()).unwrap(), pretty])), "\"[\\n  1\\n]\"");
+    assert!(json_read_str(vec![Str("{}".to_string()), Int(1)]).is_err());
+  }
+
+  #[test]
+  fn json_parse_errors_have_a_position() {
+    let e = json_read_str(vec![Str("[1,\n  2,,]".to_string())]).unwrap_err();
+    assert!(e.to_string().contains("line 2 column"), "{}", e);
+    assert!(json_write_str(vec![atom(&Nil)]).is_err());
+  }
 }
 
 // vim: ts=2:sw=2:expandtab
@@ -3,7 +3,7 @@ use std::cell::RefCell;
 //use std::collections::HashMap;
 use fnv::FnvHashMap;
 
-use types::{MalVal,MalRet,MalErr,error};
+use types::{MalVal,MalArgs,MalRet,MalErr,Arity,error,native_fn};
 use types::MalVal::{Nil,Sym,List,Vector};
 use types::MalErr::{ErrString};
 
@@ -22,20 +22,26 @@ pub fn env_new(outer: Option<Env>) -> Env {
   Rc::new(EnvStruct{data: RefCell::new(FnvHashMap::default()), outer: outer})
 }
 
-// TODO: mbinds and exprs as & types
+// TODO: mbinds as a & type
 pub fn env_bind(outer: Option<Env>, mbinds: MalVal,
-                exprs: Vec<MalVal>) -> Result<Env,MalErr> {
+                exprs: &[MalVal]) -> Result<Env,MalErr> {
   let env = env_new(outer);
   match mbinds {
     List(binds,_) | Vector(binds,_) => {
       for (i, b) in binds.iter().enumerate() {
         match b {
           Sym(s) if s == "&" => {
-            env_set(&env, binds[i+1].clone(), list!(exprs[i..].to_vec()))?;
+            match binds.iter().nth(i+1) {
+              Some(rest) => env_set(&env, rest.clone(), list!(exprs[i..].to_vec()))?,
+              None => return Err(ErrString("'&' must be followed by a parameter".to_string())),
+            };
             break;
           },
           _ => {
-            env_set(&env, b.clone(), exprs[i].clone())?;
+            match exprs.get(i) {
+              Some(e) => env_set(&env, b.clone(), e.clone())?,
+              None => return Err(ErrString(format!("wrong number of args ({}) passed to fn*", exprs.len()))),
+            };
           },
         }
       }
@@ -58,7 +64,7 @@ pub fn env_get(env: &Env, key: &MalVal) -> MalRet {
     Sym(ref s) => {
       match env_find(env, s) {
         Some(e) => Ok(e.data.borrow().get(s)
-                        .ok_or(ErrString(format!("'{}' not found", s)))?
+                        .ok_or_else(|| ErrString(format!("'{}' not found", s)))?
                         .clone()),
         _ => error(&format!("'{}' not found", s)),
       }
@@ -81,4 +87,14 @@ pub fn env_sets(env: &Env, key: &str, val: MalVal) {
   env.data.borrow_mut().insert(key.to_string(), val);
 }
 
+// binds a Rust closure as a named native function, e.g.
+//   let hits = Rc::new(Cell::new(0));
+//   env_defn(&env, "hit!", Arity::Exactly(0), move |_| {
+//     hits.set(hits.get() + 1); Ok(Int(hits.get()))
+//   });
+pub fn env_defn<F>(env: &Env, name: &str, arity: Arity, f: F)
+  where F: Fn(MalArgs) -> MalRet + 'static {
+  env_sets(env, name, native_fn(Some(name), arity, f));
+}
+
 // vim: ts=2:sw=2:expandtab
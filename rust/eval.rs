@@ -0,0 +1,310 @@
+use std::rc::Rc;
+use itertools::Itertools;
+
+use types::{MalVal,MalMap,MalArgs,MalRet,Arity,Frame,error,hash_set};
+use types::MalVal::{Nil,Bool,Sym,List,Vector,Hash,Set,Keyword,Func,MalFunc};
+use env::{Env,env_new,env_bind,env_find,env_get,env_set,env_sets};
+use reader;
+
+fn quasiquote(ast: &MalVal) -> MalVal {
+  match ast {
+    List(ref v,_) | Vector(ref v,_) if !v.is_empty() => {
+      let a0 = &v[0];
+      match a0 {
+        Sym(ref s) if s == "unquote" && v.len() == 2 => v[1].clone(),
+        _ => {
+          match a0 {
+            List(ref v0,_) | Vector(ref v0,_) if !v0.is_empty() => {
+              match v0[0] {
+                Sym(ref s) if s == "splice-unquote" && v0.len() == 2 => {
+                  list![Sym("concat".to_string()),
+                        v0[1].clone(),
+                        quasiquote(&list!(v.iter().skip(1).cloned().collect()))]
+                },
+                _ => {
+                  list![Sym("cons".to_string()),
+                        quasiquote(a0),
+                        quasiquote(&list!(v.iter().skip(1).cloned().collect()))]
+                },
+              }
+            },
+            _ => {
+              list![Sym("cons".to_string()),
+                    quasiquote(a0),
+                    quasiquote(&list!(v.iter().skip(1).cloned().collect()))]
+            }
+          }
+        }
+      }
+    },
+    _ => list![Sym("quote".to_string()), ast.clone()]
+  }
+}
+
+fn is_macro_call(ast: &MalVal, env: &Env) -> Option<(MalVal,MalArgs)> {
+  match ast {
+    List(v,_) if !v.is_empty() => {
+      match v[0] {
+        Sym(ref s) => {
+          match env_find(env, s) {
+            Some(e) => {
+              match env_get(&e, &v[0]) {
+                Ok(f @ MalFunc{is_macro: true, ..}) => {
+                  Some((f, v.iter().skip(1).cloned().collect()))
+                },
+                _ => None,
+              }
+            },
+            _ => None,
+          }
+        },
+        _ => None,
+      }
+    },
+    _ => None,
+  }
+}
+
+fn macroexpand(mut ast: MalVal, env: &Env) -> (bool, MalRet) {
+  let mut was_expanded = false;
+  while let Some((mf, args)) = is_macro_call(&ast, env) {
+    ast = match mf.apply(args) {
+      Err(e) => return (false, Err(e)),
+      Ok(a) => a,
+    };
+    was_expanded = true;
+  }
+  (was_expanded, Ok(ast))
+}
+
+fn eval_ast(ast: &MalVal, env: &Env) -> MalRet {
+  match ast {
+    Sym(_)  => Ok(env_get(env, ast)?),
+    List(v,_) => {
+      let mut lst: MalArgs = vec![];
+      for a in v.iter() { lst.push(eval(a.clone(), env.clone())?) }
+      Ok(list!(lst))
+    },
+    Vector(v,_) => {
+      let mut lst: MalArgs = vec![];
+      for a in v.iter() { lst.push(eval(a.clone(), env.clone())?) }
+      Ok(vector!(lst))
+    },
+    Hash(hm,_) => {
+      let mut new_hm = MalMap::default();
+      for (k,v) in hm.iter() {
+        new_hm.insert(k.clone(), eval(v.clone(), env.clone())?);
+      }
+      Ok(Hash(Rc::new(new_hm),Rc::new(Nil)))
+    },
+    Set(hs,_) => {
+      let mut lst: MalArgs = vec![];
+      for k in hs.iter() { lst.push(eval(k.val().clone(), env.clone())?) }
+      hash_set(lst)
+    },
+    _ => Ok(ast.clone()),
+  }
+}
+
+pub fn eval(mut ast: MalVal, env: Env) -> MalRet {
+  // ast is updated in place by tail calls, so on failure it is the innermost
+  // form, and call is the user function whose body it belongs to (a tail call
+  // replaces its caller's frame)
+  let mut call = None;
+  eval_form(&mut ast, env, &mut call).map_err(|e| {
+    let e = match reader::span_of(&ast) {
+      Some(span) => e.at(span),
+      None       => e,
+    };
+    match call {
+      // the span is only looked up once the call has failed
+      Some(frame) => e.called_from(Frame { span: reader::span_of(&frame.form), ..frame }),
+      None        => e,
+    }
+  })
+}
+
+// how many arguments each special form takes, checked before they are used
+fn special_form_arity(name: &str) -> Option<Arity> {
+  match name {
+    "def!" | "let*" | "defmacro!" | "fn*"           => Some(Arity::Exactly(2)),
+    "quote" | "quasiquote" | "macroexpand" | "eval" => Some(Arity::Exactly(1)),
+    "try*"                                          => Some(Arity::Between(1, 2)),
+    "if"                                            => Some(Arity::Between(2, 3)),
+    _                                               => None,
+  }
+}
+
+fn eval_form(ast: &mut MalVal, mut env: Env, call: &mut Option<Frame>) -> MalRet {
+  let ret: MalRet;
+
+  'tco: loop {
+
+  ret = match ast.clone() {
+    List(l,_) => {
+      match macroexpand(ast.clone(), &env) {
+        (true, Ok(new_ast)) => {
+          *ast = new_ast;
+          continue 'tco;
+        }
+        (_, Err(e)) => return Err(e),
+        _ => (),
+      }
+
+      if l.is_empty() { return Ok(ast.clone()); }
+      let a0 = &l[0];
+      if let Sym(ref a0sym) = *a0 {
+        match special_form_arity(a0sym) {
+          Some(arity) if !arity.accepts(l.len() - 1) => {
+            return error(&format!("wrong number of args ({}) passed to {}", l.len() - 1, a0sym));
+          },
+          _ => (),
+        }
+      }
+      match a0 {
+        Sym(ref a0sym) if a0sym == "def!" => {
+          env_set(&env, l[1].clone(), eval(l[2].clone(), env.clone())?.named(&l[1]))
+        },
+        Sym(ref a0sym) if a0sym == "let*" => {
+          env = env_new(Some(env.clone()));
+          let (a1, a2) = (l[1].clone(), l[2].clone());
+          match a1 {
+            List(ref binds,_) | Vector(ref binds,_) => {
+              for (b, e) in binds.iter().tuples() {
+                match b {
+                  Sym(_) => {
+                    let _ = env_set(&env, b.clone(),
+                                    eval(e.clone(), env.clone())?);
+                  },
+                  _ => { 
+                    return error("let* with non-Sym binding");
+                  }
+                }
+              }
+            },
+            _ => {
+              return error("let* with non-List bindings");
+            }
+          };
+          *ast = a2;
+          continue 'tco;
+        },
+        Sym(ref a0sym) if a0sym == "quote" => {
+          Ok(l[1].clone())
+        },
+        Sym(ref a0sym) if a0sym == "quasiquote" => {
+          *ast = quasiquote(&l[1]);
+          continue 'tco;
+        },
+        Sym(ref a0sym) if a0sym == "defmacro!" => {
+          let (a1, a2) = (l[1].clone(), l[2].clone());
+          let r = eval(a2, env.clone())?;
+          match r {
+            MalFunc{eval, ast, env, params, ..} => {
+              Ok(env_set(&env, a1.clone(),
+                 MalFunc{eval, ast: ast.clone(), env: env.clone(),
+                         params: params.clone(), is_macro: true,
+                         name: None, meta: Rc::new(Nil)}.named(&a1))?)
+            },
+              _ => error("set_macro on non-function"),
+          }
+        },
+        Sym(ref a0sym) if a0sym == "macroexpand" => {
+          match macroexpand(l[1].clone(), &env) {
+            (_, Ok(new_ast)) => Ok(new_ast),
+            (_, e) => return e,
+          }
+        },
+        Sym(ref a0sym) if a0sym == "try*" => {
+          match eval(l[1].clone(), env.clone()) {
+            Err(ref e) if l.len() >= 3 => {
+              let exc = e.exception();
+              match l[2].clone() {
+                List(c,_) if c.len() == 3 => {
+                  let catch_env = env_bind(Some(env.clone()),
+                                           list!(vec![c[1].clone()]),
+                                           &[exc])?;
+                  // the call stack the error escaped from, as data
+                  env_sets(&catch_env, "*trace*", e.trace());
+                  eval(c[2].clone(), catch_env)
+                },
+                _ => error("invalid catch block"),
+              }
+            },
+            res => res,
+          }
+        },
+        Sym(ref a0sym) if a0sym == "do" && l.len() == 1 => Ok(Nil),
+        Sym(ref a0sym) if a0sym == "do" => {
+          match eval_ast(&list!(l.iter().skip(1).take(l.len()-2).cloned().collect()), &env)? {
+            List(_,_) => {
+              *ast = l.last().unwrap_or(&Nil).clone();
+              continue 'tco;
+            },
+            _ => error("invalid do form"),
+          }
+        },
+        Sym(ref a0sym) if a0sym == "if" => {
+          let cond = eval(l[1].clone(), env.clone())?;
+          match cond {
+            Bool(false) | Nil if l.len() >= 4 => {
+              *ast = l[3].clone();
+              continue 'tco;
+            },
+            Bool(false) | Nil => Ok(Nil),
+            _ if l.len() >= 3 => {
+              *ast = l[2].clone();
+              continue 'tco;
+            },
+            _ => Ok(Nil)
+          }
+        },
+        Sym(ref a0sym) if a0sym == "fn*" => {
+          let (a1, a2) = (l[1].clone(), l[2].clone());
+          Ok(MalFunc{eval, ast: Rc::new(a2), env,
+                     params: Rc::new(a1), is_macro: false,
+                     name: None, meta: Rc::new(Nil)})
+        },
+        Sym(ref a0sym) if a0sym == "eval" => {
+          *ast = eval(l[1].clone(), env.clone())?;
+          while let Some(ref e) = env.clone().outer {
+            env = e.clone();
+          }
+          continue 'tco;
+        },
+        _ => {
+          match eval_ast(ast, &env)? {
+            List(ref el,_) => {
+              let f = &el[0];
+              let args: MalArgs = el.iter().skip(1).cloned().collect();
+              match f {
+                Func(_,_) | Keyword(_) | Hash(_,_) | Set(_,_) => f.apply(args),
+                MalFunc{ast: mast, env: menv, params, name, ..} => {
+                  let a = &**mast;
+                  let p = &**params;
+                  env = env_bind(Some(menv.clone()), p.clone(), &args)?;
+                  *call = Some(Frame { name: name.clone(), form: ast.clone(), span: None });
+                  *ast = a.clone();
+                  continue 'tco;
+                },
+                _ => error("attempt to call non-function"),
+              }
+            },
+            _ => {
+              error("expected a list")
+            }
+          }
+        }
+      }
+    },
+    _ => eval_ast(ast, &env),
+  };
+
+  break;
+
+  } // end 'tco loop
+
+  ret
+}
+
+// vim: ts=2:sw=2:expandtab
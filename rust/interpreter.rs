@@ -0,0 +1,231 @@
+use std::fs;
+use std::path::Path;
+use std::rc::{Rc,Weak};
+
+use types::{MalVal,MalArgs,MalRet,MalErr,Arity,error};
+use types::MalVal::{Nil,Str,Sym,List};
+use types::MalErr::ErrString;
+use env::{Env,EnvStruct,env_new,env_get,env_sets,env_defn};
+use reader::read_all_from;
+use eval::eval;
+use convert::{FromMal,IntoMal};
+use core;
+
+// core.mal: defined using the language itself
+const PRELUDE: &str = r#"
+(def! *host-language* "rust")
+(def! not (fn* (a) (if a false true)))
+(defmacro! cond (fn* (& xs) (if (> (count xs) 0) (list 'if (first xs) (if (> (count xs) 1) (nth xs 1) (throw "odd number of forms to cond")) (cons 'cond (rest (rest xs)))))))
+(defmacro! lazy-seq (fn* (& body) `(lazy-seq* (fn* () (do ~@body)))))
+(def! *gensym-counter* (atom 0))
+(def! gensym (fn* [] (symbol (str "G__" (swap! *gensym-counter* (fn* [x] (+ 1 x)))))))
+(defmacro! or (fn* (& xs) (if (empty? xs) nil (if (= 1 (count xs)) (first xs) (let* (condvar (gensym)) `(let* (~condvar ~(first xs)) (if ~condvar ~condvar (or ~@(rest xs)))))))))
+"#;
+
+// A Mal interpreter with its own global environment, for embedding Mal as a
+// scripting language. Values are Rc based, so it stays on one thread.
+pub struct Interpreter {
+  env: Env,
+}
+
+impl Interpreter {
+  // the core functions and the prelude, with an empty *ARGV*
+  pub fn new() -> Interpreter {
+    let env = env_new(None);
+    for (k, v) in core::ns() {
+      env_sets(&env, k, v);
+    }
+    env_sets(&env, "*ARGV*", list![]);
+    // native, so that what it reads is located in the file itself; the Weak
+    // keeps the environment from owning itself
+    let genv: Weak<EnvStruct> = Rc::downgrade(&env);
+    env_defn(&env, "load-file", Arity::Exactly(1), move |a| {
+      match (genv.upgrade(), &a[0]) {
+        (Some(env), Str(f)) => load(&env, Path::new(f)),
+        (None, _) => error("load-file: the interpreter is gone"),
+        (_, f) => error(&format!("load-file expects a file name, got {}", f.pr_str(true))),
+      }
+    });
+    let mal = Interpreter { env };
+    eval_forms(&mal.env, read_all_from(PRELUDE.to_string(), "<prelude>").expect("the prelude reads"))
+      .expect("the prelude evaluates");
+    mal
+  }
+
+  // every form in src, returning the value of the last one (nil if none)
+  pub fn eval_str(&self, src: &str) -> MalRet {
+    eval_forms(&self.env, read_all_from(src.to_string(), "<input>")?)
+  }
+
+  pub fn load_file<P: AsRef<Path>>(&self, path: P) -> MalRet {
+    load(&self.env, path.as_ref())
+  }
+
+  // def! a global
+  pub fn define<T: IntoMal>(&self, name: &str, val: T) -> Result<(),MalErr> {
+    env_sets(&self.env, name, val.into_mal()?);
+    Ok(())
+  }
+
+  // a global, converted to T
+  pub fn lookup<T: FromMal>(&self, name: &str) -> Result<T,MalErr> {
+    T::from_mal(&env_get(&self.env, &Sym(name.to_string()))?)
+  }
+
+  // a global native function, which can capture state from the host
+  pub fn register_fn<F>(&self, name: &str, arity: Arity, f: F)
+    where F: Fn(MalArgs) -> MalRet + 'static {
+    env_defn(&self.env, name, arity, f);
+  }
+
+  // the global environment, for anything the methods above don't cover
+  pub fn env(&self) -> &Env {
+    &self.env
+  }
+}
+
+fn eval_forms(env: &Env, forms: Vec<MalVal>) -> MalRet {
+  let mut ret = Nil;
+  for form in forms {
+    ret = eval(form, env.clone())?;
+  }
+  Ok(ret)
+}
+
+fn load(env: &Env, path: &Path) -> MalRet {
+  let src = fs::read_to_string(path)
+    .map_err(|e| ErrString(format!("{}: {}", path.display(), e)))?;
+  eval_forms(env, read_all_from(src, &path.to_string_lossy())?)
+}
+
+impl Default for Interpreter {
+  fn default() -> Interpreter {
+    Interpreter::new()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::cell::Cell;
+  use std::collections::HashMap;
+  use types::native_fn;
+
+  #[test]
+  fn wrong_arities_are_errors_not_panics() {
+    let mal = Interpreter::new();
+    assert!(mal.eval_str("(first)").is_err());
+    assert!(mal.eval_str("(+ 1)").is_err());
+    assert!(mal.eval_str("(nth [1])").is_err());
+    assert!(mal.eval_str("(def!)").is_err());
+    assert!(mal.eval_str("(if)").is_err());
+    assert!(mal.eval_str("(try* (throw 1) (catch* e))").is_err());
+    assert_eq!(mal.eval_str("()").ok().map(|v| v.pr_str(true)), Some("()".to_string()));
+  }
+
+  fn pr(ret: MalRet) -> String {
+    ret.and_then(|v| { v.realize()?; Ok(v.pr_str(true)) }).unwrap_or_else(|e| format!("error: {}", e))
+  }
+
+  #[test]
+  fn eval_str_returns_the_last_form() {
+    let mal = Interpreter::new();
+    assert_eq!(pr(mal.eval_str("(def! x 2) (* x 21)")), "42");
+    assert_eq!(pr(mal.eval_str("")), "nil");
+    assert_eq!(pr(mal.eval_str("*host-language*")), "\"rust\"");
+  }
+
+  #[test]
+  fn eval_str_errors() {
+    let mal = Interpreter::new();
+    assert!(mal.eval_str("(+ 1").is_err());
+    assert!(mal.eval_str("undefined-thing").is_err());
+    assert!(mal.eval_str("(throw {:a 1})").is_err());
+    assert!(mal.load_file("/no/such/file.mal").is_err());
+    // the interpreter is still usable afterwards
+    assert_eq!(pr(mal.eval_str("(+ 1 2)")), "3");
+  }
+
+  #[test]
+  fn define_and_lookup_convert_values() {
+    let mal = Interpreter::new();
+    mal.define("n", 7u8).unwrap();
+    mal.define("names", vec!["a", "b"]).unwrap();
+    mal.define("missing", None::<i64>).unwrap();
+    let mut hm = HashMap::new();
+    hm.insert("k".to_string(), 1.5);
+    mal.define("hm", hm).unwrap();
+    assert_eq!(pr(mal.eval_str("[n names missing (get hm \"k\")]")), "[7 [\"a\" \"b\"] nil 1.5]");
+
+    assert_eq!(mal.lookup::<i32>("n").ok(), Some(7));
+    assert_eq!(mal.lookup::<Vec<String>>("names").ok(), Some(vec!["a".to_string(), "b".to_string()]));
+    assert_eq!(mal.lookup::<Option<i64>>("missing").ok(), Some(None));
+    assert!(mal.lookup::<String>("n").is_err());
+    assert!(mal.lookup::<i64>("not-defined").is_err());
+    mal.eval_str("(def! big 100000000000)").unwrap();
+    assert!(mal.lookup::<i32>("big").is_err());
+  }
+
+  // a host type that turns into a Mal function, which can't be a map key
+  #[derive(PartialEq,Eq,Hash)]
+  struct Handler;
+
+  impl IntoMal for Handler {
+    fn into_mal(self) -> MalRet {
+      Ok(native_fn(None, Arity::Exactly(0), |_| Ok(Nil)))
+    }
+  }
+
+  #[test]
+  fn define_refuses_unhashable_keys() {
+    let mal = Interpreter::new();
+    let mut hm = HashMap::new();
+    hm.insert(Handler, 1);
+    assert!(mal.define("hm", hm).is_err());
+    assert!(mal.lookup::<MalVal>("hm").is_err());
+  }
+
+  #[test]
+  fn register_fn_calls_back_into_rust() {
+    let mal = Interpreter::new();
+    let calls = Rc::new(Cell::new(0));
+    let c = calls.clone();
+    mal.register_fn("twice", Arity::Exactly(1), move |a| {
+      c.set(c.get() + 1);
+      let n: i64 = FromMal::from_mal(&a[0])?;
+      (n * 2).into_mal()
+    });
+    assert_eq!(pr(mal.eval_str("(map twice [1 2 3])")), "(2 4 6)");
+    assert_eq!(calls.get(), 3);
+    assert!(mal.eval_str("(twice)").is_err());
+    assert!(mal.eval_str("(twice \"x\")").is_err());
+  }
+
+  #[test]
+  fn uncaught_errors_carry_the_mal_call_stack() {
+    let mal = Interpreter::new();
+    mal.eval_str("(def! g (fn* (x) (throw {:code x})))\n(def! f (fn* (x) (+ 1 (g x))))").unwrap();
+    assert_eq!(pr(mal.eval_str("(f 7)")),
+               "error: {:code 7} at <input>:1:18\n  in g (g x) at <input>:2:23\n  in f (f 7) at <input>:1:1");
+    // innermost call first, as data for catch*
+    assert_eq!(pr(mal.eval_str("(try* (f 7) (catch* e [e (map (fn* (fr) (get fr :name)) *trace*)]))")),
+               "[{:code 7} (\"g\" \"f\")]");
+    assert_eq!(pr(mal.eval_str("(get (first (try* (f 7) (catch* e *trace*))) :form)")), "(g x)");
+    assert_eq!(pr(mal.eval_str("(try* (throw 1) (catch* e *trace*))")), "()");
+  }
+
+  #[test]
+  fn load_file_locates_errors_in_the_file() {
+    let path = ::std::env::temp_dir().join("mal-load-file-test.mal");
+    fs::write(&path, "(def! x 1)\n  (undefined-thing)").unwrap();
+    let mal = Interpreter::new();
+    let expected = format!("error: 'undefined-thing' not found at {}:2:3", path.display());
+    assert_eq!(pr(mal.load_file(&path)), expected);
+    let src = format!("(load-file \"{}\")", path.display());
+    assert!(pr(mal.eval_str(&src)).starts_with(&expected));
+    assert_eq!(pr(mal.eval_str("x")), "1");
+    fs::remove_file(&path).unwrap();
+  }
+}
+
+// vim: ts=2:sw=2:expandtab
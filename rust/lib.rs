@@ -0,0 +1,52 @@
+//! Mal as a library, for programs that embed it as a scripting language:
+//!
+//! ```
+//! # extern crate mal;
+//! # use mal::{Interpreter,FromMal,MalErr,Arity};
+//! # use mal::MalVal::Nil;
+//! # fn run() -> Result<(),MalErr> {
+//! let mal = Interpreter::new();
+//! mal.define("limit", 10)?;
+//! mal.register_fn("log", Arity::Exactly(1), |a| { println!("{}", a[0].pr_str(false)); Ok(Nil) });
+//! mal.eval_str("(def! big? (fn* (n) (> n limit)))")?;
+//! let big: bool = FromMal::from_mal(&mal.eval_str("(big? 12)")?)?;
+//! assert!(big);
+//! # Ok(())
+//! # }
+//! # fn main() { run().unwrap() }
+//! ```
+//!
+//! The stepN binaries build the interpreter up one stage at a time, each with
+//! its own eval on top of this crate's reader, printer, core and REPL;
+//! stepA_mal is the finished one, a REPL around `Interpreter`.
+
+#[macro_use]
+extern crate lazy_static;
+extern crate regex;
+extern crate itertools;
+extern crate fnv;
+extern crate im_rc;
+extern crate num_bigint;
+extern crate num_rational;
+extern crate num_traits;
+extern crate serde;
+extern crate serde_json;
+extern crate rustyline;
+
+#[macro_use]
+pub mod types;
+pub mod reader;
+pub mod printer;
+pub mod env;
+#[macro_use]
+pub mod core;
+mod eval;
+pub mod convert;
+mod interpreter;
+pub mod repl;
+
+pub use types::{MalVal,MalArgs,MalRet,MalErr,Arity};
+pub use convert::{FromMal,IntoMal};
+pub use interpreter::Interpreter;
+
+// vim: ts=2:sw=2:expandtab
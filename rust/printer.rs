@@ -1,5 +1,5 @@
 use types::MalVal;
-use types::MalVal::{Nil,Bool,Int,Str,Sym,List,Vector,Hash,Func,MalFunc,Atom};
+use types::MalVal::{Nil,Bool,Int,Bignum,Ratio,Float,Str,Char,Bytes,Keyword,Sym,Regex,List,Vector,Hash,Set,LazySeq,Func,MalFunc,Atom};
 
 fn escape_str(s: &str) -> String {
   s.chars().map(|c| {
@@ -12,6 +12,37 @@ fn escape_str(s: &str) -> String {
   }).collect::<Vec<String>>().join("")
 }
 
+// the reverse of reader::unescape_regex: a quote is written as \", and
+// whatever else is escaped stays as it is
+fn escape_regex(s: &str) -> String {
+  let mut out = String::new();
+  let mut chars = s.chars();
+  while let Some(c) = chars.next() {
+    match c {
+      '\\' => {
+        out.push(c);
+        out.extend(chars.next());
+      },
+      '"' => out.push_str("\\\""),
+      _ => out.push(c),
+    }
+  }
+  out
+}
+
+// characters that print by name, which the reader reads back
+fn char_name(c: char) -> Option<&'static str> {
+  match c {
+    '\n'      => Some("newline"),
+    ' '       => Some("space"),
+    '\t'      => Some("tab"),
+    '\r'      => Some("return"),
+    '\u{8}'   => Some("backspace"),
+    '\u{c}'   => Some("formfeed"),
+    _         => None,
+  }
+}
+
 impl MalVal {
   pub fn pr_str(&self, print_readably: bool) -> String {
     match self {
@@ -19,27 +50,76 @@ impl MalVal {
       Bool(true)  => String::from("true"),
       Bool(false) => String::from("false"),
       Int(i)      => format!("{}", i),
-      //Float(f)    => format!("{}", f),
+      Bignum(b)   => b.to_string(),
+      Ratio(r)    => r.to_string(),
+      Float(f)    => format!("{:?}", f), // keeps the ".0" that tells it from an Int
       Str(s)      => {
-        if s.starts_with("\u{29e}") {
-          format!(":{}", &s[2..])
-        } else if print_readably {
+        if print_readably {
           format!("\"{}\"", escape_str(s))
         } else {
           s.clone()
         }
       }
+      Char(c)     => {
+        if !print_readably {
+          c.to_string()
+        } else if let Some(name) = char_name(*c) {
+          format!("\\{}", name)
+        } else if c.is_control() {
+          format!("\\u{:04x}", *c as u32)
+        } else {
+          format!("\\{}", c)
+        }
+      },
+      Bytes(b)    => {
+        let hex: Vec<String> = b.iter().map(|b| format!("{:02x}", b)).collect();
+        format!("#<bytes {}>", hex.join(" "))
+      },
+      Keyword(k)  => format!(":{}", k),
       Sym(s)      => s.clone(),
+      Regex(r)    => {
+        if print_readably {
+          format!("#\"{}\"", escape_regex(r.as_str()))
+        } else {
+          r.as_str().to_string()
+        }
+      },
       List(l,_)   => pr_seq(&**l, print_readably, "(", ")", " "),
       Vector(l,_) => pr_seq(&**l, print_readably, "[", "]", " "),
       Hash(hm,_)  => {
         let l: Vec<MalVal> = hm
           .iter()
-          .flat_map(|(k, v)| { vec![Str(k.to_string()), v.clone()] })
+          .flat_map(|(k, v)| { vec![k.val().clone(), v.clone()] })
           .collect();
         pr_seq(&l, print_readably, "{", "}", " ")
       },
-      Func(f,_)   => format!("#<fn {:?}>", f),
+      Set(hs,_)   => {
+        let l: Vec<MalVal> = hs.iter().map(|k| { k.val().clone() }).collect();
+        pr_seq(&l, print_readably, "#{", "}", " ")
+      },
+      // whatever prints a value realizes it first (see MalVal::realize), so
+      // only a seq that has not been looked at yet, say in an error message,
+      // still has a pending part; that is shown as ... rather than run here
+      LazySeq(..) => {
+        let mut l = vec![];
+        let mut s = self.clone();
+        loop {
+          s = match s {
+            LazySeq(ref lazy,_) => match lazy.peek() {
+              Some(Some((x, rest))) => { l.push(x); rest },
+              Some(None) => break,
+              None => { l.push(Sym("...".to_string())); break },
+            },
+            List(ref r,_) | Vector(ref r,_) => { l.extend(r.iter().cloned()); break },
+            _ => break,
+          }
+        }
+        pr_seq(&l, print_readably, "(", ")", " ")
+      },
+      Func(f,_)   => match f.name {
+        Some(ref name) => format!("#<fn {}>", name),
+        None           => String::from("#<fn>"),
+      },
       MalFunc{ast: a, params: p, ..} => {
         format!("(fn* {} {})", p.pr_str(true), a.pr_str(true))
       },
@@ -48,10 +128,11 @@ impl MalVal {
   }
 }
 
-pub fn pr_seq(seq: &Vec<MalVal>, print_readably: bool,
-              start: &str, end: &str, join: &str) -> String {
+pub fn pr_seq<'a, I>(seq: I, print_readably: bool,
+                     start: &str, end: &str, join: &str) -> String
+  where I: IntoIterator<Item=&'a MalVal> {
   let strs: Vec<String> = seq
-    .iter()
+    .into_iter()
     .map(|x| x.pr_str(print_readably))
     .collect();
   format!("{}{}{}", start, strs.join(join), end)
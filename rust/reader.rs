@@ -1,41 +1,109 @@
-use std::rc::Rc;
+use std::rc::{Rc,Weak};
+use std::cell::RefCell;
+use fnv::FnvHashMap;
 use regex::{Regex,Captures};
 
-use types::{MalVal,MalRet,MalErr,error,hash_map};
-use types::MalVal::{Nil,Bool,Int,Str,Sym,List,Vector};
+use types::{MalVal,MalKey,MalSeq,MalSet,MalRet,MalErr,Span,error,hash_map,bignum,ratio,keyword,re_pattern};
+use types::MalVal::{Nil,Bool,Int,Float,Str,Char,Sym,List,Vector,Set};
 use types::MalErr::ErrString;
 
+#[derive(Debug, Clone)]
+struct Token {
+    text: String,
+    line: usize,
+    col: usize,
+}
+
 #[derive(Debug, Clone)]
 struct Reader {
-    tokens: Vec<String>,
+    tokens: Vec<Token>,
     pos: usize,
+    origin: Rc<String>,
 }
 
 impl Reader {
   fn next(&mut self) -> Result<String,MalErr> {
     self.pos = self.pos + 1;
     Ok(self.tokens.get(self.pos-1)
-         .ok_or(ErrString("underflow".to_string()))?.to_string())
+         .ok_or(ErrString("underflow".to_string()))?.text.to_string())
   }
   fn peek(&self) -> Result<String,MalErr> {
     Ok(self.tokens.get(self.pos)
-         .ok_or(ErrString("underflow".to_string()))?.to_string())
+         .ok_or(ErrString("underflow".to_string()))?.text.to_string())
+  }
+  // location of the next token (or of the last one at end of input)
+  fn span(&self) -> Span {
+    let (line, col) = match self.tokens.get(self.pos).or(self.tokens.last()) {
+      Some(t) => (t.line, t.col),
+      None    => (1, 1),
+    };
+    Span { origin: self.origin.clone(), line: line, col: col }
   }
 }
 
-fn tokenize(str: &str) -> Vec<String> {
+fn tokenize(str: &str) -> Vec<Token> {
   lazy_static! {
-      static ref RE: Regex = Regex::new(r###"[\s,]*(~@|[\[\]{}()'`~^@]|"(?:\\.|[^\\"])*"?|;.*|[^\s\[\]{}('"`,;)]+)"###).unwrap();
+      static ref RE: Regex = Regex::new(r###"[\s,]*(~@|#\{|[\[\]{}()'`~^@]|#?"(?:\\.|[^\\"])*"?|;.*|\\.[^\s\[\]{}('"`,;)]*|[^\s\[\]{}('"`,;)]+)"###).unwrap();
   }
 
   let mut res = vec![];
+  let (mut line, mut line_start, mut scanned) = (1, 0, 0);
   for cap in RE.captures_iter(str) {
-    if cap[1].starts_with(";") { continue }
-    res.push(String::from(&cap[1]));
+    let m = cap.get(1).unwrap();
+    for (i, c) in str[scanned..m.start()].char_indices() {
+      if c == '\n' {
+        line += 1;
+        line_start = scanned + i + 1;
+      }
+    }
+    scanned = m.start();
+    if m.as_str().starts_with(";") { continue }
+    res.push(Token {
+      text: m.as_str().to_string(),
+      line: line,
+      col: str[line_start..m.start()].chars().count() + 1,
+    });
   }
   res
 }
 
+thread_local! {
+  // where each list/vector read from source started, keyed by the address of
+  // its elements. The Weak keeps that address from being reused while the
+  // entry exists.
+  static SPANS: RefCell<FnvHashMap<usize, (Weak<MalSeq>, Span)>> =
+    RefCell::new(FnvHashMap::default());
+}
+
+fn record_span(seq: MalVal, span: Span) -> MalVal {
+  if let List(ref v,_) | Vector(ref v,_) = seq {
+    SPANS.with(|spans| {
+      let mut spans = spans.borrow_mut();
+      if spans.len() >= 1024 && spans.len().is_power_of_two() {
+        spans.retain(|_, &mut (ref v, _)| v.upgrade().is_some());
+      }
+      spans.insert(&**v as *const MalSeq as usize, (Rc::downgrade(v), span));
+    });
+  }
+  seq
+}
+
+// the location a list or vector was read from, if it came from the reader
+pub fn span_of(ast: &MalVal) -> Option<Span> {
+  match ast {
+    List(v,_) | Vector(v,_) => SPANS.with(|spans| {
+      spans.borrow()
+        .get(&(&**v as *const MalSeq as usize))
+        .map(|&(_, ref span)| span.clone())
+    }),
+    _ => None,
+  }
+}
+
+fn error_at(msg: &str, span: Span) -> MalRet {
+  Err(ErrString(msg.to_string()).at(span))
+}
+
 fn unescape_str(s: &str) -> String {
   lazy_static! {
     static ref RE: Regex = Regex::new(r#"\\(.)"#).unwrap();
@@ -45,10 +113,46 @@ fn unescape_str(s: &str) -> String {
   }).to_string()
 }
 
+// a regex literal is kept as written, except that \" stands for a quote,
+// which the regex syntax has no escape for
+fn unescape_regex(s: &str) -> String {
+  lazy_static! {
+    static ref RE: Regex = Regex::new(r#"\\(.)"#).unwrap();
+  }
+  RE.replace_all(&s, |caps: &Captures| {
+    if &caps[1] == "\"" { caps[1].to_string() } else { caps[0].to_string() }
+  }).to_string()
+}
+
+// \a, \newline, \u00e9 or \é
+fn read_char(name: &str) -> Option<char> {
+  let mut chars = name.chars();
+  match (chars.next(), chars.next()) {
+    (Some(c), None) => return Some(c),
+    (Some('u'), Some(_)) if name.len() == 5 => {
+      return u32::from_str_radix(&name[1..], 16).ok().and_then(::std::char::from_u32);
+    },
+    _ => (),
+  }
+  match name {
+    "newline"   => Some('\n'),
+    "space"     => Some(' '),
+    "tab"       => Some('\t'),
+    "return"    => Some('\r'),
+    "backspace" => Some('\u{8}'),
+    "formfeed"  => Some('\u{c}'),
+    _           => None,
+  }
+}
+
 fn read_atom(rdr: &mut Reader) -> MalRet {
   lazy_static! {
     static ref INT_RE: Regex = Regex::new(r"^-?[0-9]+$").unwrap();
+    static ref FLOAT_RE: Regex = Regex::new(r"^-?[0-9]+(\.[0-9]+)?([eE][-+]?[0-9]+)?$").unwrap();
+    static ref RATIO_RE: Regex = Regex::new(r"^-?[0-9]+/[0-9]+$").unwrap();
+    static ref REGEX_RE: Regex = Regex::new(r#"^#"(?:\\.|[^\\"])*"$"#).unwrap();
   }
+  let span = rdr.span();
   let token = rdr.next()?;
   match &token[..] {
     "nil"   => Ok(Nil),
@@ -56,15 +160,37 @@ fn read_atom(rdr: &mut Reader) -> MalRet {
     "true"  => Ok(Bool(true)),
     _       => {
       if INT_RE.is_match(&token) {
-        Ok(Int(token.parse().unwrap()))
+        match token.parse() {
+          Ok(i)  => Ok(Int(i)),
+          Err(_) => Ok(bignum(token.parse().unwrap())),
+        }
+      } else if FLOAT_RE.is_match(&token) {
+        Ok(Float(token.parse().unwrap()))
+      } else if RATIO_RE.is_match(&token) {
+        match token.parse() {
+          Ok(r)  => Ok(ratio(r)),
+          Err(_) => error_at("division by zero", span),
+        }
+      } else if token.starts_with("\\") {
+        match read_char(&token[1..]) {
+          Some(c) => Ok(Char(c)),
+          None    => error_at(&format!("invalid character literal {}", token), span),
+        }
+      } else if token.starts_with("#\"") {
+        // unlike a string, the pattern is kept as written: \d stays \d
+        if REGEX_RE.is_match(&token) {
+          re_pattern(&unescape_regex(&token[2..token.len()-1])).map_err(|e| e.at(span))
+        } else {
+          error_at("expected '\"', got EOF", span)
+        }
       } else if token.starts_with("\"") {
         if token.ends_with("\"") {
           Ok(Str(unescape_str(&token[1..token.len()-1])))
         } else {
-          error("expected '\"', got EOF")
+          error_at("expected '\"', got EOF", span)
         }
       } else if token.starts_with(":") {
-        Ok(Str(format!("\u{29e}{}", &token[1..])))
+        Ok(keyword(&token[1..]))
       } else {
         Ok(Sym(token.to_string()))
       }
@@ -72,21 +198,32 @@ fn read_atom(rdr: &mut Reader) -> MalRet {
   }
 }
 
-fn read_seq(rdr: &mut Reader, end: &str) -> MalRet {
+// #{...} reads like {...} but builds a set
+fn read_seq(rdr: &mut Reader, end: &str, set: bool) -> MalRet {
   let mut seq : Vec<MalVal> = vec![];
+  let start = rdr.span();
   rdr.next()?;
   loop {
     let token = match rdr.peek() {
       Ok(t) => t,
-      Err(_) => return error(&format!("expected '{}', got EOF", end))
+      Err(_) => return error_at(&format!("expected '{}', got EOF", end), start)
     };
     if token == end { break }
     seq.push(read_form(rdr)?)
   }
   let _ = rdr.next();
   match end {
-    ")" => Ok(list!(seq)),
-    "]" => Ok(vector!(seq)),
+    ")" => Ok(record_span(list!(seq), start)),
+    "]" => Ok(record_span(vector!(seq), start)),
+    "}" if set => {
+      let mut hs = MalSet::default();
+      for x in seq.iter() {
+        if hs.insert(MalKey::new(x)?).is_some() {
+          return error_at(&format!("duplicate set element {}", x.pr_str(true)), start);
+        }
+      }
+      Ok(Set(Rc::new(hs),Rc::new(Nil)))
+    },
     "}" => hash_map(seq),
     _   => error("read_seq unknown end value"),
   }
@@ -120,23 +257,112 @@ fn read_form(rdr: &mut Reader) -> MalRet {
       let _ = rdr.next();
       Ok(list![Sym("deref".to_string()), read_form(rdr)?])
     },
-    ")"  => error("unexpected ')'"),
-    "("  => read_seq(rdr, ")"),
-    "]"  => error("unexpected ']'"),
-    "["  => read_seq(rdr, "]"),
-    "}"  => error("unexpected '}'"),
-    "{"  => read_seq(rdr, "}"),
+    ")"  => error_at("unexpected ')'", rdr.span()),
+    "("  => read_seq(rdr, ")", false),
+    "]"  => error_at("unexpected ']'", rdr.span()),
+    "["  => read_seq(rdr, "]", false),
+    "}"  => error_at("unexpected '}'", rdr.span()),
+    "{"  => read_seq(rdr, "}", false),
+    "#{" => read_seq(rdr, "}", true),
     _    => read_atom(rdr),
   }
 }
 
 pub fn read_str(str: String) -> MalRet {
+  read_str_from(str, "<input>")
+}
+
+// like read_str, naming where the text came from (e.g. a file) for error locations
+pub fn read_str_from(str: String, origin: &str) -> MalRet {
   let tokens = tokenize(&str);
   //println!("tokens: {:?}", tokens);
   if tokens.len() == 0 {
     return error("no input");
   }
-  read_form(&mut Reader { pos: 0, tokens: tokens })
+  read_form(&mut Reader { pos: 0, tokens: tokens, origin: Rc::new(origin.to_string()) })
+}
+
+// every form in the text, in order, e.g. the contents of a file
+pub fn read_all_from(str: String, origin: &str) -> Result<Vec<MalVal>,MalErr> {
+  let tokens = tokenize(&str);
+  let mut rdr = Reader { pos: 0, tokens: tokens, origin: Rc::new(origin.to_string()) };
+  let mut forms = vec![];
+  while rdr.pos < rdr.tokens.len() {
+    forms.push(read_form(&mut rdr)?);
+  }
+  Ok(forms)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn read_pr(s: &str) -> String {
+    read_str(s.to_string()).expect("a form").pr_str(true)
+  }
+
+  #[test]
+  fn regex_literals_keep_their_escapes() {
+    assert_eq!(read_pr(r#"#"\d+""#), r#"#"\d+""#);
+    assert_eq!(read_pr(r#"#"a\\""#), r#"#"a\\""#);
+  }
+
+  #[test]
+  fn regex_literals_accept_an_escaped_quote() {
+    assert_eq!(read_pr(r#"#"a\"b""#), r#"#"a\"b""#);
+    match read_str(r#"#"a\"b""#.to_string()) {
+      Ok(MalVal::Regex(re)) => assert!(re.is_match("a\"b")),
+      _                     => panic!("expected a regex"),
+    }
+    assert!(read_str(r#"#"a\""#.to_string()).is_err());
+  }
+
+  #[test]
+  fn float_literals() {
+    assert_eq!(read_pr("1.5"), "1.5");
+    assert_eq!(read_pr("-0.25"), "-0.25");
+    assert_eq!(read_pr("1e3"), "1000.0");
+    assert_eq!(read_pr("2.0"), "2.0");
+    match read_str("1.5".to_string()) {
+      Ok(MalVal::Float(f)) => assert_eq!(f, 1.5),
+      _                    => panic!("expected a float"),
+    }
+  }
+
+  #[test]
+  fn ratio_literals_are_normalized() {
+    assert_eq!(read_pr("1/3"), "1/3");
+    assert_eq!(read_pr("-4/6"), "-2/3");
+    assert_eq!(read_pr("4/2"), "2");
+    assert_eq!(read_pr("/"), "/");
+  }
+
+  #[test]
+  fn keywords_are_not_strings() {
+    assert_eq!(read_pr(":a"), ":a");
+    assert_eq!(read_pr("\"\u{29e}a\""), "\"\u{29e}a\"");
+    match (read_str(":a".to_string()), read_str("\":a\"".to_string())) {
+      (Ok(MalVal::Keyword(k)), Ok(MalVal::Str(s))) => assert_eq!((k.as_str(), s.as_str()), ("a", ":a")),
+      _ => panic!("expected a keyword and a string"),
+    }
+  }
+
+  #[test]
+  fn set_literals() {
+    assert_eq!(read_pr("#{}"), "#{}");
+    assert_eq!(read_pr("#{(+ 1 2)}"), "#{(+ 1 2)}");
+    assert!(read_str("#{1 2 1}".to_string()).is_err());
+    assert!(read_str("#{1 2".to_string()).is_err());
+  }
+
+  #[test]
+  fn character_literals() {
+    assert_eq!(read_pr("[\\a \\space \\newline \\\u{3bb}]"), "[\\a \\space \\newline \\\u{3bb}]");
+    match read_str("\\space".to_string()) {
+      Ok(MalVal::Char(c)) => assert_eq!(c, ' '),
+      _                   => panic!("expected a character"),
+    }
+  }
 }
 
 // vim: ts=2:sw=2:expandtab
@@ -0,0 +1,65 @@
+use std::process;
+
+use rustyline::error::ReadlineError;
+use rustyline::Editor;
+
+use types::{MalVal,MalRet,MalErr};
+use reader::read_str;
+
+// What the stepN binaries have in common: each one only brings its own eval
+// and hands it to these.
+
+// read, eval with the step's evaluator, print
+pub fn rep<F>(str: &str, eval: F) -> Result<String,MalErr>
+  where F: FnOnce(MalVal) -> MalRet {
+  let ast = read_str(str.to_string())?;
+  let ret = eval(ast)?;
+  ret.realize()?;
+  Ok(ret.pr_str(true))
+}
+
+// Invoked with arguments: run the file through f and exit with its status
+pub fn run_file<T, F>(f: F) -> !
+  where F: FnOnce() -> Result<T,MalErr> {
+  match f() {
+    Ok(_)  => process::exit(0),
+    Err(e) => {
+      println!("Error: {}", e);
+      process::exit(1);
+    }
+  }
+}
+
+// main repl loop, printing what rep returns for each line
+pub fn repl<F>(mut rep: F)
+  where F: FnMut(&str) -> Result<String,MalErr> {
+  // `()` can be used when no completer is required
+  let mut rl = Editor::<()>::new();
+  if rl.load_history(".mal-history").is_err() {
+      println!("No previous history.");
+  }
+
+  loop {
+    let readline = rl.readline("user> ");
+    match readline {
+      Ok(line) => {
+        rl.add_history_entry(&line);
+        rl.save_history(".mal-history").unwrap();
+        if !line.is_empty() {
+          match rep(&line) {
+            Ok(out) => println!("{}", out),
+            Err(e)  => println!("Error: {}", e),
+          }
+        }
+      },
+      Err(ReadlineError::Interrupted) => continue,
+      Err(ReadlineError::Eof) => break,
+      Err(err) => {
+        println!("Error: {:?}", err);
+        break
+      }
+    }
+  }
+}
+
+// vim: ts=2:sw=2:expandtab
@@ -1,33 +1,10 @@
-extern crate rustyline;
+extern crate mal;
 
-use rustyline::error::ReadlineError;
-use rustyline::Editor;
+use mal::repl;
 
 fn main() {
-  // `()` can be used when no completer is required
-  let mut rl = Editor::<()>::new();
-  if rl.load_history(".mal-history").is_err() {
-      println!("No previous history.");
-  }
-
-  loop {
-    let readline = rl.readline("user> ");
-    match readline {
-      Ok(line) => {
-        rl.add_history_entry(&line);
-        rl.save_history(".mal-history").unwrap();
-        if line.len() > 0 {
-          println!("{}", line);
-          }
-      },
-      Err(ReadlineError::Interrupted) => continue,
-      Err(ReadlineError::Eof) => break,
-      Err(err) => {
-        println!("Error: {:?}", err);
-        break
-      }
-    }
-  }
+  // echo each line back
+  repl::repl(|line| Ok(line.to_string()));
 }
 
 // vim: ts=2:sw=2:expandtab
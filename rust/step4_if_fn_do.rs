@@ -1,33 +1,15 @@
 use std::rc::Rc;
 //use std::collections::HashMap;
-use fnv::FnvHashMap;
 use itertools::Itertools;
 
 #[macro_use]
-extern crate lazy_static;
-extern crate regex;
+extern crate mal;
 extern crate itertools;
-extern crate fnv;
 
-extern crate rustyline;
-use rustyline::error::ReadlineError;
-use rustyline::Editor;
-
-#[macro_use]
-mod types;
-use types::{MalVal,MalArgs,MalRet,MalErr,error,format_error};
-use types::MalVal::{Nil,Bool,Sym,List,Vector,Hash,MalFunc};
-mod reader;
-mod printer;
-mod env;
-use env::{Env,env_new,env_get,env_set,env_sets};
-#[macro_use]
-mod core;
-
-// read
-fn read(str: &str) -> MalRet {
-  reader::read_str(str.to_string())
-}
+use mal::types::{MalVal,MalMap,MalArgs,MalRet,MalErr,error,hash_set};
+use mal::types::MalVal::{Nil,Bool,Sym,List,Vector,Hash,Set,MalFunc};
+use mal::env::{Env,env_new,env_get,env_set,env_sets};
+use mal::{core,repl};
 
 // eval
 fn eval_ast(ast: &MalVal, env: &Env) -> MalRet {
@@ -44,12 +26,17 @@ fn eval_ast(ast: &MalVal, env: &Env) -> MalRet {
       Ok(vector!(lst))
     },
     Hash(hm,_) => {
-      let mut new_hm: FnvHashMap<String,MalVal> = FnvHashMap::default();
+      let mut new_hm = MalMap::default();
       for (k,v) in hm.iter() {
-        new_hm.insert(k.to_string(), eval(v.clone(), env.clone())?);
+        new_hm.insert(k.clone(), eval(v.clone(), env.clone())?);
       }
       Ok(Hash(Rc::new(new_hm),Rc::new(Nil)))
     },
+    Set(hs,_) => {
+      let mut lst: MalArgs = vec![];
+      for k in hs.iter() { lst.push(eval(k.val().clone(), env.clone())?) }
+      hash_set(lst)
+    },
     _ => Ok(ast.clone()),
   }
 }
@@ -87,7 +74,7 @@ fn eval(ast: MalVal, env: Env) -> MalRet {
           eval(a2, let_env)
         },
         Sym(ref a0sym) if a0sym == "do" => {
-          match eval_ast(&list!(l[1..].to_vec()), &env)? {
+          match eval_ast(&list!(l.iter().skip(1).cloned().collect()), &env)? {
             List(el,_) => Ok(el.last().unwrap_or(&Nil).clone()),
             _ => error("invalid do form"),
           }
@@ -109,13 +96,13 @@ fn eval(ast: MalVal, env: Env) -> MalRet {
           let (a1, a2) = (l[1].clone(), l[2].clone());
           Ok(MalFunc{eval: eval, ast: Rc::new(a2), env: env,
                      params: Rc::new(a1), is_macro: false,
-                     meta: Rc::new(Nil)})
+                     name: None, meta: Rc::new(Nil)})
         },
         _ => {
           match eval_ast(&ast, &env)? {
             List(ref el,_) => {
               let ref f = el[0].clone();
-              f.apply(el[1..].to_vec())
+              f.apply(el.iter().skip(1).cloned().collect())
             },
             _ => {
               error("expected a list")
@@ -128,24 +115,12 @@ fn eval(ast: MalVal, env: Env) -> MalRet {
   }
 }
 
-// print
-fn print(ast: &MalVal) -> String {
-  ast.pr_str(true)
-}
-
+// read, eval, print
 fn rep(str: &str, env: &Env) -> Result<String,MalErr> {
-  let ast = read(str)?;
-  let exp = eval(ast, env.clone())?;
-  Ok(print(&exp))
+  repl::rep(str, |ast| eval(ast, env.clone()))
 }
 
 fn main() {
-  // `()` can be used when no completer is required
-  let mut rl = Editor::<()>::new();
-  if rl.load_history(".mal-history").is_err() {
-      println!("No previous history.");
-  }
-
   // core.rs: defined using rust
   let repl_env = env_new(None);
   for (k, v) in core::ns() {
@@ -156,27 +131,7 @@ fn main() {
   let _ = rep("(def! not (fn* (a) (if a false true)))", &repl_env);
 
   // main repl loop
-  loop {
-    let readline = rl.readline("user> ");
-    match readline {
-      Ok(line) => {
-        rl.add_history_entry(&line);
-        rl.save_history(".mal-history").unwrap();
-        if line.len() > 0 {
-          match rep(&line, &repl_env) {
-            Ok(out) => println!("{}", out),
-            Err(e)  => println!("Error: {}", format_error(e)),
-          }
-        }
-      },
-      Err(ReadlineError::Interrupted) => continue,
-      Err(ReadlineError::Eof) => break,
-      Err(err) => {
-        println!("Error: {:?}", err);
-        break
-      }
-    }
-  }
+  repl::repl(|line| rep(line, &repl_env));
 }
 
 // vim: ts=2:sw=2:expandtab
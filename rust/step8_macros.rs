@@ -1,33 +1,15 @@
 use std::rc::Rc;
 //use std::collections::HashMap;
-use fnv::FnvHashMap;
 use itertools::Itertools;
 
 #[macro_use]
-extern crate lazy_static;
-extern crate regex;
+extern crate mal;
 extern crate itertools;
-extern crate fnv;
 
-extern crate rustyline;
-use rustyline::error::ReadlineError;
-use rustyline::Editor;
-
-#[macro_use]
-mod types;
-use types::{MalVal,MalArgs,MalRet,MalErr,error,format_error};
-use types::MalVal::{Nil,Bool,Str,Sym,List,Vector,Hash,Func,MalFunc};
-mod reader;
-mod printer;
-mod env;
-use env::{Env,env_new,env_bind,env_find,env_get,env_set,env_sets};
-#[macro_use]
-mod core;
-
-// read
-fn read(str: &str) -> MalRet {
-  reader::read_str(str.to_string())
-}
+use mal::types::{MalVal,MalMap,MalArgs,MalRet,MalErr,error,hash_set};
+use mal::types::MalVal::{Nil,Bool,Str,Sym,List,Vector,Hash,Set,Keyword,Func,MalFunc};
+use mal::env::{Env,env_new,env_bind,env_find,env_get,env_set,env_sets};
+use mal::{core,repl};
 
 // eval
 fn quasiquote(ast: &MalVal) -> MalVal {
@@ -43,19 +25,19 @@ fn quasiquote(ast: &MalVal) -> MalVal {
                 Sym(ref s) if s == "splice-unquote" => {
                   list![Sym("concat".to_string()),
                         v0[1].clone(),
-                        quasiquote(&list!(v[1..].to_vec()))]
+                        quasiquote(&list!(v.iter().skip(1).cloned().collect()))]
                 },
                 _ => {
                   list![Sym("cons".to_string()),
                         quasiquote(a0),
-                        quasiquote(&list!(v[1..].to_vec()))]
+                        quasiquote(&list!(v.iter().skip(1).cloned().collect()))]
                 },
               }
             },
             _ => {
               list![Sym("cons".to_string()),
                     quasiquote(a0),
-                    quasiquote(&list!(v[1..].to_vec()))]
+                    quasiquote(&list!(v.iter().skip(1).cloned().collect()))]
             }
           }
         }
@@ -74,7 +56,7 @@ fn is_macro_call(ast: &MalVal, env: &Env) -> Option<(MalVal,MalArgs)> {
             Some(e) => {
               match env_get(&e, &v[0]) {
                 Ok(f @ MalFunc{is_macro: true, ..}) => {
-                  Some((f, v[1..].to_vec()))
+                  Some((f, v.iter().skip(1).cloned().collect()))
                 },
                 _ => None,
               }
@@ -92,15 +74,13 @@ fn is_macro_call(ast: &MalVal, env: &Env) -> Option<(MalVal,MalArgs)> {
 fn macroexpand(mut ast: MalVal, env: &Env) -> (bool, MalRet) {
   let mut was_expanded = false;
   while let Some((mf, args)) = is_macro_call(&ast, env) {
-    //println!("macroexpand 1: {:?}", ast);
     ast = match mf.apply(args) {
       Err(e) => return (false, Err(e)),
       Ok(a) => a,
     };
-    //println!("macroexpand 2: {:?}", ast);
     was_expanded = true;
   }
-  ((was_expanded, Ok(ast)))
+  (was_expanded, Ok(ast))
 }
 
 fn eval_ast(ast: &MalVal, env: &Env) -> MalRet {
@@ -117,12 +97,17 @@ fn eval_ast(ast: &MalVal, env: &Env) -> MalRet {
       Ok(vector!(lst))
     },
     Hash(hm,_) => {
-      let mut new_hm: FnvHashMap<String,MalVal> = FnvHashMap::default();
+      let mut new_hm = MalMap::default();
       for (k,v) in hm.iter() {
-        new_hm.insert(k.to_string(), eval(v.clone(), env.clone())?);
+        new_hm.insert(k.clone(), eval(v.clone(), env.clone())?);
       }
       Ok(Hash(Rc::new(new_hm),Rc::new(Nil)))
     },
+    Set(hs,_) => {
+      let mut lst: MalArgs = vec![];
+      for k in hs.iter() { lst.push(eval(k.val().clone(), env.clone())?) }
+      hash_set(lst)
+    },
     _ => Ok(ast.clone()),
   }
 }
@@ -188,7 +173,7 @@ fn eval(mut ast: MalVal, mut env: Env) -> MalRet {
               Ok(env_set(&env, a1.clone(),
                  MalFunc{eval: eval, ast: ast.clone(), env: env.clone(),
                          params: params.clone(), is_macro: true,
-                         meta: Rc::new(Nil)})?)
+                         name: None, meta: Rc::new(Nil)})?)
             },
               _ => error("set_macro on non-function"),
           }
@@ -200,7 +185,7 @@ fn eval(mut ast: MalVal, mut env: Env) -> MalRet {
           }
         },
         Sym(ref a0sym) if a0sym == "do" => {
-          match eval_ast(&list!(l[1..l.len()-1].to_vec()), &env)? {
+          match eval_ast(&list!(l.iter().skip(1).take(l.len()-2).cloned().collect()), &env)? {
             List(_,_) => {
               ast = l.last().unwrap_or(&Nil).clone();
               continue 'tco;
@@ -227,7 +212,7 @@ fn eval(mut ast: MalVal, mut env: Env) -> MalRet {
           let (a1, a2) = (l[1].clone(), l[2].clone());
           Ok(MalFunc{eval: eval, ast: Rc::new(a2), env: env,
                      params: Rc::new(a1), is_macro: false,
-                     meta: Rc::new(Nil)})
+                     name: None, meta: Rc::new(Nil)})
         },
         Sym(ref a0sym) if a0sym == "eval" => {
           ast = eval(l[1].clone(), env.clone())?;
@@ -240,13 +225,13 @@ fn eval(mut ast: MalVal, mut env: Env) -> MalRet {
           match eval_ast(&ast, &env)? {
             List(ref el,_) => {
               let ref f = el[0].clone();
-              let args = el[1..].to_vec();
+              let args: MalArgs = el.iter().skip(1).cloned().collect();
               match f {
-                Func(_,_) => f.apply(args),
+                Func(_,_) | Keyword(_) | Hash(_,_) | Set(_,_) => f.apply(args),
                 MalFunc{ast: mast, env: menv, params, ..} => {
                   let a = &**mast;
                   let p = &**params;
-                  env = env_bind(Some(menv.clone()), p.clone(), args)?;
+                  env = env_bind(Some(menv.clone()), p.clone(), &args)?;
                   ast = a.clone();
                   continue 'tco;
                 },
@@ -270,27 +255,15 @@ fn eval(mut ast: MalVal, mut env: Env) -> MalRet {
   ret
 }
 
-// print
-fn print(ast: &MalVal) -> String {
-  ast.pr_str(true)
-}
-
+// read, eval, print
 fn rep(str: &str, env: &Env) -> Result<String,MalErr> {
-  let ast = read(str)?;
-  let exp = eval(ast, env.clone())?;
-  Ok(print(&exp))
+  repl::rep(str, |ast| eval(ast, env.clone()))
 }
 
 fn main() {
   let mut args = std::env::args();
   let arg1 = args.nth(1);
 
-  // `()` can be used when no completer is required
-  let mut rl = Editor::<()>::new();
-  if rl.load_history(".mal-history").is_err() {
-      println!("No previous history.");
-  }
-
   // core.rs: defined using rust
   let repl_env = env_new(None);
   for (k, v) in core::ns() {
@@ -300,44 +273,19 @@ fn main() {
 
   // core.mal: defined using the language itself
   let _ = rep("(def! not (fn* (a) (if a false true)))", &repl_env);
-  let _ = rep("(def! load-file (fn* (f) (eval (read-string (str \"(do \" (slurp f) \")\")))))", &repl_env);
+  let _ = rep("(def! load-file (fn* (f) (eval (read-string (str \"(do \" (slurp f) \")\") f))))", &repl_env);
   let _ = rep("(defmacro! cond (fn* (& xs) (if (> (count xs) 0) (list 'if (first xs) (if (> (count xs) 1) (nth xs 1) (throw \"odd number of forms to cond\")) (cons 'cond (rest (rest xs)))))))", &repl_env);
+  let _ = rep("(defmacro! lazy-seq (fn* (& body) `(lazy-seq* (fn* () (do ~@body)))))", &repl_env);
   let _ = rep("(defmacro! or (fn* (& xs) (if (empty? xs) nil (if (= 1 (count xs)) (first xs) `(let* (or_FIXME ~(first xs)) (if or_FIXME or_FIXME (or ~@(rest xs))))))))", &repl_env);
 
 
   // Invoked with arguments
   if let Some(f) = arg1 {
-    match rep(&format!("(load-file \"{}\")",f), &repl_env) {
-      Ok(_)  => std::process::exit(0),
-      Err(e) => {
-        println!("Error: {}", format_error(e));
-        std::process::exit(1);
-      }
-    }
+    repl::run_file(|| rep(&format!("(load-file \"{}\")",f), &repl_env));
   }
 
   // main repl loop
-  loop {
-    let readline = rl.readline("user> ");
-    match readline {
-      Ok(line) => {
-        rl.add_history_entry(&line);
-        rl.save_history(".mal-history").unwrap();
-        if line.len() > 0 {
-          match rep(&line, &repl_env) {
-            Ok(out) => println!("{}", out),
-            Err(e)  => println!("Error: {}", format_error(e)),
-          }
-        }
-      },
-      Err(ReadlineError::Interrupted) => continue,
-      Err(ReadlineError::Eof) => break,
-      Err(err) => {
-        println!("Error: {:?}", err);
-        break
-      }
-    }
-  }
+  repl::repl(|line| rep(line, &repl_env));
 }
 
 // vim: ts=2:sw=2:expandtab
@@ -1,11 +1,25 @@
 use std::rc::Rc;
 use std::cell::RefCell;
+use std::fmt;
+use std::hash::{self,Hasher};
+use std::iter::FromIterator;
+use std::mem;
+use std::ops::{Deref,Index};
+use std::slice;
 //use std::collections::HashMap;
-use fnv::FnvHashMap;
+use fnv::{FnvHashMap,FnvBuildHasher};
+use im_rc;
+use regex;
 use itertools::Itertools;
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::{One,Zero,ToPrimitive};
+use serde::{Serialize,Serializer,Deserialize,Deserializer};
+use serde::de::{self,Visitor,SeqAccess,MapAccess,DeserializeSeed};
+use serde::ser::{Error as SerError,SerializeMap};
 
-use types::MalErr::{ErrString,ErrMalVal};
-use types::MalVal::{Nil,Bool,Int,Str,Sym,List,Vector,Hash,Func,MalFunc,Atom};
+use types::MalErr::{ErrString,ErrMalVal,ErrAt,ErrIn};
+use types::MalVal::{Nil,Bool,Int,Bignum,Ratio,Float,Str,Char,Bytes,Keyword,Sym,Regex,List,Vector,Hash,Set,LazySeq,Func,MalFunc,Atom};
 use env::{Env,env_bind};
 
 #[derive(Debug, Clone)]
@@ -13,52 +27,450 @@ pub enum MalVal {
     Nil,
     Bool(bool),
     Int(i64),
-    //Float(f64),
+    Bignum(BigInt), // only for values outside i64, see bignum()
+    Ratio(BigRational), // never a whole number, see ratio()
+    Float(f64),
     Str(String),
+    Char(char),
+    Bytes(Rc<Vec<u8>>),
+    Keyword(Rc<String>), // interned, see keyword()
     Sym(String),
-    List(Rc<Vec<MalVal>>, Rc<MalVal>),
-    Vector(Rc<Vec<MalVal>>, Rc<MalVal>),
-    Hash(Rc<FnvHashMap<String, MalVal>>, Rc<MalVal>),
-    Func(fn(MalArgs) -> MalRet, Rc<MalVal>),
+    Regex(Rc<MalRegex>),
+    List(Rc<MalSeq>, Rc<MalVal>),
+    Vector(Rc<MalSeq>, Rc<MalVal>),
+    Hash(Rc<MalMap>, Rc<MalVal>),
+    Set(Rc<MalSet>, Rc<MalVal>),
+    LazySeq(Rc<Lazy>, Rc<MalVal>),
+    Func(Rc<NativeFn>, Rc<MalVal>),
     MalFunc {
       eval: fn(ast: MalVal, env: Env) -> MalRet,
       ast: Rc<MalVal>,
       env: Env,
       params: Rc<MalVal>,
       is_macro: bool,
+      name: Option<Rc<String>>,
       meta: Rc<MalVal>,
     },
     Atom(Rc<RefCell<MalVal>>),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum MalErr {
   ErrString(String),
   ErrMalVal(MalVal),
+  ErrAt(Box<MalErr>, Span),
+  ErrIn(Box<MalErr>, Box<Frame>),
+}
+
+// where a form was read from: origin name (file or "<input>"), 1-based line and column
+#[derive(Debug, Clone, PartialEq)]
+pub struct Span {
+  pub origin: Rc<String>,
+  pub line: usize,
+  pub col: usize,
+}
+
+impl fmt::Display for Span {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}:{}:{}", self.origin, self.line, self.col)
+  }
+}
+
+// a call to a user function that an error escaped from
+#[derive(Debug, Clone)]
+pub struct Frame {
+  pub name: Option<Rc<String>>,
+  pub form: MalVal,
+  pub span: Option<Span>,
+}
+
+impl fmt::Display for Frame {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let name = self.name.as_ref().map_or("<fn>", |n| n.as_str());
+    write!(f, "{} {}", name, self.form.pr_str(true))?;
+    if let Some(ref span) = self.span {
+      write!(f, " at {}", span)?;
+    }
+    Ok(())
+  }
 }
 
 pub type MalArgs = Vec<MalVal>;
 pub type MalRet = Result<MalVal,MalErr>;
 
+// A compiled regex. re-matches needs the pattern anchored at both ends, which
+// is compiled the first time it is asked for and then kept with the regex.
+#[derive(Debug)]
+pub struct MalRegex {
+  re: regex::Regex,
+  whole: RefCell<Option<regex::Regex>>,
+}
+
+impl MalRegex {
+  pub fn whole(&self) -> Result<regex::Regex,MalErr> {
+    if let Some(ref whole) = *self.whole.borrow() {
+      return Ok(whole.clone());
+    }
+    let whole = regex::Regex::new(&format!("^(?:{})$", self.re.as_str()))
+      .map_err(|e| ErrString(format!("invalid regex: {}", e)))?;
+    *self.whole.borrow_mut() = Some(whole.clone());
+    Ok(whole)
+  }
+}
+
+impl Deref for MalRegex {
+  type Target = regex::Regex;
+
+  fn deref(&self) -> &regex::Regex {
+    &self.re
+  }
+}
+
+// how many arguments a native function takes, checked before it is called
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Arity {
+  Exactly(usize),
+  AtLeast(usize),
+  Between(usize, usize),
+}
+
+impl Arity {
+  pub fn accepts(&self, n: usize) -> bool {
+    match *self {
+      Arity::Exactly(k)      => n == k,
+      Arity::AtLeast(k)      => n >= k,
+      Arity::Between(lo, hi) => n >= lo && n <= hi,
+    }
+  }
+}
+
+// A function implemented in Rust. The closure can capture whatever host
+// state it needs: a handle, a config value, a counter in a Cell.
+#[derive(Clone)]
+pub struct NativeFn {
+  pub name: Option<Rc<String>>,
+  pub arity: Arity,
+  f: Rc<dyn Fn(MalArgs) -> MalRet>,
+}
+
+impl NativeFn {
+  pub fn call(&self, args: MalArgs) -> MalRet {
+    if !self.arity.accepts(args.len()) {
+      let name = self.name.as_ref().map_or("<fn>", |n| n.as_str());
+      return error(&format!("wrong number of args ({}) passed to {}", args.len(), name));
+    }
+    (self.f)(args)
+  }
+}
+
+impl fmt::Debug for NativeFn {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.debug_struct("NativeFn")
+      .field("name", &self.name)
+      .field("arity", &self.arity)
+      .finish()
+  }
+}
+
+// Collections are persistent: a clone shares structure with the original, so
+// cloning and then updating one costs O(1) or O(log n) rather than a copy.
+// Lists and vectors still sit behind an Rc, which gives each one read from
+// source an identity (see reader::span_of).
+pub type MalMap = im_rc::HashMap<MalKey, MalVal, FnvBuildHasher>;
+pub type MalSet = im_rc::HashSet<MalKey, FnvBuildHasher>;
+
+// Most lists are short (forms, argument lists) and a trie node is far bigger
+// than they are, so up to SMALL_SEQ values are kept in a shared array that is
+// copied on update; rest only moves the start offset. Longer lists and
+// vectors are RRB vectors.
+const SMALL_SEQ: usize = 32;
+
+#[derive(Debug,Clone)]
+pub enum MalSeq {
+  Small(Rc<Vec<MalVal>>, usize),
+  Large(im_rc::Vector<MalVal>),
+}
+
+impl MalSeq {
+  pub fn new() -> MalSeq {
+    MalSeq::Small(Rc::new(vec![]), 0)
+  }
+
+  pub fn len(&self) -> usize {
+    match *self {
+      MalSeq::Small(ref v, start) => v.len() - start,
+      MalSeq::Large(ref v) => v.len(),
+    }
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  pub fn iter<'a>(&'a self) -> SeqIter<'a> {
+    match *self {
+      MalSeq::Small(ref v, start) => SeqIter::Small(v[start..].iter()),
+      MalSeq::Large(ref v) => SeqIter::Large(v.iter()),
+    }
+  }
+
+  pub fn last(&self) -> Option<&MalVal> {
+    self.iter().next_back()
+  }
+
+  fn to_large(&self) -> im_rc::Vector<MalVal> {
+    match *self {
+      MalSeq::Small(..) => self.iter().cloned().collect(),
+      MalSeq::Large(ref v) => v.clone(),
+    }
+  }
+
+  pub fn rest(&self) -> MalSeq {
+    match *self {
+      MalSeq::Small(ref v, start) => MalSeq::Small(v.clone(), v.len().min(start + 1)),
+      MalSeq::Large(ref v) => MalSeq::Large(v.skip(1)),
+    }
+  }
+
+  pub fn push_front(&mut self, x: MalVal) {
+    if let MalSeq::Large(ref mut v) = *self {
+      return v.push_front(x);
+    }
+    *self = if self.len() < SMALL_SEQ {
+      let mut new_v = Vec::with_capacity(self.len() + 1);
+      new_v.push(x);
+      new_v.extend(self.iter().cloned());
+      MalSeq::Small(Rc::new(new_v), 0)
+    } else {
+      let mut new_v = self.to_large();
+      new_v.push_front(x);
+      MalSeq::Large(new_v)
+    };
+  }
+
+  pub fn push_back(&mut self, x: MalVal) {
+    if let MalSeq::Large(ref mut v) = *self {
+      return v.push_back(x);
+    }
+    *self = if self.len() < SMALL_SEQ {
+      let mut new_v = Vec::with_capacity(self.len() + 1);
+      new_v.extend(self.iter().cloned());
+      new_v.push(x);
+      MalSeq::Small(Rc::new(new_v), 0)
+    } else {
+      let mut new_v = self.to_large();
+      new_v.push_back(x);
+      MalSeq::Large(new_v)
+    };
+  }
+
+  pub fn append(&mut self, other: &MalSeq) {
+    if other.is_empty() { return; }
+    if let MalSeq::Large(ref mut v) = *self {
+      return v.append(other.to_large());
+    }
+    *self = if self.len() + other.len() <= SMALL_SEQ {
+      MalSeq::Small(Rc::new(self.iter().chain(other.iter()).cloned().collect()), 0)
+    } else {
+      let mut new_v = self.to_large();
+      new_v.append(other.to_large());
+      MalSeq::Large(new_v)
+    };
+  }
+}
+
+impl From<Vec<MalVal>> for MalSeq {
+  fn from(v: Vec<MalVal>) -> MalSeq {
+    if v.len() <= SMALL_SEQ {
+      MalSeq::Small(Rc::new(v), 0)
+    } else {
+      MalSeq::Large(im_rc::Vector::from(v))
+    }
+  }
+}
+
+impl FromIterator<MalVal> for MalSeq {
+  fn from_iter<I: IntoIterator<Item=MalVal>>(iter: I) -> MalSeq {
+    MalSeq::from(iter.into_iter().collect::<Vec<MalVal>>())
+  }
+}
+
+impl Extend<MalVal> for MalSeq {
+  fn extend<I: IntoIterator<Item=MalVal>>(&mut self, iter: I) {
+    for x in iter {
+      self.push_back(x);
+    }
+  }
+}
+
+impl Index<usize> for MalSeq {
+  type Output = MalVal;
+
+  fn index(&self, i: usize) -> &MalVal {
+    match *self {
+      MalSeq::Small(ref v, start) => &v[start + i],
+      MalSeq::Large(ref v) => &v[i],
+    }
+  }
+}
+
+impl PartialEq for MalSeq {
+  fn eq(&self, other: &MalSeq) -> bool {
+    self.len() == other.len() && self.iter().zip(other.iter()).all(|(a, b)| a == b)
+  }
+}
+
+impl<'a> IntoIterator for &'a MalSeq {
+  type Item = &'a MalVal;
+  type IntoIter = SeqIter<'a>;
+
+  fn into_iter(self) -> SeqIter<'a> {
+    self.iter()
+  }
+}
+
+pub enum SeqIter<'a> {
+  Small(slice::Iter<'a, MalVal>),
+  Large(im_rc::vector::Iter<'a, MalVal>),
+}
+
+impl<'a> Iterator for SeqIter<'a> {
+  type Item = &'a MalVal;
+
+  fn next(&mut self) -> Option<&'a MalVal> {
+    match *self {
+      SeqIter::Small(ref mut it) => it.next(),
+      SeqIter::Large(ref mut it) => it.next(),
+    }
+  }
+
+  fn size_hint(&self) -> (usize, Option<usize>) {
+    match *self {
+      SeqIter::Small(ref it) => it.size_hint(),
+      SeqIter::Large(ref it) => it.size_hint(),
+    }
+  }
+}
+
+impl<'a> DoubleEndedIterator for SeqIter<'a> {
+  fn next_back(&mut self) -> Option<&'a MalVal> {
+    match *self {
+      SeqIter::Small(ref mut it) => it.next_back(),
+      SeqIter::Large(ref mut it) => it.next_back(),
+    }
+  }
+}
+
+impl<'a> ExactSizeIterator for SeqIter<'a> {}
+
+// A lazy seq starts out as the code that produces it: a function returning
+// nil, a list, a vector or another lazy seq. The first time anything looks at
+// it that runs once, and the first element and the rest are kept.
+pub struct Lazy(RefCell<LazyState>);
+
+pub enum LazyState {
+  Pending(Box<dyn FnOnce() -> MalRet>),
+  Realized(Option<(MalVal, MalVal)>),
+}
+
+pub fn lazy_seq<F: FnOnce() -> MalRet + 'static>(f: F) -> MalVal {
+  LazySeq(Rc::new(Lazy(RefCell::new(LazyState::Pending(Box::new(f))))), Rc::new(Nil))
+}
+
+// a lazy seq that is already realized, to x followed by rest
+pub fn lazy_cons(x: MalVal, rest: MalVal) -> MalVal {
+  LazySeq(Rc::new(Lazy(RefCell::new(LazyState::Realized(Some((x, rest)))))), Rc::new(Nil))
+}
+
+impl Lazy {
+  // what realizing gave, without running anything: None while pending
+  pub fn peek(&self) -> Option<Option<(MalVal, MalVal)>> {
+    match self.0.try_borrow() {
+      Ok(ref s) => match **s {
+        LazyState::Realized(ref r) => Some(r.clone()),
+        LazyState::Pending(_)      => None,
+      },
+      Err(_) => None,
+    }
+  }
+
+  fn realize(&self) -> Result<Option<(MalVal, MalVal)>, MalErr> {
+    if let LazyState::Realized(ref r) = *self.0.borrow() {
+      return Ok(r.clone());
+    }
+    let f = match mem::replace(&mut *self.0.borrow_mut(), LazyState::Realized(None)) {
+      LazyState::Pending(f) => f,
+      LazyState::Realized(_) => unreachable!(),
+    };
+    match f().and_then(|s| s.uncons()) {
+      Ok(r) => {
+        *self.0.borrow_mut() = LazyState::Realized(r.clone());
+        Ok(r)
+      },
+      Err(e) => {
+        // asking again fails the same way
+        let again = e.clone();
+        *self.0.borrow_mut() = LazyState::Pending(Box::new(move || Err(again)));
+        Err(e)
+      },
+    }
+  }
+}
+
+impl fmt::Debug for Lazy {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self.0.try_borrow() {
+      Ok(ref s) => match **s {
+        LazyState::Realized(ref r) => write!(f, "Lazy({:?})", r),
+        LazyState::Pending(_)      => write!(f, "Lazy(pending)"),
+      },
+      Err(_) => write!(f, "Lazy(realizing)"),
+    }
+  }
+}
+
+// Dropping a long realized seq would otherwise recurse once per element.
+impl Drop for Lazy {
+  fn drop(&mut self) {
+    let mut next = match mem::replace(self.0.get_mut(), LazyState::Realized(None)) {
+      LazyState::Realized(Some((_, rest))) => rest,
+      _ => return,
+    };
+    while let LazySeq(lazy, _) = next {
+      next = match Rc::try_unwrap(lazy) {
+        Ok(mut lazy) => match mem::replace(lazy.0.get_mut(), LazyState::Realized(None)) {
+          LazyState::Realized(Some((_, rest))) => rest,
+          _ => return,
+        },
+        Err(_) => return,
+      };
+    }
+  }
+}
+
 // type utility macros
 
+#[macro_export]
 macro_rules! list {
   ($seq:expr) => {{
-    List(Rc::new($seq),Rc::new(Nil))
+    let v: Vec<MalVal> = $seq;
+    List(Rc::new($crate::types::MalSeq::from(v)),Rc::new(Nil))
   }};
   [$($args:expr),*] => {{
     let v: Vec<MalVal> = vec![$($args),*];
-    List(Rc::new(v),Rc::new(Nil))
+    List(Rc::new($crate::types::MalSeq::from(v)),Rc::new(Nil))
   }}
 }
 
+#[macro_export]
 macro_rules! vector {
   ($seq:expr) => {{
-    Vector(Rc::new($seq),Rc::new(Nil))
+    let v: Vec<MalVal> = $seq;
+    Vector(Rc::new($crate::types::MalSeq::from(v)),Rc::new(Nil))
   }};
   [$($args:expr),*] => {{
     let v: Vec<MalVal> = vec![$($args),*];
-    Vector(Rc::new(v),Rc::new(Nil))
+    Vector(Rc::new($crate::types::MalSeq::from(v)),Rc::new(Nil))
   }}
 }
 
@@ -68,10 +480,95 @@ pub fn error(s: &str) -> MalRet {
   Err(ErrString(s.to_string()))
 }
 
+// integers that fit in an i64 are always Ints, so = and the printer never
+// have to compare an Int against an equal Bignum
+pub fn bignum(b: BigInt) -> MalVal {
+  match b.to_i64() {
+    Some(i) => Int(i),
+    None    => Bignum(b),
+  }
+}
+
+// exact division gives a Ratio only when there's a remainder
+pub fn ratio(r: BigRational) -> MalVal {
+  if r.denom().is_one() {
+    bignum(r.to_integer())
+  } else {
+    Ratio(r)
+  }
+}
+
 pub fn format_error(e: MalErr) -> String {
   match e {
-    ErrString(s)  => s.clone(),
-    ErrMalVal(mv) => mv.pr_str(true),
+    ErrString(s)    => s.clone(),
+    ErrMalVal(mv)   => mv.pr_str(true),
+    ErrAt(e, span)  => format!("{} at {}", format_error(*e), span),
+    ErrIn(e, frame) => format!("{}\n  in {}", format_error(*e), frame),
+  }
+}
+
+impl fmt::Display for MalErr {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", format_error(self.clone()))
+  }
+}
+
+impl ::std::error::Error for MalErr {}
+
+impl MalErr {
+  // keep the innermost location: the first form that failed. It sits
+  // directly around the original error, beneath any frames.
+  pub fn at(self, span: Span) -> MalErr {
+    match self {
+      ErrAt(..)       => self,
+      ErrIn(e, frame) => ErrIn(Box::new(e.at(span)), frame),
+      e               => ErrAt(Box::new(e), span),
+    }
+  }
+
+  pub fn called_from(self, frame: Frame) -> MalErr {
+    ErrIn(Box::new(self), Box::new(frame))
+  }
+
+  // the value bound by catch*
+  pub fn exception(&self) -> MalVal {
+    match self {
+      ErrMalVal(mv) => mv.clone(),
+      ErrString(s)  => Str(s.to_string()),
+      ErrAt(e, _)   => e.exception(),
+      ErrIn(e, _)   => e.exception(),
+    }
+  }
+
+  // the frames as a list of {:name :form :location} maps, innermost call first
+  pub fn trace(&self) -> MalVal {
+    let mut frames = vec![];
+    let mut e = self;
+    loop {
+      e = match e {
+        ErrAt(inner, _)     => inner,
+        ErrIn(inner, frame) => {
+          let mut hm = MalMap::default();
+          hm.insert(MalKey(keyword("name")),
+                    frame.name.as_ref().map_or(Nil, |n| Str(n.to_string())));
+          hm.insert(MalKey(keyword("form")), frame.form.clone());
+          hm.insert(MalKey(keyword("location")),
+                    frame.span.as_ref().map_or(Nil, |s| Str(s.to_string())));
+          frames.push(Hash(Rc::new(hm),Rc::new(Nil)));
+          inner
+        },
+        _ => break,
+      };
+    }
+    frames.reverse();
+    list!(frames)
+  }
+}
+
+pub fn re_pattern(pattern: &str) -> MalRet {
+  match regex::Regex::new(pattern) {
+    Ok(re) => Ok(Regex(Rc::new(MalRegex { re: re, whole: RefCell::new(None) }))),
+    Err(e) => Err(ErrString(format!("invalid regex: {}", e))),
   }
 }
 
@@ -79,18 +576,96 @@ pub fn atom(mv: &MalVal) -> MalVal {
   Atom(Rc::new(RefCell::new(mv.clone())))
 }
 
+thread_local! {
+  static KEYWORDS: RefCell<FnvHashMap<String, Rc<String>>> =
+    RefCell::new(FnvHashMap::default());
+}
+
+// every :name shares one Rc, so comparing keywords is usually a pointer check
+pub fn keyword(name: &str) -> MalVal {
+  KEYWORDS.with(|kws| {
+    let mut kws = kws.borrow_mut();
+    if let Some(kw) = kws.get(name) {
+      return Keyword(kw.clone());
+    }
+    let kw = Rc::new(name.to_string());
+    kws.insert(name.to_string(), kw.clone());
+    Keyword(kw)
+  })
+}
+
+// (:k m) and (m :k), with an optional default for a missing key
+fn lookup(m: &MalVal, k: &MalVal, default: Option<&MalVal>) -> MalRet {
+  let found = match *m {
+    Hash(ref hm,_) => MalKey::new(k).ok().and_then(|k| hm.get(&k).cloned()),
+    _              => None,
+  };
+  Ok(found.or_else(|| default.cloned()).unwrap_or(Nil))
+}
+
 impl MalVal {
   pub fn keyword(&self) -> MalRet {
     match self {
-      Str(s) if s.starts_with("\u{29e}") => Ok(Str(s.to_string())),
-      Str(s)                             => Ok(Str(format!("\u{29e}{}", s))),
+      Keyword(_) => Ok(self.clone()),
+      Str(s)     => Ok(keyword(s)),
       _ => error("invalid type for keyword"),
     }
   }
 
+  // The first element and the rest of a list, vector, set, lazy seq, map (as
+  // [key value] vectors) or string (as one-character strings), or None if it
+  // is empty. This is what realizes a lazy seq.
+  pub fn uncons(&self) -> Result<Option<(MalVal, MalVal)>, MalErr> {
+    match self {
+      List(l,_) | Vector(l,_) if l.is_empty() => Ok(None),
+      List(l,_) | Vector(l,_) => Ok(Some((l[0].clone(), List(Rc::new(l.rest()),Rc::new(Nil))))),
+      Set(..) | Hash(..) | Str(_) | Bytes(_) => list!(self.seq_vals()?).uncons(),
+      LazySeq(lazy,_) => lazy.realize(),
+      Nil => Ok(None),
+      _ => Err(ErrString(format!("{} is not a sequence", self.pr_str(true)))),
+    }
+  }
+
+  // runs every lazy seq inside, so that an error from one comes out here;
+  // the printer itself never realizes anything
+  pub fn realize(&self) -> Result<(), MalErr> {
+    match self {
+      List(l,_) | Vector(l,_) => l.iter().try_for_each(|x| x.realize()),
+      Hash(hm,_) => hm.iter().try_for_each(|(k, v)| { k.val().realize()?; v.realize() }),
+      Set(s,_) => s.iter().try_for_each(|k| k.val().realize()),
+      LazySeq(..) => self.seq_vals()?.iter().try_for_each(|x| x.realize()),
+      Atom(a) => a.borrow().realize(),
+      _ => Ok(()),
+    }
+  }
+
+  // every element, realizing a lazy seq to its end
+  pub fn seq_vals(&self) -> Result<MalArgs, MalErr> {
+    match self {
+      List(l,_) | Vector(l,_) => Ok(l.iter().cloned().collect()),
+      Set(s,_) => Ok(s.iter().map(|k| k.val().clone()).collect()),
+      Hash(hm,_) => Ok(hm.iter().map(|(k, v)| vector![k.val().clone(), v.clone()]).collect()),
+      Str(s) => Ok(s.chars().map(|c| Str(c.to_string())).collect()),
+      Bytes(b) => Ok(b.iter().map(|&b| Int(b as i64)).collect()),
+      _ => {
+        let mut vals = vec![];
+        let mut s = self.clone();
+        while let Some((x, rest)) = s.uncons()? {
+          vals.push(x);
+          s = rest;
+        }
+        Ok(vals)
+      },
+    }
+  }
+
   pub fn empty_q(&self) -> MalRet {
     match self {
       List(l,_) | Vector(l,_) => Ok(Bool(l.len() == 0)),
+      Set(s,_)                => Ok(Bool(s.is_empty())),
+      Str(s)                  => Ok(Bool(s.is_empty())),
+      Bytes(b)                => Ok(Bool(b.is_empty())),
+      LazySeq(..)             => Ok(Bool(self.uncons()?.is_none())),
       Nil                     => Ok(Bool(true)),
       _ => error("invalid type for empty?"),
     }
@@ -99,6 +674,18 @@ impl MalVal {
   pub fn count(&self) -> MalRet {
     match self {
       List(l,_) | Vector(l,_) => Ok(Int(l.len() as i64)),
+      Set(s,_)                => Ok(Int(s.len() as i64)),
+      Str(s)                  => Ok(Int(s.chars().count() as i64)),
+      Bytes(b)                => Ok(Int(b.len() as i64)),
+      LazySeq(..)             => {
+        let mut n = 0;
+        let mut s = self.clone();
+        while let Some((_, rest)) = s.uncons()? {
+          n += 1;
+          s = rest;
+        }
+        Ok(Int(n))
+      },
       Nil                     => Ok(Int(0)),
       _ => error("invalid type for count"),
     }
@@ -106,21 +693,68 @@ impl MalVal {
 
   pub fn apply(&self, args: MalArgs) -> MalRet {
     match *self {
-      Func(f,_) => f(args),
-      MalFunc{eval, ref ast, ref env, ref params, ..} => {
+      Func(ref f,_) => f.call(args),
+      MalFunc{eval, ref ast, ref env, ref params, ref name, ..} => {
         let a = &**ast;
         let p = &**params;
-        let fn_env = env_bind(Some(env.clone()), p.clone(), args)?;
-        Ok(eval(a.clone(), fn_env)?)
+        let fn_env = env_bind(Some(env.clone()), p.clone(), &args)?;
+        eval(a.clone(), fn_env).map_err(|e| {
+          // only built for a call that failed
+          let mut form = vec![name.as_ref().map_or(self.clone(), |n| Sym(n.to_string()))];
+          form.extend(args);
+          e.called_from(Frame { name: name.clone(), form: list!(form), span: None })
+        })
       }
+      Keyword(_) if args.len() == 1 || args.len() == 2 => lookup(&args[0], self, args.get(1)),
+      Hash(_,_) if args.len() == 1 || args.len() == 2  => lookup(self, &args[0], args.get(1)),
+      Keyword(_) | Hash(_,_) => error("lookup takes a key and an optional default"),
+      // (s x) is x if s contains it, nil otherwise
+      Set(ref s,_) if args.len() == 1 => {
+        let found = MalKey::new(&args[0]).map_or(false, |k| s.contains(&k));
+        Ok(if found { args[0].clone() } else { Nil })
+      },
+      Set(_,_) => error("set lookup takes one argument"),
       _ => error("attempt to call non-function"),
     }
   }
 
-  pub fn keyword_q(&self) -> bool {
-    match self {
-      Str(s) if s.starts_with("\u{29e}") => true,
-      _ => false,
+  // give an anonymous function the name of the symbol it is def!'d to
+  pub fn named(self, sym: &MalVal) -> MalVal {
+    match (self, sym) {
+      (MalFunc{eval, ast, env, params, is_macro, name: None, meta}, &Sym(ref s)) => {
+        MalFunc{eval: eval, ast: ast, env: env, params: params,
+                is_macro: is_macro, name: Some(Rc::new(s.clone())), meta: meta}
+      },
+      (Func(ref f, ref meta), &Sym(ref s)) if f.name.is_none() => {
+        Func(Rc::new(NativeFn { name: Some(Rc::new(s.clone())), ..(**f).clone() }), meta.clone())
+      },
+      (v, _) => v,
+    }
+  }
+
+  // ints are promoted to floats in mixed arithmetic
+  pub fn to_f64(&self) -> Result<f64,MalErr> {
+    match *self {
+      Int(i)        => Ok(i as f64),
+      Bignum(ref b) => Ok(b.to_f64().unwrap_or(::std::f64::NAN)),
+      Ratio(ref r)  => Ok(r.to_f64().unwrap_or(::std::f64::NAN)),
+      Float(f)      => Ok(f),
+      _             => Err(ErrString("expecting number arg".to_string())),
+    }
+  }
+
+  pub fn to_bigint(&self) -> Result<BigInt,MalErr> {
+    match *self {
+      Int(i)        => Ok(BigInt::from(i)),
+      Bignum(ref b) => Ok(b.clone()),
+      _             => Err(ErrString("expecting integer arg".to_string())),
+    }
+  }
+
+  pub fn to_ratio(&self) -> Result<BigRational,MalErr> {
+    match *self {
+      Ratio(ref r) => Ok(r.clone()),
+      _            => Ok(BigRational::from_integer(self.to_bigint()?)),
     }
   }
 
@@ -156,7 +790,7 @@ impl MalVal {
 
   pub fn get_meta(&self) -> MalRet {
     match self {
-      List(_,meta) | Vector(_,meta) | Hash(_,meta) => Ok((&**meta).clone()),
+      List(_,meta) | Vector(_,meta) | Hash(_,meta) | Set(_,meta) | LazySeq(_,meta) => Ok((&**meta).clone()),
       Func(_,meta) => Ok((&**meta).clone()),
       MalFunc{meta,..} => Ok((&**meta).clone()),
       _ => error("meta not supported by type"),
@@ -168,6 +802,8 @@ impl MalVal {
       List(_, ref mut meta) |
       Vector(_, ref mut meta) |
       Hash(_, ref mut meta) |
+      Set(_, ref mut meta) |
+      LazySeq(_, ref mut meta) |
       Func(_,ref mut meta) |
       MalFunc{ref mut meta, ..} => {
         *meta = Rc::new((&*new_meta).clone());
@@ -184,49 +820,551 @@ impl PartialEq for MalVal {
       (Nil,Nil) => true,
       (Bool(ref a),Bool(ref b)) => a == b,
       (Int(ref a),Int(ref b)) => a == b,
+      (Float(ref a),Float(ref b)) => a == b,
+      (Int(ref a),Float(ref b)) => *a as f64 == *b,
+      (Float(ref a),Int(ref b)) => *a == *b as f64,
+      (Bignum(ref a),Bignum(ref b)) => a == b,
+      (Bignum(_),Float(ref b)) => self.to_f64().ok() == Some(*b),
+      (Float(ref a),Bignum(_)) => other.to_f64().ok() == Some(*a),
+      (Ratio(ref a),Ratio(ref b)) => a == b,
+      (Ratio(_),Float(ref b)) => self.to_f64().ok() == Some(*b),
+      (Float(ref a),Ratio(_)) => other.to_f64().ok() == Some(*a),
       (Str(ref a),Str(ref b)) => a == b,
+      (Char(a),Char(b)) => a == b,
+      (Bytes(ref a),Bytes(ref b)) => a == b,
+      (Keyword(ref a),Keyword(ref b)) => Rc::ptr_eq(a, b) || a == b,
       (Sym(ref a),Sym(ref b)) => a == b,
+      (Regex(ref a),Regex(ref b)) => a.as_str() == b.as_str(),
       (List(ref a,_),List(ref b,_)) |
       (Vector(ref a,_),Vector(ref b,_)) |
       (List(ref a,_),Vector(ref b,_)) |
       (Vector(ref a,_),List(ref b,_)) => a == b,
       (Hash(ref a,_),Hash(ref b,_)) => a == b,
+      (Set(ref a,_),Set(ref b,_)) => a == b,
+      (LazySeq(..),List(..)) | (LazySeq(..),Vector(..)) | (LazySeq(..),LazySeq(..)) |
+      (List(..),LazySeq(..)) | (Vector(..),LazySeq(..)) => seq_eq(self, other),
       (MalFunc{..}, MalFunc{..}) => false,
       _ => false,
     }
   }
 }
 
-pub fn func(f: fn(MalArgs) -> MalRet) -> MalVal {
-  Func(f, Rc::new(Nil))
+// compared element by element, so an infinite seq is only ever realized as
+// far as the first difference; a seq that fails to realize is unequal
+fn seq_eq(a: &MalVal, b: &MalVal) -> bool {
+  let (mut a, mut b) = (a.clone(), b.clone());
+  loop {
+    match (a.uncons(), b.uncons()) {
+      (Ok(None), Ok(None)) => return true,
+      (Ok(Some((x, xs))), Ok(Some((y, ys)))) if x == y => { a = xs; b = ys; },
+      _ => return false,
+    }
+  }
 }
 
-pub fn _assoc(mut hm: FnvHashMap<String,MalVal>, kvs: MalArgs) -> MalRet {
+// A hash map key: any value whose equality is well-defined. Floats,
+// functions, atoms and maps are not allowed. A list and a vector with the
+// same elements are equal, so they have to hash the same. The original value
+// is kept so that keys print back as they were written.
+#[derive(Debug, Clone)]
+pub struct MalKey(MalVal);
+
+impl MalKey {
+  pub fn new(k: &MalVal) -> Result<MalKey,MalErr> {
+    if hashable(k) {
+      Ok(MalKey(k.clone()))
+    } else {
+      Err(ErrString(format!("{} cannot be a hash map key", k.pr_str(true))))
+    }
+  }
+
+  pub fn val(&self) -> &MalVal {
+    &self.0
+  }
+}
+
+fn hashable(v: &MalVal) -> bool {
+  match *v {
+    Nil | Bool(_) | Int(_) | Bignum(_) | Ratio(_) | Str(_) | Char(_) | Bytes(_) |
+    Keyword(_) | Sym(_) => true,
+    List(ref l,_) | Vector(ref l,_) => l.iter().all(hashable),
+    _ => false,
+  }
+}
+
+fn hash_val<H: Hasher>(v: &MalVal, state: &mut H) {
+  match *v {
+    Nil               => state.write_u8(0),
+    Bool(b)           => { state.write_u8(1); hash::Hash::hash(&b, state) },
+    Int(i)            => { state.write_u8(2); hash::Hash::hash(&i, state) },
+    Bignum(ref b)     => { state.write_u8(3); hash::Hash::hash(b, state) },
+    Ratio(ref r)      => { state.write_u8(4); hash::Hash::hash(r, state) },
+    Str(ref s)        => { state.write_u8(5); hash::Hash::hash(s, state) },
+    Keyword(ref s)    => { state.write_u8(6); hash::Hash::hash(s, state) },
+    Sym(ref s)        => { state.write_u8(7); hash::Hash::hash(s, state) },
+    Char(c)           => { state.write_u8(9); hash::Hash::hash(&c, state) },
+    Bytes(ref b)      => { state.write_u8(10); hash::Hash::hash(b, state) },
+    List(ref l,_) |
+    Vector(ref l,_)   => {
+      state.write_u8(8);
+      state.write_usize(l.len());
+      for x in l.iter() { hash_val(x, state) }
+    },
+    _ => unreachable!("MalKey::new only accepts hashable values"),
+  }
+}
+
+impl PartialEq for MalKey {
+  fn eq(&self, other: &MalKey) -> bool {
+    self.0 == other.0
+  }
+}
+
+impl Eq for MalKey {}
+
+impl hash::Hash for MalKey {
+  fn hash<H: Hasher>(&self, state: &mut H) {
+    hash_val(&self.0, state)
+  }
+}
+
+// an anonymous native function taking any number of arguments
+pub fn func<F>(f: F) -> MalVal where F: Fn(MalArgs) -> MalRet + 'static {
+  native_fn(None, Arity::AtLeast(0), f)
+}
+
+pub fn native_fn<F>(name: Option<&str>, arity: Arity, f: F) -> MalVal
+  where F: Fn(MalArgs) -> MalRet + 'static {
+  Func(Rc::new(NativeFn {
+    name: name.map(|n| Rc::new(n.to_string())),
+    arity: arity,
+    f: Rc::new(f),
+  }), Rc::new(Nil))
+}
+
+pub fn _assoc(mut hm: MalMap, kvs: MalArgs) -> MalRet {
   if kvs.len() % 2 != 0 {
     return error("odd number of elements")
   }
   for (k, v) in kvs.iter().tuples() {
-    match k {
-      Str(s) => { hm.insert(s.to_string(), v.clone()); },
-      _      => { return error("key is not string") },
-    }
+    hm.insert(MalKey::new(k)?, v.clone());
   }
   Ok(Hash(Rc::new(hm),Rc::new(Nil)))
 }
 
-pub fn _dissoc(mut hm: FnvHashMap<String,MalVal>, ks: MalArgs) -> MalRet {
+pub fn _dissoc(mut hm: MalMap, ks: MalArgs) -> MalRet {
   for k in ks.iter() {
-    match k {
-      Str(ref s) => { hm.remove(s); },
-      _      => { return error("key is not string") },
+    // a value that can't be a key can't be in the map either
+    if let Ok(key) = MalKey::new(k) {
+      hm.remove(&key);
     }
   }
   Ok(Hash(Rc::new(hm),Rc::new(Nil)))
 }
 
 pub fn hash_map(kvs: MalArgs) -> MalRet {
-  let hm: FnvHashMap<String,MalVal> = FnvHashMap::default();
-  _assoc(hm, kvs)
+  _assoc(MalMap::default(), kvs)
+}
+
+pub fn _conj_set(mut hs: MalSet, xs: &[MalVal]) -> MalRet {
+  for x in xs.iter() {
+    hs.insert(MalKey::new(x)?);
+  }
+  Ok(Set(Rc::new(hs),Rc::new(Nil)))
+}
+
+pub fn hash_set(xs: MalArgs) -> MalRet {
+  _conj_set(MalSet::default(), &xs)
+}
+
+// serde: Mal data goes to and from any serde format. MalVal's own impls
+// write what a program on the other end expects: keywords and symbols as
+// their names, ratios as floats, lists, sets and lazy seqs as sequences and
+// maps as maps. Reading back gives strings, floats, vectors and maps.
+//
+// Tagged(v) round-trips instead. Values a format has no type for are written
+// as a map with a single "mal/..." key, and read back from one:
+//
+//   :k           {"mal/keyword": "k"}
+//   1/3          {"mal/ratio": [1, 3]}
+//   {:a 1}       {"mal/map": [[{"mal/keyword": "a"}, 1]]}
+//
+// The last is for maps with keys other than strings, and for a map that would
+// otherwise look like one of these. Nothing is guessed from the content of a
+// string. Functions, atoms and regexes have no serialized form either way.
+
+pub struct Tagged<T>(pub T);
+
+const TAG_KEYWORD: &'static str = "mal/keyword";
+const TAG_RATIO: &'static str = "mal/ratio";
+const TAG_MAP: &'static str = "mal/map";
+
+fn serialize_tagged<S: Serializer, T: Serialize>(s: S, tag: &str, v: &T) -> Result<S::Ok, S::Error> {
+  let mut m = s.serialize_map(Some(1))?;
+  m.serialize_entry(tag, v)?;
+  m.end()
+}
+
+// a plain map has string keys, and isn't mistaken for a tagged value
+fn is_plain_map(hm: &MalMap) -> bool {
+  hm.keys().all(|k| match *k.val() { Str(_) => true, _ => false }) &&
+    !(hm.len() == 1 && hm.keys().any(|k| match *k.val() {
+      Str(ref s) => s == TAG_KEYWORD || s == TAG_RATIO || s == TAG_MAP,
+      _ => false,
+    }))
+}
+
+// a value and whether to write it tagged, for both impls and their elements
+struct Form<'a>(&'a MalVal, bool);
+
+impl<'a> Serialize for Form<'a> {
+  fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+    let Form(v, tagged) = *self;
+    match *v {
+      Nil => s.serialize_unit(),
+      Bool(b) => s.serialize_bool(b),
+      Int(i) => s.serialize_i64(i),
+      Bignum(ref b) => match b.to_i128() {
+        Some(i) => s.serialize_i128(i),
+        None    => Err(S::Error::custom(format!("{} is too big to serialize", b))),
+      },
+      Ratio(ref r) if tagged => {
+        serialize_tagged(s, TAG_RATIO, &(bignum(r.numer().clone()), bignum(r.denom().clone())))
+      },
+      Ratio(_) => s.serialize_f64(v.to_f64().map_err(S::Error::custom)?),
+      Float(f) => s.serialize_f64(f),
+      Str(ref st) | Sym(ref st) => s.serialize_str(st),
+      Char(c) => s.serialize_char(c),
+      Bytes(ref b) => s.serialize_bytes(b),
+      Keyword(ref k) if tagged => serialize_tagged(s, TAG_KEYWORD, &**k),
+      Keyword(ref k) => s.serialize_str(k),
+      List(ref l,_) | Vector(ref l,_) => s.collect_seq(l.iter().map(|x| Form(x, tagged))),
+      Set(ref hs,_) => s.collect_seq(hs.iter().map(|k| Form(k.val(), tagged))),
+      LazySeq(..) => {
+        s.collect_seq(v.seq_vals().map_err(S::Error::custom)?.iter().map(|x| Form(x, tagged)))
+      },
+      Hash(ref hm,_) if !tagged || is_plain_map(hm) => {
+        s.collect_map(hm.iter().map(|(k, v)| (Form(k.val(), tagged), Form(v, tagged))))
+      },
+      Hash(ref hm,_) => {
+        let kvs = hm.iter().map(|(k, v)| (Form(k.val(), tagged), Form(v, tagged))).collect::<Vec<_>>();
+        serialize_tagged(s, TAG_MAP, &kvs)
+      },
+      Regex(_) | Func(..) | MalFunc{..} | Atom(_) => {
+        Err(S::Error::custom(format!("cannot serialize {}", v.pr_str(true))))
+      },
+    }
+  }
+}
+
+impl Serialize for MalVal {
+  fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+    Form(self, false).serialize(s)
+  }
+}
+
+impl<'a> Serialize for Tagged<&'a MalVal> {
+  fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+    Form(self.0, true).serialize(s)
+  }
+}
+
+impl<'de> Deserialize<'de> for MalVal {
+  fn deserialize<D: Deserializer<'de>>(d: D) -> Result<MalVal, D::Error> {
+    d.deserialize_any(MalValVisitor { tagged: false })
+  }
+}
+
+impl<'de> Deserialize<'de> for Tagged<MalVal> {
+  fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Tagged<MalVal>, D::Error> {
+    d.deserialize_any(MalValVisitor { tagged: true }).map(Tagged)
+  }
+}
+
+#[derive(Clone,Copy)]
+struct MalValVisitor {
+  tagged: bool,
+}
+
+impl<'de> DeserializeSeed<'de> for MalValVisitor {
+  type Value = MalVal;
+
+  fn deserialize<D: Deserializer<'de>>(self, d: D) -> Result<MalVal, D::Error> {
+    d.deserialize_any(self)
+  }
+}
+
+impl<'de> Visitor<'de> for MalValVisitor {
+  type Value = MalVal;
+
+  fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str("a Mal value")
+  }
+
+  fn visit_unit<E: de::Error>(self) -> Result<MalVal, E> { Ok(Nil) }
+  fn visit_none<E: de::Error>(self) -> Result<MalVal, E> { Ok(Nil) }
+  fn visit_some<D: Deserializer<'de>>(self, d: D) -> Result<MalVal, D::Error> {
+    d.deserialize_any(self)
+  }
+  fn visit_bool<E: de::Error>(self, b: bool) -> Result<MalVal, E> { Ok(Bool(b)) }
+  fn visit_i64<E: de::Error>(self, i: i64) -> Result<MalVal, E> { Ok(Int(i)) }
+  fn visit_u64<E: de::Error>(self, u: u64) -> Result<MalVal, E> { Ok(bignum(BigInt::from(u))) }
+  fn visit_i128<E: de::Error>(self, i: i128) -> Result<MalVal, E> { Ok(bignum(BigInt::from(i))) }
+  fn visit_u128<E: de::Error>(self, u: u128) -> Result<MalVal, E> { Ok(bignum(BigInt::from(u))) }
+  fn visit_f64<E: de::Error>(self, f: f64) -> Result<MalVal, E> { Ok(Float(f)) }
+  fn visit_char<E: de::Error>(self, c: char) -> Result<MalVal, E> { Ok(Char(c)) }
+  fn visit_bytes<E: de::Error>(self, b: &[u8]) -> Result<MalVal, E> { Ok(Bytes(Rc::new(b.to_vec()))) }
+  fn visit_byte_buf<E: de::Error>(self, b: Vec<u8>) -> Result<MalVal, E> { Ok(Bytes(Rc::new(b))) }
+
+  fn visit_str<E: de::Error>(self, s: &str) -> Result<MalVal, E> { Ok(Str(s.to_string())) }
+
+  fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<MalVal, A::Error> {
+    let mut v = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+    while let Some(x) = seq.next_element_seed(self)? {
+      v.push(x);
+    }
+    Ok(vector!(v))
+  }
+
+  fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<MalVal, A::Error> {
+    let mut entries = vec![];
+    while let Some(kv) = map.next_entry_seed(self, self)? {
+      entries.push(kv);
+    }
+    if let [(Str(ref k), Str(ref n))] = entries[..] {
+      if k == JSON_NUMBER {
+        return json_number(n).map_err(de::Error::custom);
+      }
+    }
+    if self.tagged && entries.len() == 1 {
+      if let Some(v) = untag(&entries[0].0, &entries[0].1).map_err(de::Error::custom)? {
+        return Ok(v);
+      }
+    }
+    hash_from_entries(entries).map_err(de::Error::custom)
+  }
+}
+
+// serde_json is built with arbitrary_precision, so a number it can't give as
+// an i64, u64 or exact f64 comes as a map from this key to the number as
+// written. Integers that don't fit an i64 keep all their digits that way.
+const JSON_NUMBER: &'static str = "$serde_json::private::Number";
+
+fn json_number(n: &str) -> Result<MalVal,String> {
+  match n.parse::<BigInt>() {
+    Ok(b)  => Ok(bignum(b)),
+    Err(_) => n.parse().map(Float).map_err(|_| format!("invalid number {}", n)),
+  }
+}
+
+fn hash_from_entries(entries: Vec<(MalVal, MalVal)>) -> MalRet {
+  let mut hm = MalMap::default();
+  for (k, v) in entries {
+    hm.insert(MalKey::new(&k)?, v);
+  }
+  Ok(Hash(Rc::new(hm),Rc::new(Nil)))
+}
+
+// the value a {"mal/...": v} map stands for, or None if it's just a map
+fn untag(tag: &MalVal, v: &MalVal) -> Result<Option<MalVal>,MalErr> {
+  let tag = match *tag {
+    Str(ref tag) => tag,
+    _ => return Ok(None),
+  };
+  let integer = |x: &MalVal| match *x {
+    Int(i)        => Some(BigInt::from(i)),
+    Bignum(ref b) => Some(b.clone()),
+    _ => None,
+  };
+  match (&tag[..], v) {
+    (TAG_KEYWORD, &Str(ref k)) => Ok(Some(keyword(k))),
+    (TAG_RATIO, &Vector(ref nd,_)) if nd.len() == 2 => {
+      match (integer(&nd[0]), integer(&nd[1])) {
+        (Some(n), Some(d)) if !d.is_zero() => Ok(Some(ratio(BigRational::new(n, d)))),
+        _ => Ok(None),
+      }
+    },
+    (TAG_MAP, &Vector(ref kvs,_)) => {
+      let mut entries = vec![];
+      for kv in kvs.iter() {
+        match *kv {
+          Vector(ref kv,_) if kv.len() == 2 => entries.push((kv[0].clone(), kv[1].clone())),
+          _ => return Ok(None),
+        }
+      }
+      hash_from_entries(entries).map(Some)
+    },
+    _ => Ok(None),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json;
+  use reader::read_str;
+  use std::collections::hash_map::DefaultHasher;
+
+  fn key(src: &str) -> MalKey {
+    MalKey::new(&read_str(src.to_string()).unwrap()).unwrap()
+  }
+
+  fn hash<T: hash::Hash>(t: &T) -> u64 {
+    let mut s = DefaultHasher::new();
+    t.hash(&mut s);
+    s.finish()
+  }
+
+  #[test]
+  fn any_hashable_value_is_a_map_key() {
+    for src in &["1", "\"a\"", "a", ":a", "nil", "true", "[1 [2]]", "1/2", "\\c"] {
+      assert!(key(src) == key(src), "{}", src);
+      assert_eq!(hash(&key(src)), hash(&key(src)), "{}", src);
+    }
+    assert!(MalKey::new(&atom(&Nil)).is_err());
+    assert!(MalKey::new(&Float(1.5)).is_err());
+    assert!(MalKey::new(&read_str("[1 {}]".to_string()).unwrap()).is_err());
+  }
+
+  #[test]
+  fn equal_keys_hash_alike() {
+    // a list and a vector with the same elements are =, so one key
+    assert!(key("[1 2]") == key("(1 2)"));
+    assert_eq!(hash(&key("[1 2]")), hash(&key("(1 2)")));
+    assert!(key(":a") != key("\"a\""));
+    assert!(key("a") != key("\"a\""));
+    assert!(key("1") != key("\"1\""));
+  }
+
+  fn to_json(src: &str) -> String {
+    serde_json::to_string(&read_str(src.to_string()).unwrap()).unwrap()
+  }
+
+  fn from_json(json: &str) -> String {
+    serde_json::from_str::<MalVal>(json).unwrap().pr_str(true)
+  }
+
+  #[test]
+  fn native_fns_check_their_arity_before_running() {
+    let calls = Rc::new(RefCell::new(0));
+    let c = calls.clone();
+    let f = native_fn(Some("f"), Arity::Between(1, 2), move |a| { *c.borrow_mut() += 1; Ok(a[0].clone()) });
+    assert!(f.apply(vec![Int(1)]).is_ok());
+    assert!(f.apply(vec![Int(1), Int(2)]).is_ok());
+    for args in vec![vec![], vec![Int(1), Int(2), Int(3)]] {
+      match f.apply(args) {
+        Err(e) => assert!(e.to_string().starts_with("wrong number of args")),
+        Ok(_)  => panic!("expected an arity error"),
+      }
+    }
+    assert_eq!(*calls.borrow(), 2);
+    assert!(Arity::Exactly(0).accepts(0) && !Arity::Exactly(0).accepts(1));
+    assert!(Arity::AtLeast(1).accepts(9) && !Arity::AtLeast(1).accepts(0));
+  }
+
+  fn ints(seq: &MalSeq) -> Vec<i64> {
+    seq.iter().map(|x| match *x { Int(i) => i, _ => panic!("expected an int") }).collect()
+  }
+
+  #[test]
+  fn seqs_are_values_across_the_small_to_large_switch() {
+    let mut small = MalSeq::from((0..SMALL_SEQ as i64).map(Int).collect::<Vec<_>>());
+    let before = small.clone();
+    small.push_back(Int(-1));
+    small.push_front(Int(-2));
+    assert_eq!(small.len(), SMALL_SEQ + 2);
+    assert_eq!(before.len(), SMALL_SEQ);
+    assert_eq!(ints(&small)[..3], [-2, 0, 1]);
+    assert_eq!(small.last(), Some(&Int(-1)));
+
+    let rest = small.rest();
+    assert_eq!(rest.len(), small.len() - 1);
+    assert_eq!(rest[0], Int(0));
+    assert!(small[0] == Int(-2));
+
+    let mut both = before.rest();
+    both.append(&before);
+    assert_eq!(both.len(), 2 * SMALL_SEQ - 1);
+    assert_eq!(ints(&before), (0..SMALL_SEQ as i64).collect::<Vec<_>>());
+  }
+
+  #[test]
+  fn rest_of_a_small_seq_shares_it() {
+    let seq = MalSeq::from(vec![Int(1), Int(2)]);
+    let rest = seq.rest();
+    assert_eq!(ints(&rest), vec![2]);
+    assert_eq!(ints(&rest.rest()), Vec::<i64>::new());
+    assert!(rest.rest().rest().is_empty());
+    match (&seq, &rest) {
+      (&MalSeq::Small(ref a, _), &MalSeq::Small(ref b, _)) => assert!(Rc::ptr_eq(a, b)),
+      _ => panic!("expected small seqs"),
+    }
+  }
+
+  #[test]
+  fn serde_writes_plain_data() {
+    assert_eq!(to_json(":k"), r#""k""#);
+    assert_eq!(to_json("[sym \":k\"]"), r#"["sym",":k"]"#);
+    assert_eq!(to_json("-1/4"), "-0.25");
+    assert_eq!(to_json("{:a [1 nil true]}"), r#"{"a":[1,null,true]}"#);
+    assert_eq!(to_json("#{:b}"), r#"["b"]"#);
+    assert_eq!(from_json(r#"{"mal/keyword":"k"}"#), "{\"mal/keyword\" \"k\"}");
+  }
+
+  fn to_tagged_json(src: &str) -> String {
+    serde_json::to_string(&Tagged(&read_str(src.to_string()).unwrap())).unwrap()
+  }
+
+  fn from_tagged_json(json: &str) -> String {
+    serde_json::from_str::<Tagged<MalVal>>(json).unwrap().0.pr_str(true)
+  }
+
+  #[test]
+  fn tagged_serde_tags_keywords_ratios_and_other_maps() {
+    assert_eq!(to_tagged_json(":k"), r#"{"mal/keyword":"k"}"#);
+    assert_eq!(to_tagged_json("\":k\""), r#"":k""#);
+    assert_eq!(to_tagged_json("-2/6"), r#"{"mal/ratio":[-1,3]}"#);
+    assert_eq!(to_tagged_json("{\"a\" [1 nil true]}"), r#"{"a":[1,null,true]}"#);
+    assert_eq!(to_tagged_json("{:a 1}"), r#"{"mal/map":[[{"mal/keyword":"a"},1]]}"#);
+    assert_eq!(to_tagged_json("{\"mal/keyword\" \"k\"}"), r#"{"mal/map":[["mal/keyword","k"]]}"#);
+  }
+
+  #[test]
+  fn tagged_serde_round_trips() {
+    for src in &[":k", "\":k\"", "1/3", "-7/2", "2.5", "18446744073709551615",
+                 "{:a {\"b\" [1 :c]}}", "{\"mal/keyword\" \"k\"}", "{\"mal/ratio\" [1 2]}",
+                 "{1 2 [3] 4}", "[nil true \"s\"]"] {
+      let v = read_str(src.to_string()).unwrap();
+      let back = from_tagged_json(&serde_json::to_string(&Tagged(&v)).unwrap());
+      assert_eq!(back, v.pr_str(true), "{}", src);
+    }
+  }
+
+  #[test]
+  fn tagged_serde_reads_plain_json_as_it_is() {
+    assert_eq!(from_tagged_json(r#"":k""#), "\":k\"");
+    assert_eq!(from_tagged_json(r#"{"keyword":"k"}"#), "{\"keyword\" \"k\"}");
+    assert_eq!(from_tagged_json(r#"{"mal/keyword":1}"#), "{\"mal/keyword\" 1}");
+    assert_eq!(from_tagged_json(r#"{"mal/ratio":[1,0]}"#), "{\"mal/ratio\" [1 0]}");
+    let two: Tagged<MalVal> = serde_json::from_str(r#"{"mal/keyword":"k","x":1}"#).unwrap();
+    assert!(two.0 == read_str("{\"mal/keyword\" \"k\" \"x\" 1}".to_string()).unwrap());
+  }
+
+  #[test]
+  fn serde_json_reads_numbers_exactly() {
+    assert_eq!(from_json("12345678901234567890123"), "12345678901234567890123");
+    assert_eq!(from_json("-12345678901234567890123"), "-12345678901234567890123");
+    assert_eq!(from_json("[1.5, 1e3, -2.5E-1]"), "[1.5 1000.0 -0.25]");
+    assert_eq!(from_json(r#"{"a": 0.1}"#), "{\"a\" 0.1}");
+  }
+
+  #[test]
+  fn serde_refuses_what_has_no_serialized_form() {
+    let f = native_fn(None, Arity::Exactly(0), |_| Ok(Nil));
+    assert!(serde_json::to_string(&f).is_err());
+    assert!(serde_json::to_string(&atom(&Nil)).is_err());
+    assert!(serde_json::to_string(&Tagged(&f)).is_err());
+  }
 }
 
 // vim: ts=2:sw=2:expandtab